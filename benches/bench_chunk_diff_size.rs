@@ -0,0 +1,120 @@
+//! Compares the size of the structured `RegionChunkDiff` with a naive `BlobDiff` of the whole
+//! chunk NBT, over consecutive versions of the sample regions.
+//!
+//! Not a timing benchmark: run it with `cargo bench --bench bench_chunk_diff_size` and read the
+//! summary it prints.
+
+use std::fs;
+
+use region_diff::{
+    config::{Config, init_config},
+    diff::{Diff, base::BlobDiff, chunk::RegionChunkDiff},
+    mca::{ChunkNbt, MCAReader},
+    util::{create_chunk_ixz_iter, serde::ser},
+};
+
+const REGIONS: &[&[&str]] = &[
+    &[
+        "resources/test-payload/region/mca/hairlessvillager-0/20250511.mca",
+        "resources/test-payload/region/mca/hairlessvillager-0/20250512.mca",
+        "resources/test-payload/region/mca/hairlessvillager-0/20250513.mca",
+        "resources/test-payload/region/mca/hairlessvillager-0/20250514.mca",
+        "resources/test-payload/region/mca/hairlessvillager-0/20250515.mca",
+        "resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+    ],
+    &[
+        "resources/test-payload/region/mca/ycc-atm/r.-1.-1.mca",
+        "resources/test-payload/region/mca/ycc-atm/r.-1.-1v2.mca",
+        "resources/test-payload/region/mca/ycc-atm/r.-1.-1v3.mca",
+    ],
+    &[
+        "resources/test-payload/region/mca/ycc-ender/r.0.-1v1.mca",
+        "resources/test-payload/region/mca/ycc-ender/r.0.-1v2.mca",
+        "resources/test-payload/region/mca/ycc-ender/r.0.-1v3.mca",
+    ],
+];
+
+#[derive(Default)]
+struct Sizes {
+    chunks: usize,
+    structured: usize,
+    blob: usize,
+}
+
+impl Sizes {
+    fn add(&mut self, other: &Sizes) {
+        self.chunks += other.chunks;
+        self.structured += other.structured;
+        self.blob += other.blob;
+    }
+    fn print(&self, name: &str) {
+        println!(
+            "{:<72} {:>6} {:>12} {:>12} {:>7.3}",
+            name,
+            self.chunks,
+            self.structured,
+            self.blob,
+            self.structured as f64 / self.blob as f64,
+        );
+    }
+}
+
+fn nbt_of(
+    reader: &MCAReader<impl std::io::Read + std::io::Seek>,
+    x: usize,
+    z: usize,
+) -> Option<Vec<u8>> {
+    match &reader.get_chunk(x, z).unwrap()?.nbt {
+        ChunkNbt::Small(nbt) => Some(nbt.clone()),
+        ChunkNbt::Large => None,
+    }
+}
+
+fn compare(old: &str, new: &str) -> Sizes {
+    let old_bytes = fs::read(old).unwrap();
+    let new_bytes = fs::read(new).unwrap();
+    let old_reader = MCAReader::from_bytes(&old_bytes).unwrap();
+    let new_reader = MCAReader::from_bytes(&new_bytes).unwrap();
+    let mut sizes = Sizes::default();
+    for (_, x, z) in create_chunk_ixz_iter() {
+        // only chunks present in both versions, the other cases are not diffed per chunk
+        let (Some(old_nbt), Some(new_nbt)) = (nbt_of(&old_reader, x, z), nbt_of(&new_reader, x, z))
+        else {
+            continue;
+        };
+        if old_nbt == new_nbt {
+            continue;
+        }
+        let old_value = old_reader.get_chunk_value(x, z).unwrap().unwrap();
+        let new_value = new_reader.get_chunk_value(x, z).unwrap().unwrap();
+        sizes.chunks += 1;
+        sizes.structured += ser(RegionChunkDiff::from_compare(&old_value, &new_value)).len();
+        sizes.blob += ser(BlobDiff::from_compare(&old_nbt, &new_nbt)).len();
+    }
+    sizes
+}
+
+fn main() {
+    init_config(Config::for_test());
+    println!(
+        "{:<72} {:>6} {:>12} {:>12} {:>7}",
+        "old -> new", "chunks", "structured", "blob", "ratio"
+    );
+    let mut total = Sizes::default();
+    for versions in REGIONS {
+        for pair in versions.windows(2) {
+            let sizes = compare(pair[0], pair[1]);
+            sizes.print(&format!(
+                "{} -> {}",
+                pair[0],
+                pair[1].rsplit('/').next().unwrap()
+            ));
+            total.add(&sizes);
+        }
+    }
+    total.print("total");
+    println!(
+        "structured diffs save {:.1}% over whole-chunk blobs",
+        (1.0 - total.structured as f64 / total.blob as f64) * 100.0
+    );
+}
@@ -9,10 +9,7 @@ use region_diff::{
 };
 
 fn criterion_benchmark(c: &mut Criterion) {
-    init_config(Config {
-        log_config: region_diff::config::LogConfig::NoLog,
-        threads: 16,
-    });
+    init_config(Config::for_test());
     let old =
         fs::read("resources/test-payload/region/mca/hairlessvillager-0/20250511.mca").unwrap();
     let new =
@@ -112,6 +112,37 @@ impl CompressionType {
         }
         Ok(())
     }
+    /// Like `compress`, but lets `write` produce the input directly into the encoder instead of
+    /// reading it from a buffer.
+    pub fn compress_with(
+        &self,
+        output: &mut impl Write,
+        write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            CompressionType::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(output, flate2::Compression::default());
+                write(&mut encoder)?;
+                encoder.finish()?;
+            }
+            CompressionType::Zlib => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(output, flate2::Compression::default());
+                write(&mut encoder)?;
+                encoder.finish()?;
+            }
+            CompressionType::No => {
+                write(output)?;
+            }
+            CompressionType::LZ4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(output);
+                write(&mut encoder)?;
+                encoder.finish()?;
+            }
+        }
+        Ok(())
+    }
     pub fn decompress(
         &self,
         input: &mut impl Read,
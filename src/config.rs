@@ -1,12 +1,49 @@
 #[cfg(not(test))]
 use std::sync::OnceLock;
 
+use crate::compress::CompressionType;
 use crate::logging::init_log;
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub log_config: LogConfig,
     pub threads: usize,
+    /// Stack size in bytes of each worker thread, large enough for deeply nested NBT
+    pub thread_stack_size: usize,
+    /// Compression type of the chunks in rebuilt region files
+    pub compression_type: CompressionType,
+    /// Dotted NBT tag paths left out of chunk diffs; patched chunks keep the base's values
+    pub ignored_tags: Vec<String>,
+    /// Seed to shuffle the chunk processing order with, instead of ordering by estimated cost
+    pub shuffle_seed: Option<u64>,
+    /// Record chunks whose nbt is byte-equal but timestamp differs as unchanged, without decoding
+    pub skip_equal_chunks: bool,
+    /// Take chunks with the same timestamp as unchanged without comparing their nbt
+    pub compare_timestamps: bool,
+    /// Keep the chunks of patched and reverted region files in the sectors they had in the input
+    pub preserve_empty_sectors: bool,
+    /// Combined size in bytes of the old and new data above which a `MyersDiff` stores them
+    /// whole instead of running Myers on them
+    pub algorithm_threshold: usize,
+}
+
+impl Config {
+    /// Quiet config shared by the tests and benches, with the same defaults as the command line
+    /// except for the thread count.
+    pub const fn for_test() -> Self {
+        Self {
+            log_config: LogConfig::NoLog,
+            threads: 16,
+            thread_stack_size: 8 << 20,
+            compression_type: CompressionType::Zlib,
+            ignored_tags: Vec::new(),
+            shuffle_seed: None,
+            skip_equal_chunks: false,
+            compare_timestamps: true,
+            preserve_empty_sectors: false,
+            algorithm_threshold: usize::MAX,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -56,6 +93,18 @@ pub fn get_config() -> Config {
     })
 }
 
+/// Same as `get_config`, or `None` before `init_config`, for code that library users may call
+/// without a config.
+#[cfg(not(test))]
+pub fn try_get_config() -> Option<Config> {
+    CONFIG.get().cloned()
+}
+
+#[cfg(test)]
+pub fn try_get_config() -> Option<Config> {
+    TEST_CONFIG.with(|c| c.borrow().clone())
+}
+
 #[cfg(test)]
 pub fn with_test_config<R>(config: Config, f: impl FnOnce() -> R) -> R {
     TEST_CONFIG.with(|c| {
@@ -1,6 +1,6 @@
 use bincode::{Decode, Encode};
 
-use crate::diff::Diff;
+use crate::diff::{Diff, DiffError, TryDiff};
 
 // Blob is one kind of git object, another two: Tree, Commit.
 //
@@ -37,6 +37,15 @@ impl Diff<Vec<u8>> for BlobDiff {
         self.old_text.clone()
     }
 }
+// a blob diff replaces the whole input, so any input is fine
+impl TryDiff<Vec<u8>> for BlobDiff {
+    fn try_patch(&self, old: &Vec<u8>) -> Result<Vec<u8>, DiffError> {
+        Ok(self.patch(old))
+    }
+    fn try_revert(&self, new: &Vec<u8>) -> Result<Vec<u8>, DiffError> {
+        Ok(self.revert(new))
+    }
+}
 impl BlobDiff {
     #[allow(dead_code)]
     pub fn new() -> Self {
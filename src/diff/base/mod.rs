@@ -3,3 +3,4 @@ mod myers;
 
 pub use blob::BlobDiff;
 pub use myers::MyersDiff;
+pub(crate) use myers::Replace;
@@ -1,22 +1,33 @@
-use bincode::{Decode, Encode};
+use bincode::{
+    Decode, Encode,
+    de::Decoder,
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+};
 use similar::{Algorithm, DiffOp, capture_diff_slices};
+use std::hash::Hash;
 use std::io::{Cursor, Read, Seek};
 
-use crate::diff::Diff;
+use crate::diff::{Diff, DiffError, TryDiff};
 
-#[derive(Debug, Encode, Decode, PartialEq, Clone)]
+/// Serialized with each replace's offsets stored relative to the end of the previous replace,
+/// which keeps them small for diffs of many scattered edits in a large text.
+#[derive(Debug, PartialEq, Clone)]
 pub struct MyersDiff {
-    old_text: Vec<u8>,
-    new_text: Vec<u8>,
-    replaces: Vec<Replace>,
+    pub(crate) old_text: Vec<u8>,
+    pub(crate) new_text: Vec<u8>,
+    pub(crate) replaces: Vec<Replace>,
+    /// Set by `strip_revert`, `old_text` is then empty. Not serialized: a patch-only diff
+    /// can't be encoded.
+    pub(crate) patch_only: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Encode, Decode)]
-struct Replace {
-    old_idx: usize,
-    old_len: usize,
-    new_idx: usize,
-    new_len: usize,
+pub(crate) struct Replace {
+    pub(crate) old_idx: usize,
+    pub(crate) old_len: usize,
+    pub(crate) new_idx: usize,
+    pub(crate) new_len: usize,
 }
 
 #[derive(Debug)]
@@ -44,19 +55,21 @@ enum VxPtr {
     Disable(usize),
 }
 
+/// Combined length in bytes of the inputs above which Myers, which takes O(ND) time on large
+/// dissimilar inputs, isn't run: see `Config::algorithm_threshold`. Unlimited without a config.
+fn algorithm_threshold() -> usize {
+    crate::config::try_get_config().map_or(usize::MAX, |config| config.algorithm_threshold)
+}
+
 static ERR_MSG: &str = "Failed to squash MyersDiff";
+static ERR_MSG_PATCH_ONLY: &str = "MyersDiff is patch-only and cannot be reverted";
 
-impl Diff<Vec<u8>> for MyersDiff {
-    fn from_compare(old: &Vec<u8>, new: &Vec<u8>) -> Self {
-        let mut diff = Self {
-            old_text: Vec::new(),
-            new_text: Vec::new(),
-            replaces: Vec::new(),
-        };
-        let ops = capture_diff_slices(Algorithm::Myers, old, new);
-        let mut old_ptr = 0;
-        let mut new_ptr = 0;
-        let replace_iter = ops.iter().filter_map(|op| match op {
+/// Replaces of the non-equal `ops`, in units of the diffed elements.
+fn replaces(ops: &[DiffOp]) -> Vec<Replace> {
+    let mut old_ptr = 0;
+    let mut new_ptr = 0;
+    ops.iter()
+        .filter_map(|op| match op {
             DiffOp::Equal {
                 old_index: _,
                 new_index: _,
@@ -110,58 +123,261 @@ impl Diff<Vec<u8>> for MyersDiff {
                 new_ptr += new_len;
                 r
             }
-        });
-        for replace in replace_iter {
-            diff.old_text
-                .extend_from_slice(&old[replace.old_idx..replace.old_idx + replace.old_len]);
-            diff.new_text
-                .extend_from_slice(&new[replace.new_idx..replace.new_idx + replace.new_len]);
-            diff.replaces.push(replace);
+        })
+        .collect()
+}
+
+impl Encode for MyersDiff {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        if self.patch_only {
+            return Err(EncodeError::Other("cannot encode a patch-only MyersDiff"));
         }
-        diff
+        self.old_text.encode(encoder)?;
+        self.new_text.encode(encoder)?;
+        self.replaces.len().encode(encoder)?;
+        let (mut old_end, mut new_end) = (0, 0);
+        for replace in &self.replaces {
+            (replace.old_idx - old_end).encode(encoder)?;
+            replace.old_len.encode(encoder)?;
+            (replace.new_idx - new_end).encode(encoder)?;
+            replace.new_len.encode(encoder)?;
+            old_end = replace.old_idx + replace.old_len;
+            new_end = replace.new_idx + replace.new_len;
+        }
+        Ok(())
+    }
+}
+
+impl<C> Decode<C> for MyersDiff {
+    fn decode<D: Decoder<Context = C>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let old_text = Vec::decode(decoder)?;
+        let new_text = Vec::decode(decoder)?;
+        let len = usize::decode(decoder)?;
+        let mut replaces = Vec::new();
+        let (mut old_end, mut new_end) = (0usize, 0usize);
+        for _ in 0..len {
+            let offset = |end: usize, delta: usize| {
+                end.checked_add(delta)
+                    .ok_or(DecodeError::Other("replace offset overflows"))
+            };
+            let old_idx = offset(old_end, usize::decode(decoder)?)?;
+            let old_len = usize::decode(decoder)?;
+            let new_idx = offset(new_end, usize::decode(decoder)?)?;
+            let new_len = usize::decode(decoder)?;
+            old_end = offset(old_idx, old_len)?;
+            new_end = offset(new_idx, new_len)?;
+            replaces.push(Replace {
+                old_idx,
+                old_len,
+                new_idx,
+                new_len,
+            });
+        }
+        Ok(Self {
+            old_text,
+            new_text,
+            replaces,
+            patch_only: false,
+        })
+    }
+}
+bincode::impl_borrow_decode!(MyersDiff);
+
+impl Diff<Vec<u8>> for MyersDiff {
+    fn from_compare(old: &Vec<u8>, new: &Vec<u8>) -> Self {
+        Self::from_compare_with_threshold(old, new, algorithm_threshold())
     }
 
     fn from_squash(base: &Self, squashing: &Self) -> Self {
+        assert!(
+            !base.patch_only && !squashing.patch_only,
+            "Cannot squash patch-only MyersDiff"
+        );
         let endpoints = Self::build_endpoints(&base, &squashing);
         Self::build_diff(&base, &squashing, &endpoints)
     }
 
     fn patch(&self, old: &Vec<u8>) -> Vec<u8> {
-        let capacity = old.len() - self.old_text.len() + self.new_text.len();
-        let mut patched = Vec::with_capacity(capacity);
+        let mut patched = Vec::new();
+        self.patch_into(old, &mut patched);
+        patched
+    }
+
+    fn revert(&self, new: &Vec<u8>) -> Vec<u8> {
+        let mut reverted = Vec::new();
+        self.revert_into(new, &mut reverted);
+        reverted
+    }
+}
+
+impl TryDiff<Vec<u8>> for MyersDiff {
+    fn try_patch(&self, old: &Vec<u8>) -> Result<Vec<u8>, DiffError> {
+        self.check_texts()?;
+        self.check_old(old)?;
+        Ok(self.patch(old))
+    }
+    fn try_revert(&self, new: &Vec<u8>) -> Result<Vec<u8>, DiffError> {
+        if self.patch_only {
+            return Err(DiffError::PatchOnly);
+        }
+        self.check_texts()?;
+        self.check_new(new)?;
+        Ok(self.revert(new))
+    }
+}
+
+impl MyersDiff {
+    /// Diff `old` and `new` with Myers, unless they are together longer than `threshold` bytes.
+    pub fn from_compare_with_threshold(old: &[u8], new: &[u8], threshold: usize) -> Self {
+        if old.len() + new.len() > threshold {
+            let whole = Replace {
+                old_idx: 0,
+                old_len: old.len(),
+                new_idx: 0,
+                new_len: new.len(),
+            };
+            let replaces = if old != new { vec![whole] } else { Vec::new() };
+            return Self::from_replaces(old, new, replaces);
+        }
+        let ops = capture_diff_slices(Algorithm::Myers, old, new);
+        Self::from_replaces(old, new, replaces(&ops))
+    }
+    /// Diff the bytes of the `old` tokens against those of the `new` tokens with Myers over the
+    /// tokens rather than their bytes, e.g. over block state indices or NBT tags, so that every
+    /// replace starts and ends on token boundaries instead of splitting multi-byte values.
+    /// `encode` appends the bytes of a token to the buffer it is given.
+    ///
+    /// The diff is patched and reverted on the concatenated bytes of the tokens, like any other.
+    pub fn from_tokens<T: Eq + Hash + Ord>(
+        old: &[T],
+        new: &[T],
+        encode: impl Fn(&T, &mut Vec<u8>),
+    ) -> Self {
+        // the bytes of the tokens, and the byte offset of every token and of the end of the last
+        let encode_all = |tokens: &[T]| {
+            let (mut bytes, mut offsets) = (Vec::new(), vec![0]);
+            for token in tokens {
+                encode(token, &mut bytes);
+                offsets.push(bytes.len());
+            }
+            (bytes, offsets)
+        };
+        let (old_bytes, old_offsets) = encode_all(old);
+        let (new_bytes, new_offsets) = encode_all(new);
+        let threshold = algorithm_threshold();
+        if old_bytes.len() + new_bytes.len() > threshold {
+            return Self::from_compare_with_threshold(&old_bytes, &new_bytes, threshold);
+        }
+        let ops = capture_diff_slices(Algorithm::Myers, old, new);
+        let replaces = replaces(&ops)
+            .into_iter()
+            .map(|replace| Replace {
+                old_idx: old_offsets[replace.old_idx],
+                old_len: old_offsets[replace.old_idx + replace.old_len]
+                    - old_offsets[replace.old_idx],
+                new_idx: new_offsets[replace.new_idx],
+                new_len: new_offsets[replace.new_idx + replace.new_len]
+                    - new_offsets[replace.new_idx],
+            })
+            .collect();
+        Self::from_replaces(&old_bytes, &new_bytes, replaces)
+    }
+    fn from_replaces(old: &[u8], new: &[u8], replaces: Vec<Replace>) -> Self {
+        let mut diff = Self {
+            old_text: Vec::new(),
+            new_text: Vec::new(),
+            replaces: Vec::new(),
+            patch_only: false,
+        };
+        for replace in replaces {
+            diff.old_text
+                .extend_from_slice(&old[replace.old_idx..replace.old_idx + replace.old_len]);
+            diff.new_text
+                .extend_from_slice(&new[replace.new_idx..replace.new_idx + replace.new_len]);
+            diff.replaces.push(replace);
+        }
+        diff
+    }
+    /// Drop the replaced bytes, which only `revert` needs, to shrink a diff held in memory that
+    /// will only be patched. Reverting or encoding the diff afterwards fails.
+    pub fn strip_revert(&mut self) {
+        self.old_text = Vec::new();
+        self.patch_only = true;
+    }
+    pub fn is_patch_only(&self) -> bool {
+        self.patch_only
+    }
+    /// Number of bytes this diff removes from the old text.
+    pub fn removed_len(&self) -> usize {
+        self.replaces.iter().map(|r| r.old_len).sum()
+    }
+    /// Check that the replaces take up exactly the recorded bytes, which a truncated diff doesn't.
+    /// Only the inserted bytes are recorded in a patch-only diff.
+    fn check_texts(&self) -> Result<(), DiffError> {
+        let total = |len: fn(&Replace) -> usize| {
+            self.replaces
+                .iter()
+                .try_fold(0usize, |total, replace| total.checked_add(len(replace)))
+        };
+        if total(|r| r.new_len) != Some(self.new_text.len())
+            || (!self.patch_only && total(|r| r.old_len) != Some(self.old_text.len()))
+        {
+            return Err(DiffError::Mismatch(format!(
+                "replaces do not add up to the {} removed and {} inserted bytes recorded",
+                self.old_text.len(),
+                self.new_text.len()
+            )));
+        }
+        Ok(())
+    }
+    /// Check that the bytes this diff replaces are in `old` as recorded. Only their ranges can
+    /// be checked for a patch-only diff.
+    pub fn check_old(&self, old: &[u8]) -> Result<(), DiffError> {
+        check_replaced(
+            old,
+            (!self.patch_only).then_some(&self.old_text[..]),
+            self.replaces.iter().map(|r| (r.old_idx, r.old_len)),
+        )
+    }
+    /// Check that the bytes this diff inserts are in `new` as recorded.
+    pub fn check_new(&self, new: &[u8]) -> Result<(), DiffError> {
+        check_replaced(
+            new,
+            Some(&self.new_text),
+            self.replaces.iter().map(|r| (r.new_idx, r.new_len)),
+        )
+    }
+    /// Same as `patch`, but writes into `out` (cleared first) to reuse its allocation.
+    pub fn patch_into(&self, old: &[u8], out: &mut Vec<u8>) {
+        out.clear();
+        out.reserve(old.len().saturating_sub(self.removed_len()) + self.new_text.len());
 
         let mut old_ptr: usize = 0;
         let mut new_text_ptr: usize = 0;
         for replace in &self.replaces {
-            patched.extend_from_slice(&old[old_ptr..replace.old_idx]);
-            patched.extend_from_slice(&self.new_text[new_text_ptr..new_text_ptr + replace.new_len]);
+            out.extend_from_slice(&old[old_ptr..replace.old_idx]);
+            out.extend_from_slice(&self.new_text[new_text_ptr..new_text_ptr + replace.new_len]);
             old_ptr = replace.old_idx + replace.old_len;
             new_text_ptr += replace.new_len;
         }
-        patched.extend_from_slice(&old[old_ptr..]);
-
-        patched
+        out.extend_from_slice(&old[old_ptr..]);
     }
-
-    fn revert(&self, new: &Vec<u8>) -> Vec<u8> {
-        let capacity = new.len() - self.new_text.len() + self.old_text.len();
-        let mut patched = Vec::with_capacity(capacity);
+    /// Same as `revert`, but writes into `out` (cleared first) to reuse its allocation.
+    pub fn revert_into(&self, new: &[u8], out: &mut Vec<u8>) {
+        assert!(!self.patch_only, "{}", ERR_MSG_PATCH_ONLY);
+        out.clear();
+        out.reserve(new.len().saturating_sub(self.new_text.len()) + self.old_text.len());
 
         let mut new_ptr: usize = 0;
         let mut old_text_ptr: usize = 0;
         for replace in &self.replaces {
-            patched.extend_from_slice(&new[new_ptr..replace.new_idx]);
-            patched.extend_from_slice(&self.old_text[old_text_ptr..old_text_ptr + replace.old_len]);
+            out.extend_from_slice(&new[new_ptr..replace.new_idx]);
+            out.extend_from_slice(&self.old_text[old_text_ptr..old_text_ptr + replace.old_len]);
             new_ptr = replace.new_idx + replace.new_len;
             old_text_ptr += replace.old_len;
         }
-        patched.extend_from_slice(&new[new_ptr..]);
-
-        patched
+        out.extend_from_slice(&new[new_ptr..]);
     }
-}
-
-impl MyersDiff {
     fn build_endpoints(base: &Self, squashing: &Self) -> Vec<NamedReplaceEndpoint> {
         let mut endpoints: Vec<NamedReplaceEndpoint> = base
             .replaces
@@ -206,6 +422,7 @@ impl MyersDiff {
             old_text: Vec::new(),
             new_text: Vec::new(),
             replaces: Vec::new(),
+            patch_only: false,
         };
 
         let mut v0_ptr = VxPtr::Disable(0);
@@ -359,14 +576,63 @@ impl MyersDiff {
     }
 }
 
+/// Check that `ranges` of `input` are in order and hold `text`, one after another.
+fn check_replaced(
+    input: &[u8],
+    text: Option<&[u8]>,
+    ranges: impl Iterator<Item = (usize, usize)>,
+) -> Result<(), DiffError> {
+    let mut input_ptr = 0;
+    let mut text_ptr: usize = 0;
+    for (idx, len) in ranges {
+        let (end, text_end) = match (idx.checked_add(len), text_ptr.checked_add(len)) {
+            (Some(end), Some(text_end)) => (end, text_end),
+            _ => {
+                return Err(DiffError::Mismatch(format!(
+                    "replaced range at {} of {} bytes overflows",
+                    idx, len
+                )));
+            }
+        };
+        let text_fits = text.is_none_or(|text| text_end <= text.len());
+        if idx < input_ptr || end > input.len() || !text_fits {
+            return Err(DiffError::Mismatch(format!(
+                "replaced range {}..{} does not fit in {} bytes",
+                idx,
+                end,
+                input.len()
+            )));
+        }
+        if text.is_some_and(|text| input[idx..end] != text[text_ptr..text_end]) {
+            return Err(DiffError::Mismatch(format!(
+                "bytes {}..{} differ from the recorded ones",
+                idx, end
+            )));
+        }
+        input_ptr = end;
+        text_ptr = text_end;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use similar::{Algorithm, DiffOp, capture_diff_slices};
 
-    use crate::util::test::create_test_bytes;
+    use crate::{
+        config::{Config, with_test_config},
+        util::test::create_test_bytes,
+    };
 
     use super::*;
 
+    fn test_config() -> Config {
+        Config {
+            compare_timestamps: false,
+            ..Config::for_test()
+        }
+    }
+
     #[test]
     fn test_similar_works() {
         let a = vec![1, 2, 3];
@@ -451,4 +717,197 @@ mod tests {
             assert_eq!(reverted_v2, v0, "v0: {:?}; v1{:?}; v2: {:?}", v0, v1, v2);
         }
     }
+    #[test]
+    fn test_patch_into_reuses_buffer() {
+        let mut old_iter = create_test_bytes(114514);
+        let mut new_iter = create_test_bytes(1919810);
+        let mut buffer = vec![42; 16];
+        for _ in 0..10_000 {
+            let old = old_iter.next().unwrap();
+            let new = new_iter.next().unwrap();
+            let diff = MyersDiff::from_compare(&old, &new);
+            diff.patch_into(&old, &mut buffer);
+            assert_eq!(buffer, diff.patch(&old), "old: {:?}; new: {:?}", old, new);
+            diff.revert_into(&new, &mut buffer);
+            assert_eq!(buffer, diff.revert(&new), "old: {:?}; new: {:?}", old, new);
+        }
+    }
+    #[test]
+    fn test_relative_offsets() {
+        use crate::util::serde::{de, ser};
+
+        let old = (0..200_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut new = old.clone();
+        for i in (100..new.len()).step_by(200) {
+            new[i] = new[i].wrapping_add(1);
+        }
+        let diff = MyersDiff::from_compare(&old, &new);
+        assert!(diff.replaces.len() >= 1000);
+
+        let relative = ser(&diff);
+        let absolute = ser((&diff.old_text, &diff.new_text, &diff.replaces));
+        assert!(relative.len() < absolute.len());
+        let decoded: MyersDiff = de(&relative);
+        assert_eq!(decoded, diff);
+        assert_eq!(decoded.patch(&old), new);
+    }
+    #[test]
+    fn test_check_replaced_overflow() {
+        let input = [0u8; 8];
+        for ranges in [
+            vec![(usize::MAX, 2)],
+            vec![(2, usize::MAX)],
+            vec![(4, 2), (2, 1)],
+        ] {
+            assert!(matches!(
+                check_replaced(&input, None, ranges.into_iter()),
+                Err(DiffError::Mismatch(_))
+            ));
+        }
+        assert!(check_replaced(&input, None, [(2, 2), (4, 4)].into_iter()).is_ok());
+    }
+    #[test]
+    fn test_try_patch_revert_truncated_text() {
+        let old = b"the quick brown fox".to_vec();
+        let new = b"the slow brown dog".to_vec();
+        let diff = MyersDiff::from_compare(&old, &new);
+        assert_eq!(diff.try_patch(&old).unwrap(), new);
+        assert_eq!(diff.try_revert(&new).unwrap(), old);
+
+        let mut truncated_new = diff.clone();
+        truncated_new.new_text.pop();
+        assert!(matches!(
+            truncated_new.try_patch(&old),
+            Err(DiffError::Mismatch(_))
+        ));
+        assert!(matches!(
+            truncated_new.try_revert(&new),
+            Err(DiffError::Mismatch(_))
+        ));
+
+        let mut truncated_old = diff.clone();
+        truncated_old.old_text.pop();
+        assert!(matches!(
+            truncated_old.try_revert(&new),
+            Err(DiffError::Mismatch(_))
+        ));
+        assert!(matches!(
+            truncated_old.try_patch(&old),
+            Err(DiffError::Mismatch(_))
+        ));
+    }
+    #[test]
+    fn test_strip_revert() {
+        use crate::util::serde::{de, ser};
+
+        let mut old_iter = create_test_bytes(114514);
+        let mut new_iter = create_test_bytes(1919810);
+        for _ in 0..1_000 {
+            let old = old_iter.next().unwrap();
+            let new = new_iter.next().unwrap();
+            let diff = MyersDiff::from_compare(&old, &new);
+            let mut stripped = diff.clone();
+            stripped.strip_revert();
+            assert!(stripped.is_patch_only());
+            assert!(stripped.old_text.is_empty());
+            assert!(bincode::encode_to_vec(&stripped, bincode::config::standard()).is_err());
+            assert!(!de::<MyersDiff>(&ser(&diff)).is_patch_only());
+
+            assert_eq!(stripped.try_patch(&old).unwrap(), diff.patch(&old));
+            assert!(matches!(
+                stripped.try_revert(&new),
+                Err(DiffError::PatchOnly)
+            ));
+        }
+    }
+    #[test]
+    fn test_algorithm_threshold() {
+        // above the threshold the data is stored whole, in a single replace spanning both
+        let is_whole = |diff: &MyersDiff, old: &[u8], new: &[u8]| match &diff.replaces[..] {
+            [] => old == new,
+            [replace] => {
+                replace.old_idx == 0
+                    && replace.old_len == old.len()
+                    && replace.new_idx == 0
+                    && replace.new_len == new.len()
+            }
+            _ => false,
+        };
+        let mut v0_iter = create_test_bytes(114514);
+        let mut v1_iter = create_test_bytes(1919810);
+        let mut v2_iter = create_test_bytes(19260817);
+        for _ in 0..10_000 {
+            let v0 = v0_iter.next().unwrap();
+            let v1 = v1_iter.next().unwrap();
+            let v2 = v2_iter.next().unwrap();
+            if v0.is_empty() && v1.is_empty() {
+                continue;
+            }
+            let threshold = v0.len() + v1.len() - 1;
+            let diff_v01 = MyersDiff::from_compare_with_threshold(&v0, &v1, threshold);
+            assert!(is_whole(&diff_v01, &v0, &v1));
+            assert_eq!(diff_v01.patch(&v0), v1);
+            assert_eq!(diff_v01.revert(&v1), v0);
+
+            let diff_v12 = MyersDiff::from_compare_with_threshold(&v1, &v2, usize::MAX);
+            let squashed = MyersDiff::from_squash(&diff_v01, &diff_v12);
+            assert_eq!(squashed.patch(&v0), v2);
+            assert_eq!(squashed.revert(&v2), v0);
+        }
+
+        // `from_compare` takes the threshold from the config; a single changed byte is a
+        // single-byte replace for Myers
+        let old = (0..64u8).collect::<Vec<_>>();
+        let mut new = old.clone();
+        new[32] = 0xff;
+        let config = Config {
+            algorithm_threshold: 100,
+            ..test_config()
+        };
+        let diff = with_test_config(config, || MyersDiff::from_compare(&old, &new));
+        assert!(is_whole(&diff, &old, &new));
+        let diff = with_test_config(test_config(), || MyersDiff::from_compare(&old, &new));
+        assert!(!is_whole(&diff, &old, &new));
+        assert_eq!(diff.patch(&old), new);
+    }
+    #[test]
+    fn test_from_tokens() {
+        let bytes = |values: &[u32]| {
+            values
+                .iter()
+                .flat_map(|v| v.to_be_bytes())
+                .collect::<Vec<_>>()
+        };
+        let old_values = (0..1000u32).map(|i| i * 0x0101).collect::<Vec<_>>();
+        let mut new_values = old_values.clone();
+        for i in (7..new_values.len()).step_by(50) {
+            // shares all but the last byte with the old value
+            new_values[i] += 1;
+        }
+        new_values.insert(500, 0xdeadbeef);
+        new_values.remove(900);
+        let (old_bytes, new_bytes) = (bytes(&old_values), bytes(&new_values));
+
+        let diff = MyersDiff::from_tokens(&old_values, &new_values, |v, out| {
+            out.extend_from_slice(&v.to_be_bytes())
+        });
+        assert!(!diff.replaces.is_empty());
+        for replace in &diff.replaces {
+            assert_eq!(replace.old_idx % 4, 0, "{:?}", replace);
+            assert_eq!(replace.old_len % 4, 0, "{:?}", replace);
+            assert_eq!(replace.new_idx % 4, 0, "{:?}", replace);
+            assert_eq!(replace.new_len % 4, 0, "{:?}", replace);
+        }
+        assert_eq!(diff.patch(&old_bytes), new_bytes);
+        assert_eq!(diff.revert(&new_bytes), old_bytes);
+
+        // byte by byte, the changed values are only partly replaced
+        let bytewise = MyersDiff::from_compare(&old_bytes, &new_bytes);
+        assert!(
+            bytewise
+                .replaces
+                .iter()
+                .any(|replace| replace.old_len % 4 != 0)
+        );
+    }
 }
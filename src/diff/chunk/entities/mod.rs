@@ -1,13 +1,12 @@
 use bincode::{Decode, Encode};
 use fastnbt::Value;
 use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 
-use crate::diff::Diff;
 use crate::diff::base::{BlobDiff, MyersDiff};
+use crate::diff::{Diff, DiffError, TryDiff};
 use crate::util::nbt_serde::{de, ser};
 
-type Uuid = [i32; 4];
-
 #[derive(Debug, Clone, Encode, Decode)]
 enum EntityDiff {
     Create(BlobDiff),
@@ -17,35 +16,56 @@ enum EntityDiff {
 
 #[derive(Debug, Clone, Encode, Decode)]
 pub struct EntitiesDiff {
-    old_uuid_list: Vec<Uuid>,
-    new_uuid_list: Vec<Uuid>,
-    map: BTreeMap<Uuid, EntityDiff>,
+    old_uuid_list: Vec<EntityKey>,
+    new_uuid_list: Vec<EntityKey>,
+    map: BTreeMap<EntityKey, EntityDiff>,
+}
+
+/// Identity of an entity: its UUID as four ints, most significant first, as stored in `UUID`
+/// since 1.16.
+type EntityKey = [i32; 4];
+
+/// The UUID of an entity, read from the `UUID` int array, the `UUIDMost`/`UUIDLeast` longs of
+/// older versions or a `UUID` string, so that an entity is matched across these forms.
+fn try_entity_key(e: &Value) -> Result<EntityKey, DiffError> {
+    let invalid = |msg: String| DiffError::InvalidInput(msg);
+    let kv = match e {
+        Value::Compound(kv) => kv,
+        _ => return Err(invalid("'e' should be Value::Compound".to_string())),
+    };
+    match (kv.get("UUID"), kv.get("UUIDMost"), kv.get("UUIDLeast")) {
+        (Some(Value::IntArray(int_array)), _, _) => int_array
+            .iter()
+            .copied()
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| invalid("The length of the IntArray should be 4 to form a UUID.".into())),
+        (Some(Value::String(uuid)), _, _) => {
+            let uuid = u128::from_str_radix(&uuid.replace('-', ""), 16)
+                .map_err(|_| invalid(format!("Invalid UUID string {:?}", uuid)))?;
+            Ok([96, 64, 32, 0].map(|shift| (uuid >> shift) as u32 as i32))
+        }
+        (None, Some(Value::Long(most)), Some(Value::Long(least))) => Ok([
+            (most >> 32) as i32,
+            *most as i32,
+            (least >> 32) as i32,
+            *least as i32,
+        ]),
+        _ => Err(invalid(
+            "Entity should have a 'UUID' or 'UUIDMost' and 'UUIDLeast'.".to_string(),
+        )),
+    }
+}
+fn entity_key(e: &Value) -> EntityKey {
+    try_entity_key(e).unwrap_or_else(|e| panic!("{}", e))
 }
 
-fn build_es_uuid_map_and_uuid_list(es: &Value) -> (BTreeMap<Uuid, &Value>, Vec<Uuid>) {
+fn build_es_uuid_map_and_uuid_list(es: &Value) -> (BTreeMap<EntityKey, &Value>, Vec<EntityKey>) {
     match es {
         Value::List(es) => {
-            let i = es.iter().map(|e| match e {
-                Value::Compound(kv) => {
-                    let uuid = match kv.get("UUID") {
-                        Some(Value::IntArray(int_array)) => {
-                            if int_array.len() != 4 {
-                                panic!("The length of the IntArray should be 4 to form a Uuid.");
-                            }
-                            let mut uuid_array = [0; 4];
-                            for (i, &val) in int_array.iter().enumerate() {
-                                uuid_array[i] = val;
-                            }
-                            uuid_array
-                        }
-                        _ => panic!("The value for 'UUID' should be a Value::IntArray."),
-                    };
-                    (uuid, e)
-                }
-                _ => panic!("'be.x' should be Value::Compound"),
-            });
+            let i = es.iter().map(|e| (entity_key(e), e));
             (
-                BTreeMap::from_iter(i.clone().map(|(uuid, e)| (uuid, e))),
+                BTreeMap::from_iter(i.clone()),
                 Vec::from_iter(i.map(|(uuid, _)| uuid)),
             )
         }
@@ -53,37 +73,68 @@ fn build_es_uuid_map_and_uuid_list(es: &Value) -> (BTreeMap<Uuid, &Value>, Vec<U
     }
 }
 
-fn build_es_map(es: &Value) -> BTreeMap<Uuid, Value> {
-    match es {
-        Value::List(es) => BTreeMap::from_iter(es.iter().map(|e| match e {
-            Value::Compound(kv) => {
-                let uuid = match kv.get("UUID") {
-                    Some(Value::IntArray(int_array)) => {
-                        if int_array.len() != 4 {
-                            panic!("The length of the IntArray should be 4 to form a Uuid.");
-                        }
-                        let mut uuid_array = [0; 4];
-                        for (i, &val) in int_array.iter().enumerate() {
-                            uuid_array[i] = val;
-                        }
-                        uuid_array
-                    }
-                    _ => panic!("The value for 'UUID' should be a Value::IntArray."),
-                };
-                (uuid, e.clone())
-            }
-            _ => panic!("'be.x' should be Value::Compound"),
-        })),
-        _ => panic!("'bes' should be Value::List"),
+/// Entities by their UUID. Fails if the list is malformed or two entities share a UUID.
+fn try_build_es_map(es: &Value) -> Result<BTreeMap<EntityKey, Value>, DiffError> {
+    let Value::List(es) = es else {
+        return Err(DiffError::InvalidInput(
+            "'es' should be Value::List".to_string(),
+        ));
+    };
+    let mut map = BTreeMap::new();
+    for e in es {
+        let uuid = try_entity_key(e)?;
+        if map.insert(uuid, e.clone()).is_some() {
+            return Err(DiffError::InvalidInput(format!(
+                "two entities have UUID {:?}",
+                uuid
+            )));
+        }
     }
+    Ok(map)
+}
+fn build_es_map(es: &Value) -> BTreeMap<EntityKey, Value> {
+    try_build_es_map(es).unwrap_or_else(|e| panic!("{}", e))
 }
 
-fn build_es_value(mut map: BTreeMap<Uuid, Value>, uuid_list: &Vec<Uuid>) -> Value {
+fn build_es_value(mut map: BTreeMap<EntityKey, Value>, uuid_list: &Vec<EntityKey>) -> Value {
     Value::List(Vec::from_iter(
         uuid_list.iter().map(|uuid| map.remove(uuid).unwrap()),
     ))
 }
 
+impl EntitiesDiff {
+    /// Check that `es` holds the entities of the old side of this diff if `is_old`, or of the
+    /// new side otherwise.
+    fn check(&self, es: &Value, is_old: bool) -> Result<(), DiffError> {
+        let es_map = try_build_es_map(es)?;
+        let uuid_list = if is_old {
+            &self.old_uuid_list
+        } else {
+            &self.new_uuid_list
+        };
+        if es_map.len() != uuid_list.len()
+            || uuid_list.iter().any(|uuid| !es_map.contains_key(uuid))
+        {
+            return Err(DiffError::Mismatch(
+                "entity UUIDs differ from the recorded ones".to_string(),
+            ));
+        }
+        for (uuid, diff) in &self.map {
+            if let EntityDiff::Update(diff) = diff {
+                let e = es_map
+                    .get(uuid)
+                    .ok_or_else(|| DiffError::Mismatch(format!("entity {:?} is missing", uuid)))?;
+                let e = ser(e);
+                match is_old {
+                    true => diff.check_old(&e)?,
+                    false => diff.check_new(&e)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Diff<Value> for EntitiesDiff {
     fn from_compare(old: &Value, new: &Value) -> Self
     where
@@ -170,6 +221,9 @@ impl Diff<Value> for EntitiesDiff {
                     squashed.map(|diff| (uuid.clone(), diff))
                 }),
             );
+        // The squashed diff spans base.old -> squashing.new, so the list orders are taken from
+        // those two ends. Entities created and then deleted in between appear in neither list
+        // and are dropped from the map above.
         Self {
             old_uuid_list: base.old_uuid_list.clone(),
             new_uuid_list: squashing.new_uuid_list.clone(),
@@ -318,8 +372,165 @@ impl Diff<Value> for EntitiesChunkDiff {
     }
 }
 
+impl EntitiesChunkDiff {
+    /// Numbers of entities this diff creates, deletes and updates.
+    pub fn entity_changes(&self) -> (usize, usize, usize) {
+        self.entities
+            .map
+            .values()
+            .fold((0, 0, 0), |(c, d, u), diff| match diff {
+                EntityDiff::Create(_) => (c + 1, d, u),
+                EntityDiff::Delete(_) => (c, d + 1, u),
+                EntityDiff::Update(myers) if myers.replaces.is_empty() => (c, d, u),
+                EntityDiff::Update(_) => (c, d, u + 1),
+            })
+    }
+    /// Check that `chunk` is shaped like the old side of this diff if `is_old`, or like the new
+    /// side otherwise, so that patching or reverting it cannot fail.
+    fn check_chunk(&self, chunk: &Value, is_old: bool) -> Result<(), DiffError> {
+        let mut chunk = match chunk {
+            Value::Compound(x) => x.clone(),
+            _ => {
+                return Err(DiffError::InvalidInput(
+                    "chunk is not a compound".to_string(),
+                ));
+            }
+        };
+        let entities = chunk
+            .remove("Entities")
+            .ok_or_else(|| DiffError::InvalidInput("Entities is missing".to_string()))?;
+        self.entities.check(&entities, is_old)?;
+        let others = ser(&Value::Compound(chunk));
+        match is_old {
+            true => self.others.check_old(&others),
+            false => self.others.check_new(&others),
+        }
+    }
+}
+
+/// Compact summary like `entities=+2/-1/~0`, counting created, deleted and updated entities.
+impl fmt::Display for EntitiesChunkDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (created, deleted, updated) = self.entity_changes();
+        write!(f, "entities=+{}/-{}/~{}", created, deleted, updated)
+    }
+}
+
+impl TryDiff<Value> for EntitiesChunkDiff {
+    fn try_patch(&self, old: &Value) -> Result<Value, DiffError> {
+        self.check_chunk(old, true)?;
+        Ok(self.patch(old))
+    }
+    fn try_revert(&self, new: &Value) -> Result<Value, DiffError> {
+        self.check_chunk(new, false)?;
+        Ok(self.revert(new))
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    mod test_entity_key {
+        use fastnbt::{Value, nbt};
+
+        use crate::diff::Diff;
+
+        use super::super::{
+            EntitiesDiff, EntityDiff, entity_key, try_build_es_map, try_entity_key,
+        };
+        use crate::diff::DiffError;
+
+        #[test]
+        fn test_uuid_forms() {
+            let int_array = nbt!({ "UUID": [I; 1, -2, 3, -4] });
+            let longs = nbt!({
+                "UUIDMost": (1_i64 << 32) | (-2_i32 as u32 as i64),
+                "UUIDLeast": (3_i64 << 32) | (-4_i32 as u32 as i64),
+            });
+            let string = nbt!({ "UUID": "00000001-ffff-fffe-0000-0003fffffffc" });
+            assert_eq!(entity_key(&int_array), [1, -2, 3, -4]);
+            assert_eq!(entity_key(&longs), [1, -2, 3, -4]);
+            assert_eq!(entity_key(&string), [1, -2, 3, -4]);
+        }
+
+        #[test]
+        fn test_malformed_entities() {
+            for e in [
+                Value::Int(0),
+                nbt!({ "id": "minecraft:pig" }),
+                nbt!({ "UUID": [I; 1, 2, 3] }),
+                nbt!({ "UUID": "not a uuid" }),
+            ] {
+                assert!(matches!(
+                    try_entity_key(&e),
+                    Err(DiffError::InvalidInput(_))
+                ));
+            }
+            let e = nbt!({ "UUID": [I; 1, 2, 3, 4] });
+            assert_eq!(
+                try_build_es_map(&Value::List(vec![e.clone()]))
+                    .unwrap()
+                    .len(),
+                1
+            );
+            assert!(try_build_es_map(&Value::List(vec![e.clone(), e])).is_err());
+        }
+
+        #[test]
+        fn test_match_across_versions() {
+            let old = Value::List(vec![nbt!({
+                "UUIDMost": 42_i64,
+                "UUIDLeast": -7_i64,
+                "id": "Cow",
+                "Health": 10_f32,
+            })]);
+            let new = Value::List(vec![nbt!({
+                "UUID": [I; 0, 42, -1, -7],
+                "id": "minecraft:cow",
+                "Health": 8_f32,
+            })]);
+            let diff = EntitiesDiff::from_compare(&old, &new);
+            assert_eq!(diff.map.len(), 1);
+            assert!(matches!(diff.map[&[0, 42, -1, -7]], EntityDiff::Update(_)));
+            assert_eq!(diff.patch(&old), new);
+        }
+    }
+    mod test_list_order {
+        use fastnbt::{Value, nbt};
+
+        use crate::diff::Diff;
+
+        use super::super::EntitiesDiff;
+
+        fn entity(uuid: i32, id: &str) -> Value {
+            nbt!({
+                "UUID": [I; uuid, 0, 0, 0],
+                "id": id,
+            })
+        }
+
+        #[test]
+        fn test_squash_order_matches_direct_diff() {
+            let (a, b, c) = (
+                entity(1, "minecraft:cow"),
+                entity(2, "minecraft:pig"),
+                entity(3, "minecraft:sheep"),
+            );
+            let v0 = Value::List(vec![a.clone(), b.clone()]);
+            let v1 = Value::List(vec![a.clone(), c.clone(), b.clone()]);
+            let v2 = Value::List(vec![b.clone(), a.clone()]);
+
+            let diff_v01 = EntitiesDiff::from_compare(&v0, &v1);
+            let diff_v12 = EntitiesDiff::from_compare(&v1, &v2);
+            let squashed_diff = EntitiesDiff::from_squash(&diff_v01, &diff_v12);
+            let direct_diff = EntitiesDiff::from_compare(&v0, &v2);
+
+            assert_eq!(squashed_diff.patch(&v0), direct_diff.patch(&v0));
+            assert_eq!(squashed_diff.revert(&v2), direct_diff.revert(&v2));
+            assert_eq!(squashed_diff.patch(&v0), v2);
+            assert_eq!(squashed_diff.revert(&v2), v0);
+        }
+    }
+
     mod test_in_continuous_data {
         use crate::diff::Diff;
         use crate::diff::chunk::EntitiesChunkDiff;
@@ -1,8 +1,12 @@
 mod entities;
+mod poi;
 mod region;
+mod subtree;
 
 pub use entities::EntitiesChunkDiff;
-pub use region::RegionChunkDiff;
+pub use poi::PoiChunkDiff;
+pub use region::{ChangeDescription, RegionChunkDiff};
+pub use subtree::{SubtreeDiffer, register_subtree_differ};
 
 #[cfg(test)]
 mod tests {
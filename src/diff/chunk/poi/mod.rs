@@ -0,0 +1,369 @@
+use bincode::{Decode, Encode};
+use fastnbt::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::diff::base::{BlobDiff, MyersDiff};
+use crate::diff::{Diff, DiffError, TryDiff};
+use crate::util::nbt_serde::{de, ser};
+
+#[derive(Debug, Clone, Encode, Decode)]
+enum PoiSectionDiff {
+    Create(BlobDiff),
+    Delete(BlobDiff),
+    Update(MyersDiff),
+}
+
+/// Diff of the `Sections` compound of a POI chunk, whose keys are the section Y indexes as
+/// strings and whose values hold the `Records` of the points of interest in that section.
+#[derive(Debug, Clone, Encode, Decode)]
+struct PoiSectionsDiff {
+    map: BTreeMap<String, PoiSectionDiff>,
+}
+
+fn build_sections_map(sections: &Value) -> BTreeMap<String, Value> {
+    match sections {
+        Value::Compound(kv) => kv.iter().map(|(y, v)| (y.clone(), v.clone())).collect(),
+        _ => panic!("'Sections' should be Value::Compound"),
+    }
+}
+
+impl PoiSectionsDiff {
+    /// Check that `sections` holds the sections of the old side of this diff if `is_old`, or of
+    /// the new side otherwise.
+    fn check(&self, sections: &Value, is_old: bool) -> Result<(), DiffError> {
+        let Value::Compound(sections) = sections else {
+            return Err(DiffError::InvalidInput(
+                "Sections is not a compound".to_string(),
+            ));
+        };
+        for (y, diff) in &self.map {
+            let section = sections.get(y);
+            match (diff, section, is_old) {
+                (PoiSectionDiff::Create(_), None, true)
+                | (PoiSectionDiff::Delete(_), None, false) => {}
+                (PoiSectionDiff::Create(_), Some(_), false)
+                | (PoiSectionDiff::Delete(_), Some(_), true) => {}
+                (PoiSectionDiff::Update(diff), Some(section), _) => match is_old {
+                    true => diff.check_old(&ser(section))?,
+                    false => diff.check_new(&ser(section))?,
+                },
+                _ => {
+                    return Err(DiffError::Mismatch(format!(
+                        "POI section {} differs from the recorded one",
+                        y
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Diff<Value> for PoiSectionsDiff {
+    fn from_compare(old: &Value, new: &Value) -> Self {
+        let old_map = build_sections_map(old);
+        let new_map = build_sections_map(new);
+        let ys = BTreeSet::from_iter(old_map.keys().chain(new_map.keys()));
+        let map = BTreeMap::from_iter(ys.into_iter().filter_map(|y| {
+            let diff = match (old_map.get(y), new_map.get(y)) {
+                (None, Some(new)) => PoiSectionDiff::Create(BlobDiff::from_create(&ser(new))),
+                (Some(old), None) => PoiSectionDiff::Delete(BlobDiff::from_delete(&ser(old))),
+                // most sections of a chunk are left as they are
+                (Some(old), Some(new)) if old == new => return None,
+                (Some(old), Some(new)) => {
+                    PoiSectionDiff::Update(MyersDiff::from_compare(&ser(old), &ser(new)))
+                }
+                (None, None) => unreachable!(),
+            };
+            Some((y.clone(), diff))
+        }));
+        Self { map }
+    }
+
+    fn from_squash(base: &Self, squashing: &Self) -> Self {
+        let ys = BTreeSet::from_iter(base.map.keys().chain(squashing.map.keys()));
+        let map = BTreeMap::from_iter(ys.into_iter().filter_map(|y| {
+            let squashed = match (base.map.get(y), squashing.map.get(y)) {
+                (None, Some(diff)) | (Some(diff), None) => Some(diff.clone()),
+                (Some(PoiSectionDiff::Create(_)), Some(PoiSectionDiff::Delete(_))) => None,
+                (Some(PoiSectionDiff::Delete(base)), Some(PoiSectionDiff::Create(squashing))) => {
+                    Some(PoiSectionDiff::Update(MyersDiff::from_compare(
+                        base.get_old_text(),
+                        squashing.get_new_text(),
+                    )))
+                }
+                (Some(PoiSectionDiff::Create(blob)), Some(PoiSectionDiff::Update(myers))) => {
+                    Some(PoiSectionDiff::Create(BlobDiff::from_create(
+                        &myers.patch(blob.get_new_text()),
+                    )))
+                }
+                (Some(PoiSectionDiff::Update(myers)), Some(PoiSectionDiff::Delete(blob))) => {
+                    Some(PoiSectionDiff::Delete(BlobDiff::from_delete(
+                        &myers.revert(blob.get_old_text()),
+                    )))
+                }
+                (Some(PoiSectionDiff::Update(base)), Some(PoiSectionDiff::Update(squashing))) => {
+                    Some(PoiSectionDiff::Update(MyersDiff::from_squash(
+                        base, squashing,
+                    )))
+                }
+                (Some(base), Some(squashing)) => panic!(
+                    "Mismatched base diff {:?} and squashing diff {:?} for POI section {}",
+                    base, squashing, y
+                ),
+                (None, None) => unreachable!(),
+            };
+            squashed.map(|diff| (y.clone(), diff))
+        }));
+        Self { map }
+    }
+
+    fn patch(&self, old: &Value) -> Value {
+        let mut map = build_sections_map(old);
+        for (y, diff) in &self.map {
+            let new_section = match (map.get(y), diff) {
+                (None, PoiSectionDiff::Create(diff)) => Some(de(&diff.patch0())),
+                (Some(_), PoiSectionDiff::Delete(_)) => None,
+                (Some(old), PoiSectionDiff::Update(diff)) => Some(de(&diff.patch(&ser(old)))),
+                (old, diff) => panic!("Unmatching {:?} and {:?} for POI section {}", old, diff, y),
+            };
+            match new_section {
+                Some(section) => map.insert(y.clone(), section),
+                None => map.remove(y),
+            };
+        }
+        Value::Compound(map.into_iter().collect())
+    }
+
+    fn revert(&self, new: &Value) -> Value {
+        let mut map = build_sections_map(new);
+        for (y, diff) in &self.map {
+            let old_section = match (diff, map.get(y)) {
+                (PoiSectionDiff::Create(_), Some(_)) => None,
+                (PoiSectionDiff::Delete(diff), None) => Some(de(&diff.revert0())),
+                (PoiSectionDiff::Update(diff), Some(new)) => Some(de(&diff.revert(&ser(new)))),
+                (diff, new) => panic!("Unmatching {:?} and {:?} for POI section {}", diff, new, y),
+            };
+            match old_section {
+                Some(section) => map.insert(y.clone(), section),
+                None => map.remove(y),
+            };
+        }
+        Value::Compound(map.into_iter().collect())
+    }
+}
+
+/// Diff of a chunk of a `poi/*.mca` file, which holds the points of interest (beds, job sites,
+/// portals, ...) of the chunk instead of blocks.
+#[derive(Debug, Encode, Decode, Clone)]
+pub struct PoiChunkDiff {
+    sections: PoiSectionsDiff,
+    others: MyersDiff,
+}
+
+static ERR_MSG_OLD: &str = "Invalid old nbt";
+static ERR_MSG_NEW: &str = "Invalid new nbt";
+
+impl Diff<Value> for PoiChunkDiff {
+    fn from_compare(old: &Value, new: &Value) -> Self
+    where
+        Self: Sized,
+    {
+        let mut old = match old {
+            Value::Compound(x) => x.clone(),
+            _ => panic!("{}", ERR_MSG_OLD),
+        };
+        let mut new = match new {
+            Value::Compound(x) => x.clone(),
+            _ => panic!("{}", ERR_MSG_NEW),
+        };
+        let diff_sections;
+        {
+            let old_sections = old.remove("Sections").expect(ERR_MSG_OLD);
+            let new_sections = new.remove("Sections").expect(ERR_MSG_NEW);
+            diff_sections = PoiSectionsDiff::from_compare(&old_sections, &new_sections);
+        }
+
+        let diff_others;
+        {
+            let old_others = ser(&Value::Compound(old));
+            let new_others = ser(&Value::Compound(new));
+            diff_others = MyersDiff::from_compare(&old_others, &new_others);
+        }
+
+        Self {
+            sections: diff_sections,
+            others: diff_others,
+        }
+    }
+
+    fn from_squash(base: &Self, squashing: &Self) -> Self
+    where
+        Self: Sized,
+    {
+        let sections = PoiSectionsDiff::from_squash(&base.sections, &squashing.sections);
+        let others = MyersDiff::from_squash(&base.others, &squashing.others);
+        Self { sections, others }
+    }
+
+    fn patch(&self, old: &Value) -> Value {
+        let mut old = match old {
+            Value::Compound(x) => x.clone(),
+            _ => panic!("{}", ERR_MSG_OLD),
+        };
+        let sections;
+        {
+            let old_sections = old.remove("Sections").expect(ERR_MSG_OLD);
+            sections = self.sections.patch(&old_sections);
+        }
+        let mut others;
+        {
+            let old_others = ser(&Value::Compound(old));
+            let new_others = self.others.patch(&old_others);
+            let wrapped_others: Value = de(&new_others);
+            others = match wrapped_others {
+                Value::Compound(x) => x,
+                _ => panic!("{}", ERR_MSG_NEW),
+            }
+        }
+        others.insert("Sections".to_string(), sections);
+        Value::Compound(others)
+    }
+
+    fn revert(&self, new: &Value) -> Value {
+        let mut new = match new {
+            Value::Compound(x) => x.clone(),
+            _ => panic!("{}", ERR_MSG_NEW),
+        };
+        let sections;
+        {
+            let new_sections = new.remove("Sections").expect(ERR_MSG_NEW);
+            sections = self.sections.revert(&new_sections);
+        }
+        let mut others;
+        {
+            let new_others = ser(&Value::Compound(new));
+            let old_others = self.others.revert(&new_others);
+            let wrapped_others: Value = de(&old_others);
+            others = match wrapped_others {
+                Value::Compound(x) => x,
+                _ => panic!("{}", ERR_MSG_OLD),
+            };
+        }
+        others.insert("Sections".to_string(), sections);
+        Value::Compound(others)
+    }
+}
+
+impl PoiChunkDiff {
+    /// Numbers of POI sections this diff creates, deletes and updates.
+    pub fn section_changes(&self) -> (usize, usize, usize) {
+        self.sections
+            .map
+            .values()
+            .fold((0, 0, 0), |(c, d, u), diff| match diff {
+                PoiSectionDiff::Create(_) => (c + 1, d, u),
+                PoiSectionDiff::Delete(_) => (c, d + 1, u),
+                PoiSectionDiff::Update(_) => (c, d, u + 1),
+            })
+    }
+    /// Check that `chunk` is shaped like the old side of this diff if `is_old`, or like the new
+    /// side otherwise, so that patching or reverting it cannot fail.
+    fn check_chunk(&self, chunk: &Value, is_old: bool) -> Result<(), DiffError> {
+        let mut chunk = match chunk {
+            Value::Compound(x) => x.clone(),
+            _ => {
+                return Err(DiffError::InvalidInput(
+                    "chunk is not a compound".to_string(),
+                ));
+            }
+        };
+        let sections = chunk
+            .remove("Sections")
+            .ok_or_else(|| DiffError::InvalidInput("Sections is missing".to_string()))?;
+        self.sections.check(&sections, is_old)?;
+        let others = ser(&Value::Compound(chunk));
+        match is_old {
+            true => self.others.check_old(&others),
+            false => self.others.check_new(&others),
+        }
+    }
+}
+
+/// Compact summary like `poi_sections=+1/-0/~2`, counting created, deleted and updated POI
+/// sections.
+impl fmt::Display for PoiChunkDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (created, deleted, updated) = self.section_changes();
+        write!(f, "poi_sections=+{}/-{}/~{}", created, deleted, updated)
+    }
+}
+
+impl TryDiff<Value> for PoiChunkDiff {
+    fn try_patch(&self, old: &Value) -> Result<Value, DiffError> {
+        self.check_chunk(old, true)?;
+        Ok(self.patch(old))
+    }
+    fn try_revert(&self, new: &Value) -> Result<Value, DiffError> {
+        self.check_chunk(new, false)?;
+        Ok(self.revert(new))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fastnbt::nbt;
+
+    use super::*;
+
+    fn poi_chunk(sections: Value) -> Value {
+        nbt!({
+            "DataVersion": 4189,
+            "Sections": sections,
+        })
+    }
+
+    #[test]
+    fn test_diff_patch_revert_squash() {
+        let v0 = poi_chunk(nbt!({
+            "4": {
+                "Valid": 1_i8,
+                "Records": [
+                    { "type": "minecraft:home", "pos": [I; 3, 70, 5], "free_tickets": 1 }
+                ]
+            }
+        }));
+        let v1 = poi_chunk(nbt!({
+            "4": {
+                "Valid": 1_i8,
+                "Records": [
+                    { "type": "minecraft:home", "pos": [I; 3, 70, 5], "free_tickets": 0 }
+                ]
+            },
+            "-1": {
+                "Valid": 1_i8,
+                "Records": [
+                    { "type": "minecraft:nether_portal", "pos": [I; 0, -10, 0], "free_tickets": 0 }
+                ]
+            }
+        }));
+        let v2 = poi_chunk(nbt!({
+            "-1": {
+                "Valid": 1_i8,
+                "Records": []
+            }
+        }));
+
+        let diff_v01 = PoiChunkDiff::from_compare(&v0, &v1);
+        assert_eq!(diff_v01.section_changes(), (1, 0, 1));
+        assert_eq!(diff_v01.patch(&v0), v1);
+        assert_eq!(diff_v01.revert(&v1), v0);
+        assert!(diff_v01.try_patch(&v1).is_err());
+
+        let diff_v12 = PoiChunkDiff::from_compare(&v1, &v2);
+        let squashed = PoiChunkDiff::from_squash(&diff_v01, &diff_v12);
+        assert_eq!(squashed.patch(&v0), v2);
+        assert_eq!(squashed.revert(&v2), v0);
+    }
+}
@@ -5,12 +5,15 @@ use fastnbt::Value;
 
 use crate::{
     diff::{
-        Diff,
+        Diff, DiffError,
         base::{BlobDiff, MyersDiff},
     },
     util::nbt_serde::{de, ser},
 };
-type XYZ = (i32, i32, i32);
+
+use super::ChangeDescription;
+
+pub type XYZ = (i32, i32, i32);
 
 #[derive(Debug, Clone, Encode, Decode)]
 enum BlockEntityDiff {
@@ -32,6 +35,57 @@ pub struct BlockEntitiesDiff {
 
 static ERR_MSG: &str = "Failed to parse 'block_entities' section";
 
+impl BlockEntitiesDiff {
+    /// Check that `bes` holds the block entities of the old side of this diff if `is_old`, or of
+    /// the new side otherwise.
+    pub fn check(&self, bes: &Value, is_old: bool) -> Result<(), DiffError> {
+        let bes_map = try_build_bes_map(bes)?;
+        let xyz_list = if is_old {
+            &self.old_xyz_list
+        } else {
+            &self.new_xyz_list
+        };
+        if bes_map.len() != xyz_list.len() || xyz_list.iter().any(|xyz| !bes_map.contains_key(xyz))
+        {
+            return Err(DiffError::Mismatch(
+                "block entity positions differ from the recorded ones".to_string(),
+            ));
+        }
+        for (xyz, diff) in &self.map {
+            if let BlockEntityDiff::UpdateSameBlockEntityID(diff) = diff {
+                let be = bes_map.get(xyz).ok_or_else(|| {
+                    DiffError::Mismatch(format!("block entity at {:?} is missing", xyz))
+                })?;
+                let be = ser(be);
+                match is_old {
+                    true => diff.check_old(&be)?,
+                    false => diff.check_new(&be)?,
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Block entities created, deleted or changed by this diff, in coordinate order.
+    pub fn describe_changes(&self) -> Vec<ChangeDescription> {
+        self.map
+            .iter()
+            .filter_map(|(xyz, diff)| match diff {
+                BlockEntityDiff::Create(_) => Some(ChangeDescription::BlockEntityCreated(*xyz)),
+                BlockEntityDiff::Delete(_) => Some(ChangeDescription::BlockEntityDeleted(*xyz)),
+                BlockEntityDiff::UpdateSameBlockEntityID(myers) if myers.replaces.is_empty() => {
+                    None
+                }
+                BlockEntityDiff::UpdateSameBlockEntityID(_) => {
+                    Some(ChangeDescription::BlockEntityUpdated(*xyz))
+                }
+                BlockEntityDiff::UpdateDiffBlockEntityID(_) => {
+                    Some(ChangeDescription::BlockEntityReplaced(*xyz))
+                }
+            })
+            .collect()
+    }
+}
+
 fn build_bes_id_map_and_xyz_list(bes: &Value) -> (BTreeMap<XYZ, (String, &Value)>, Vec<XYZ>) {
     match bes {
         Value::List(bes) => {
@@ -65,28 +119,37 @@ fn build_bes_id_map_and_xyz_list(bes: &Value) -> (BTreeMap<XYZ, (String, &Value)
         _ => panic!("'bes' should be Value::List"),
     }
 }
-fn build_bes_map(bes: &Value) -> BTreeMap<XYZ, Value> {
-    match bes {
-        Value::List(bes) => BTreeMap::from_iter(bes.iter().map(|be| match be {
-            Value::Compound(kv) => {
-                let x = match kv.get("x").expect(ERR_MSG) {
-                    Value::Int(i) => *i,
-                    _ => panic!("'be.x' should be Value::Int"),
-                };
-                let y = match kv.get("y").expect(ERR_MSG) {
-                    Value::Int(i) => *i,
-                    _ => panic!("'be.y' should be Value::Int"),
-                };
-                let z = match kv.get("z").expect(ERR_MSG) {
-                    Value::Int(i) => *i,
-                    _ => panic!("'be.z' should be Value::Int"),
-                };
-                ((x, y, z), be.clone())
-            }
-            _ => panic!("'be' should be Value::Compound"),
-        })),
-        _ => panic!("'bes' should be Value::List"),
+/// Block entities by their position. Fails if the list is malformed or two block entities
+/// share a position.
+fn try_build_bes_map(bes: &Value) -> Result<BTreeMap<XYZ, Value>, DiffError> {
+    let invalid = |msg: &str| DiffError::InvalidInput(msg.to_string());
+    let Value::List(bes) = bes else {
+        return Err(invalid("'bes' should be Value::List"));
+    };
+    let mut map = BTreeMap::new();
+    for be in bes {
+        let Value::Compound(kv) = be else {
+            return Err(invalid("'be' should be Value::Compound"));
+        };
+        let coord = |key| match kv.get(key) {
+            Some(Value::Int(i)) => Ok(*i),
+            _ => Err(DiffError::InvalidInput(format!(
+                "'be.{}' should be Value::Int",
+                key
+            ))),
+        };
+        let xyz = (coord("x")?, coord("y")?, coord("z")?);
+        if map.insert(xyz, be.clone()).is_some() {
+            return Err(DiffError::InvalidInput(format!(
+                "two block entities are at {:?}",
+                xyz
+            )));
+        }
     }
+    Ok(map)
+}
+fn build_bes_map(bes: &Value) -> BTreeMap<XYZ, Value> {
+    try_build_bes_map(bes).unwrap_or_else(|e| panic!("{}: {}", ERR_MSG, e))
 }
 fn build_bes_value(mut map: BTreeMap<XYZ, Value>, xyz_list: &Vec<XYZ>) -> Value {
     Value::List(Vec::from_iter(
@@ -107,7 +170,10 @@ impl Diff<Value> for BlockEntitiesDiff {
             let old = old_bes_map.get(xyz);
             let new = new_bes_map.get(xyz);
             let diff = match (old, new) {
-                (None, None) => panic!("Block not exists in both old and new block entities"),
+                (None, None) => panic!(
+                    "Block entity at {:?} not exists in both old and new block entities",
+                    xyz
+                ),
                 (None, Some((_, v))) => {
                     BlockEntityDiff::Create(BlobDiff::from_compare(&Vec::with_capacity(0), &ser(v)))
                 }
@@ -237,6 +303,9 @@ impl Diff<Value> for BlockEntitiesDiff {
             };
             squashed.map(|diff| (xyz.clone(), diff))
         }));
+        // The squashed diff spans base.old -> squashing.new, so the list orders are taken from
+        // those two ends. Block entities created and then deleted in between appear in neither
+        // list and are dropped from the map above.
         Self {
             old_xyz_list: base.old_xyz_list.clone(),
             new_xyz_list: squashing.new_xyz_list.clone(),
@@ -257,7 +326,10 @@ impl Diff<Value> for BlockEntitiesDiff {
                 (Some(_), BlockEntityDiff::UpdateDiffBlockEntityID(diff)) => {
                     Some(de(&diff.patch0()))
                 }
-                (old_be, diff) => panic!("Unmatching {:?} and {:?}", old_be, diff),
+                (old_be, diff) => panic!(
+                    "Unmatching {:?} and {:?} for block entity at {:?}",
+                    old_be, diff, xyz
+                ),
             };
             match new_be {
                 Some(be) => bes_map.insert(*xyz, be),
@@ -280,7 +352,10 @@ impl Diff<Value> for BlockEntitiesDiff {
                 (BlockEntityDiff::UpdateDiffBlockEntityID(diff), Some(_)) => {
                     Some(de(&diff.revert0()))
                 }
-                (diff, new_be) => panic!("Unmatching {:?} and {:?}", diff, new_be),
+                (diff, new_be) => panic!(
+                    "Unmatching {:?} and {:?} for block entity at {:?}",
+                    diff, new_be, xyz
+                ),
             };
             match old_be {
                 Some(be) => bes_map.insert(*xyz, be),
@@ -294,7 +369,7 @@ impl Diff<Value> for BlockEntitiesDiff {
 mod tests {
     use std::path::PathBuf;
 
-    use fastnbt::Value;
+    use fastnbt::{Value, nbt};
 
     use crate::{
         diff::Diff,
@@ -302,7 +377,8 @@ mod tests {
         util::{nbt_serde::de, test::get_test_chunk_by_xz},
     };
 
-    use super::BlockEntitiesDiff;
+    use super::{BlockEntitiesDiff, try_build_bes_map};
+    use crate::diff::DiffError;
 
     fn get_block_entities_from_chunk(chunk: ChunkWithTimestamp) -> Value {
         let nbt = match chunk.nbt {
@@ -319,6 +395,24 @@ mod tests {
         }
     }
     #[test]
+    fn test_malformed_block_entities() {
+        let be = nbt!({ "id": "minecraft:chest", "x": 1, "y": 2, "z": 3 });
+        let bes = Value::List(vec![be.clone()]);
+        assert_eq!(try_build_bes_map(&bes).unwrap().len(), 1);
+        for bes in [
+            Value::Int(0),
+            Value::List(vec![Value::Int(0)]),
+            Value::List(vec![nbt!({ "x": 1, "y": 2 })]),
+            Value::List(vec![nbt!({ "x": 1, "y": 2, "z": "3" })]),
+            Value::List(vec![be.clone(), be]),
+        ] {
+            assert!(matches!(
+                try_build_bes_map(&bes),
+                Err(DiffError::InvalidInput(_))
+            ));
+        }
+    }
+    #[test]
     fn test_diff_patch_revert() {
         let old_chunk = get_test_chunk_by_xz(
             &PathBuf::from("./resources/test-payload/region/mca/hairlessvillager-0/20250515.mca"),
@@ -364,4 +458,31 @@ mod tests {
         assert_eq!(patched_v0, v2);
         assert_eq!(reverted_v2, v0);
     }
+
+    #[test]
+    fn test_squash_order_matches_direct_diff() {
+        fn chest(x: i32) -> Value {
+            nbt!({
+                "id": "minecraft:chest",
+                "x": x,
+                "y": 64,
+                "z": 0,
+                "Items": [],
+            })
+        }
+        let (a, b, c) = (chest(0), chest(1), chest(2));
+        let v0 = Value::List(vec![a.clone(), b.clone()]);
+        let v1 = Value::List(vec![a.clone(), c.clone(), b.clone()]);
+        let v2 = Value::List(vec![b.clone(), a.clone()]);
+
+        let diff_v01 = BlockEntitiesDiff::from_compare(&v0, &v1);
+        let diff_v12 = BlockEntitiesDiff::from_compare(&v1, &v2);
+        let squashed_diff = BlockEntitiesDiff::from_squash(&diff_v01, &diff_v12);
+        let direct_diff = BlockEntitiesDiff::from_compare(&v0, &v2);
+
+        assert_eq!(squashed_diff.patch(&v0), direct_diff.patch(&v0));
+        assert_eq!(squashed_diff.revert(&v2), direct_diff.revert(&v2));
+        assert_eq!(squashed_diff.patch(&v0), v2);
+        assert_eq!(squashed_diff.revert(&v2), v0);
+    }
 }
@@ -1,20 +1,238 @@
 mod block_entites;
+mod sections;
+
+use std::{collections::BTreeMap, fmt};
 
 use bincode::{Decode, Encode};
 use fastnbt::Value;
 
 use crate::{
-    diff::{Diff, base::MyersDiff},
+    diff::{
+        Diff, DiffError, TryDiff,
+        base::{BlobDiff, MyersDiff},
+        chunk::subtree::{registered_differs, subtree_differ},
+    },
     util::nbt_serde::{de, ser},
 };
 
-use block_entites::BlockEntitiesDiff;
+use block_entites::{BlockEntitiesDiff, XYZ};
+use sections::SectionsDiff;
 
 #[derive(Debug, Encode, Decode, Clone)]
 pub struct RegionChunkDiff {
     block_entities: BlockEntitiesDiff,
-    sections: Vec<MyersDiff>,
+    sections: SectionsDiff,
     others: MyersDiff,
+    /// Encoded diffs of the tags with a `SubtreeDiffer` registered when comparing
+    custom: BTreeMap<String, Vec<u8>>,
+    /// Whole old and new chunks, recorded instead of diffing their tags when the chunks were
+    /// written by different game versions, as the tags may have moved or changed shape
+    whole: Option<BlobDiff>,
+}
+
+/// A change recorded in a `RegionChunkDiff`, as reported by `describe_changes`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeDescription {
+    BlockEntityCreated(XYZ),
+    BlockEntityDeleted(XYZ),
+    /// Same block entity ID with changed content, e.g. a chest gained an item
+    BlockEntityUpdated(XYZ),
+    /// Different block entity ID, e.g. a chest replaced by a furnace
+    BlockEntityReplaced(XYZ),
+    /// Section at height `Y` created
+    SectionCreated(i8),
+    /// Section at height `Y` deleted
+    SectionDeleted(i8),
+    /// Serialized section at height `y` had `removed` bytes replaced with `inserted` bytes
+    SectionChanged {
+        y: i8,
+        removed: usize,
+        inserted: usize,
+    },
+    /// Tags other than sections and block entities had `removed` bytes replaced with
+    /// `inserted` bytes
+    OthersChanged {
+        removed: usize,
+        inserted: usize,
+    },
+    /// Whole chunk replaced, as it was written by another game version
+    ChunkReplaced {
+        removed: usize,
+        inserted: usize,
+    },
+}
+
+impl fmt::Display for ChangeDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BlockEntityCreated((x, y, z)) => {
+                write!(f, "created block entity at ({}, {}, {})", x, y, z)
+            }
+            Self::BlockEntityDeleted((x, y, z)) => {
+                write!(f, "deleted block entity at ({}, {}, {})", x, y, z)
+            }
+            Self::BlockEntityUpdated((x, y, z)) => {
+                write!(f, "updated block entity at ({}, {}, {})", x, y, z)
+            }
+            Self::BlockEntityReplaced((x, y, z)) => {
+                write!(f, "replaced block entity at ({}, {}, {})", x, y, z)
+            }
+            Self::SectionCreated(y) => write!(f, "created section Y={}", y),
+            Self::SectionDeleted(y) => write!(f, "deleted section Y={}", y),
+            Self::SectionChanged {
+                y,
+                removed,
+                inserted,
+            } => write!(
+                f,
+                "changed section Y={}: -{} +{} bytes",
+                y, removed, inserted
+            ),
+            Self::OthersChanged { removed, inserted } => {
+                write!(f, "changed other tags: -{} +{} bytes", removed, inserted)
+            }
+            Self::ChunkReplaced { removed, inserted } => {
+                write!(f, "replaced whole chunk: -{} +{} bytes", removed, inserted)
+            }
+        }
+    }
+}
+
+impl RegionChunkDiff {
+    /// Summary of the changes in this diff: block entities first, then sections, then the
+    /// remaining tags. Unchanged parts are left out.
+    pub fn describe_changes(&self) -> Vec<ChangeDescription> {
+        if let Some(whole) = &self.whole {
+            return vec![ChangeDescription::ChunkReplaced {
+                removed: whole.get_old_text().len(),
+                inserted: whole.get_new_text().len(),
+            }];
+        }
+        let mut changes = self.block_entities.describe_changes();
+        changes.extend(self.sections.describe_changes());
+        if !self.others.replaces.is_empty() {
+            changes.push(ChangeDescription::OthersChanged {
+                removed: self.others.removed_len(),
+                inserted: self.others.new_text.len(),
+            });
+        }
+        changes
+    }
+    /// Number of sections this diff creates, deletes or changes.
+    pub fn changed_sections(&self) -> usize {
+        self.sections.describe_changes().len()
+    }
+    /// Numbers of block entities this diff creates, deletes, and updates or replaces.
+    pub fn block_entity_changes(&self) -> (usize, usize, usize) {
+        self.block_entities
+            .describe_changes()
+            .iter()
+            .fold((0, 0, 0), |(c, d, u), change| match change {
+                ChangeDescription::BlockEntityCreated(_) => (c + 1, d, u),
+                ChangeDescription::BlockEntityDeleted(_) => (c, d + 1, u),
+                _ => (c, d, u + 1),
+            })
+    }
+}
+
+/// Compact summary like `sections=3, be=+2/-1/~0`, counting changed sections and created,
+/// deleted and updated block entities.
+impl fmt::Display for RegionChunkDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (created, deleted, updated) = self.block_entity_changes();
+        write!(
+            f,
+            "sections={}, be=+{}/-{}/~{}",
+            self.changed_sections(),
+            created,
+            deleted,
+            updated
+        )
+    }
+}
+
+impl RegionChunkDiff {
+    /// Check that `chunk` is shaped like the old side of this diff if `is_old`, or like the new
+    /// side otherwise, so that patching or reverting it cannot fail.
+    fn check_chunk(&self, chunk: &Value, is_old: bool) -> Result<(), DiffError> {
+        if let Some(whole) = &self.whole {
+            let expected = match is_old {
+                true => whole.get_old_text(),
+                false => whole.get_new_text(),
+            };
+            return match &ser(chunk) == expected {
+                true => Ok(()),
+                false => Err(DiffError::Mismatch(
+                    "chunk differs from the one replaced by the diff".to_string(),
+                )),
+            };
+        }
+        let mut chunk = match chunk {
+            Value::Compound(x) => x.clone(),
+            _ => {
+                return Err(DiffError::InvalidInput(
+                    "chunk is not a compound".to_string(),
+                ));
+            }
+        };
+        let block_entities = chunk
+            .remove("block_entities")
+            .ok_or_else(|| DiffError::InvalidInput("block_entities is missing".to_string()))?;
+        self.block_entities.check(&block_entities, is_old)?;
+
+        let sections = chunk
+            .remove("sections")
+            .ok_or_else(|| DiffError::InvalidInput("sections is missing".to_string()))?;
+        self.sections.check(&sections, is_old)?;
+
+        for tag in self.custom.keys() {
+            chunk.remove(tag);
+        }
+        let others = ser(&Value::Compound(chunk));
+        match is_old {
+            true => self.others.check_old(&others),
+            false => self.others.check_new(&others),
+        }
+    }
+}
+
+/// The `DataVersion` of the game that wrote `chunk`, if it records one.
+fn data_version(chunk: &Value) -> Option<i32> {
+    match chunk {
+        Value::Compound(kv) => match kv.get("DataVersion") {
+            Some(Value::Int(version)) => Some(*version),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl RegionChunkDiff {
+    /// Diff replacing `old` with `new` as a whole.
+    fn from_whole(old: &Value, new: &Value) -> Self {
+        Self::from_whole_blob(BlobDiff::from_compare(&ser(old), &ser(new)))
+    }
+    fn from_whole_blob(whole: BlobDiff) -> Self {
+        let empty = Value::List(Vec::new());
+        Self {
+            block_entities: BlockEntitiesDiff::from_compare(&empty, &empty),
+            sections: SectionsDiff::from_compare(&empty, &empty),
+            others: MyersDiff::from_compare(&Vec::new(), &Vec::new()),
+            custom: BTreeMap::new(),
+            whole: Some(whole),
+        }
+    }
+}
+
+impl TryDiff<Value> for RegionChunkDiff {
+    fn try_patch(&self, old: &Value) -> Result<Value, DiffError> {
+        self.check_chunk(old, true)?;
+        Ok(self.patch(old))
+    }
+    fn try_revert(&self, new: &Value) -> Result<Value, DiffError> {
+        self.check_chunk(new, false)?;
+        Ok(self.revert(new))
+    }
 }
 
 static ERR_MSG_OLD: &str = "Invalid old nbt";
@@ -25,6 +243,15 @@ impl Diff<Value> for RegionChunkDiff {
     where
         Self: Sized,
     {
+        let (old_version, new_version) = (data_version(old), data_version(new));
+        if old_version != new_version {
+            log::debug!(
+                "chunk DataVersion changed from {:?} to {:?}, replacing it as a whole",
+                old_version,
+                new_version
+            );
+            return Self::from_whole(old, new);
+        }
         let mut old = match old {
             Value::Compound(x) => x.clone(),
             _ => panic!("{}", ERR_MSG_OLD),
@@ -45,25 +272,16 @@ impl Diff<Value> for RegionChunkDiff {
         let diff_sections;
         {
             let old_sections = old.remove("sections").expect(ERR_MSG_OLD);
-            let old_sections = match old_sections {
-                Value::List(x) => x,
-                _ => panic!("{}", ERR_MSG_OLD),
-            };
             let new_sections = new.remove("sections").expect(ERR_MSG_NEW);
-            let new_sections = match new_sections {
-                Value::List(x) => x,
-                _ => panic!("{}", ERR_MSG_NEW),
-            };
-            assert_eq!(old_sections.len(), new_sections.len());
-
-            let mut mut_diff_sections = Vec::with_capacity(old_sections.len());
-            for (old, new) in old_sections.iter().zip(new_sections.iter()) {
-                let old = ser(old);
-                let new = ser(new);
-                let diff = MyersDiff::from_compare(&old, &new);
-                mut_diff_sections.push(diff);
+            diff_sections = SectionsDiff::from_compare(&old_sections, &new_sections);
+        }
+
+        let mut diff_custom = BTreeMap::new();
+        for (tag, differ) in registered_differs() {
+            let (old_tag, new_tag) = (old.remove(&tag), new.remove(&tag));
+            if old_tag.is_some() || new_tag.is_some() {
+                diff_custom.insert(tag, differ.compare(old_tag.as_ref(), new_tag.as_ref()));
             }
-            diff_sections = mut_diff_sections;
         }
 
         let diff_others;
@@ -77,6 +295,8 @@ impl Diff<Value> for RegionChunkDiff {
             block_entities: diff_block_entities,
             sections: diff_sections,
             others: diff_others,
+            custom: diff_custom,
+            whole: None,
         }
     }
 
@@ -84,23 +304,51 @@ impl Diff<Value> for RegionChunkDiff {
     where
         Self: Sized,
     {
+        match (&base.whole, &squashing.whole) {
+            (None, None) => {}
+            (Some(base), Some(squashing)) => {
+                return Self::from_whole_blob(BlobDiff::from_squash(base, squashing));
+            }
+            (Some(whole), None) => {
+                let new = squashing.patch(&de(whole.get_new_text()));
+                return Self::from_whole_blob(BlobDiff::from_compare(
+                    whole.get_old_text(),
+                    &ser(&new),
+                ));
+            }
+            (None, Some(whole)) => {
+                let old = base.revert(&de(whole.get_old_text()));
+                return Self::from_whole_blob(BlobDiff::from_compare(
+                    &ser(&old),
+                    whole.get_new_text(),
+                ));
+            }
+        }
         let block_entities =
             BlockEntitiesDiff::from_squash(&base.block_entities, &squashing.block_entities);
-        let sections = base
-            .sections
-            .iter()
-            .zip(squashing.sections.iter())
-            .map(|(base, squashing)| MyersDiff::from_squash(base, squashing))
-            .collect();
+        let sections = SectionsDiff::from_squash(&base.sections, &squashing.sections);
         let others = MyersDiff::from_squash(&base.others, &squashing.others);
+        let mut custom = base.custom.clone();
+        for (tag, diff) in &squashing.custom {
+            let squashed = match base.custom.get(tag) {
+                Some(base) => subtree_differ(tag).squash(base, diff),
+                None => diff.clone(),
+            };
+            custom.insert(tag.clone(), squashed);
+        }
         Self {
             block_entities,
             sections,
             others,
+            custom,
+            whole: None,
         }
     }
 
     fn patch(&self, old: &Value) -> Value {
+        if let Some(whole) = &self.whole {
+            return de(whole.get_new_text());
+        }
         let mut old = match old {
             Value::Compound(x) => x.clone(),
             _ => panic!("{}", ERR_MSG_OLD),
@@ -112,25 +360,21 @@ impl Diff<Value> for RegionChunkDiff {
             block_entities = self.block_entities.patch(&old_block_entities);
         }
 
-        let sections: Vec<Value>;
+        let sections;
         {
             let old_sections = old.remove("sections").expect(ERR_MSG_OLD);
-            let old_sections = match old_sections {
-                Value::List(x) => x,
-                _ => panic!("{}", ERR_MSG_OLD),
-            };
-            sections = old_sections
-                .iter()
-                .zip(self.sections.iter())
-                .map(|(old, diff)| {
-                    let old = ser(old);
-                    let new = diff.patch(&old);
-                    let new = de(&new);
-                    new
-                })
-                .collect()
+            sections = self.sections.patch(&old_sections);
         }
 
+        let custom: Vec<_> = self
+            .custom
+            .iter()
+            .map(|(tag, diff)| {
+                let old_tag = old.remove(tag);
+                (tag, subtree_differ(tag).patch(diff, old_tag.as_ref()))
+            })
+            .collect();
+
         let mut others;
         {
             let old_others = ser(&Value::Compound(old));
@@ -142,13 +386,21 @@ impl Diff<Value> for RegionChunkDiff {
             }
         }
 
-        others.insert("sections".to_string(), Value::List(sections));
+        others.insert("sections".to_string(), sections);
         others.insert("block_entities".to_string(), block_entities);
+        for (tag, value) in custom {
+            if let Some(value) = value {
+                others.insert(tag.clone(), value);
+            }
+        }
 
         Value::Compound(others)
     }
 
     fn revert(&self, new: &Value) -> Value {
+        if let Some(whole) = &self.whole {
+            return de(whole.get_old_text());
+        }
         let mut new = match new {
             Value::Compound(x) => x.clone(),
             _ => panic!("{}", ERR_MSG_NEW),
@@ -160,24 +412,21 @@ impl Diff<Value> for RegionChunkDiff {
             block_entities = self.block_entities.revert(&new_block_entities);
         }
 
-        let sections: Vec<Value>;
+        let sections;
         {
             let new_sections = new.remove("sections").expect(ERR_MSG_NEW);
-            let new_sections = match new_sections {
-                Value::List(x) => x,
-                _ => panic!("{}", ERR_MSG_NEW),
-            };
-            sections = new_sections
-                .iter()
-                .zip(self.sections.iter())
-                .map(|(new_section, diff)| {
-                    let new_bytes = ser(new_section);
-                    let old_bytes = diff.revert(&new_bytes);
-                    de(&old_bytes)
-                })
-                .collect();
+            sections = self.sections.revert(&new_sections);
         }
 
+        let custom: Vec<_> = self
+            .custom
+            .iter()
+            .map(|(tag, diff)| {
+                let new_tag = new.remove(tag);
+                (tag, subtree_differ(tag).revert(diff, new_tag.as_ref()))
+            })
+            .collect();
+
         let mut others;
         {
             let new_others = ser(&Value::Compound(new));
@@ -189,17 +438,274 @@ impl Diff<Value> for RegionChunkDiff {
             };
         }
 
-        others.insert("sections".to_string(), Value::List(sections));
+        others.insert("sections".to_string(), sections);
         others.insert("block_entities".to_string(), block_entities);
+        for (tag, value) in custom {
+            if let Some(value) = value {
+                others.insert(tag.clone(), value);
+            }
+        }
 
         Value::Compound(others)
     }
 }
 #[cfg(test)]
 mod tests {
+    use fastnbt::nbt;
     use rand::prelude::*;
 
     use super::*;
+
+    #[test]
+    fn test_try_patch() {
+        use crate::mca::MCAReader;
+        use std::path::PathBuf;
+
+        let chunk_nbt = |path| {
+            MCAReader::from_file(&PathBuf::from(path), true)
+                .unwrap()
+                .get_chunk_value(25, 29)
+                .unwrap()
+                .expect("chunk (25, 29) should exist")
+        };
+        let old = chunk_nbt("./resources/test-payload/region/mca/hairlessvillager-0/20250515.mca");
+        let new = chunk_nbt("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca");
+        let diff = RegionChunkDiff::from_compare(&old, &new);
+        assert_eq!(diff.try_patch(&old).unwrap(), new);
+        assert_eq!(diff.try_revert(&new).unwrap(), old);
+        assert!(matches!(
+            diff.try_patch(&Value::Int(0)),
+            Err(DiffError::InvalidInput(_))
+        ));
+
+        let mut corrupted = diff.clone();
+        corrupted.sections = SectionsDiff::from_compare(&Value::List(vec![]), &Value::List(vec![]));
+        assert!(matches!(
+            corrupted.try_patch(&old),
+            Err(DiffError::Mismatch(_))
+        ));
+
+        let mut corrupted = diff.clone();
+        corrupted.others = MyersDiff::from_compare(&vec![1, 2, 3], &vec![4]);
+        assert!(matches!(
+            corrupted.try_patch(&old),
+            Err(DiffError::Mismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_sections_matched_by_y() {
+        let chunk = |sections: Vec<Value>| {
+            nbt!({
+                "Status": "minecraft:full",
+                "sections": sections,
+                "block_entities": [],
+            })
+        };
+        let section = |y: i8, biome: &str| {
+            nbt!({
+                "Y": y,
+                "biomes": { "palette": [biome] },
+            })
+        };
+        let v0 = chunk(vec![
+            section(0, "minecraft:plains"),
+            section(2, "minecraft:plains"),
+        ]);
+        // a section inserted in the middle
+        let v1 = chunk(vec![
+            section(0, "minecraft:plains"),
+            section(1, "minecraft:forest"),
+            section(2, "minecraft:plains"),
+        ]);
+        let v2 = chunk(vec![
+            section(0, "minecraft:plains"),
+            section(1, "minecraft:desert"),
+        ]);
+
+        let diff_v01 = RegionChunkDiff::from_compare(&v0, &v1);
+        assert_eq!(
+            diff_v01.describe_changes(),
+            vec![ChangeDescription::SectionCreated(1)]
+        );
+        assert_eq!(diff_v01.patch(&v0), v1);
+        assert_eq!(diff_v01.revert(&v1), v0);
+
+        let diff_v12 = RegionChunkDiff::from_compare(&v1, &v2);
+        assert!(matches!(
+            diff_v12.describe_changes()[..],
+            [
+                ChangeDescription::SectionChanged { y: 1, .. },
+                ChangeDescription::SectionDeleted(2)
+            ]
+        ));
+
+        let squashed = RegionChunkDiff::from_squash(&diff_v01, &diff_v12);
+        assert!(matches!(
+            squashed.describe_changes()[..],
+            [
+                ChangeDescription::SectionCreated(1),
+                ChangeDescription::SectionDeleted(2)
+            ]
+        ));
+        assert_eq!(squashed.patch(&v0), v2);
+        assert_eq!(squashed.revert(&v2), v0);
+    }
+
+    #[test]
+    fn test_data_version_changed() {
+        // a chunk of 1.17, before sections and block entities moved out of `Level`
+        let v0 = nbt!({
+            "DataVersion": 2730,
+            "Level": {
+                "Status": "full",
+                "Sections": [{ "Y": 0_i8 }],
+                "TileEntities": [],
+            },
+        });
+        let v1 = nbt!({
+            "DataVersion": 4189,
+            "Status": "minecraft:full",
+            "sections": [{ "Y": 0_i8 }],
+            "block_entities": [],
+        });
+        let v2 = nbt!({
+            "DataVersion": 4189,
+            "Status": "minecraft:full",
+            "sections": [{ "Y": 0_i8 }, { "Y": 1_i8 }],
+            "block_entities": [],
+        });
+
+        let diff_v01 = RegionChunkDiff::from_compare(&v0, &v1);
+        assert!(matches!(
+            diff_v01.describe_changes()[..],
+            [ChangeDescription::ChunkReplaced { .. }]
+        ));
+        assert_eq!(diff_v01.try_patch(&v0).unwrap(), v1);
+        assert_eq!(diff_v01.try_revert(&v1).unwrap(), v0);
+        assert!(matches!(
+            diff_v01.try_patch(&v1),
+            Err(DiffError::Mismatch(_))
+        ));
+
+        let diff_v12 = RegionChunkDiff::from_compare(&v1, &v2);
+        assert_eq!(
+            diff_v12.describe_changes(),
+            vec![ChangeDescription::SectionCreated(1)]
+        );
+        let squashed = RegionChunkDiff::from_squash(&diff_v01, &diff_v12);
+        assert_eq!(squashed.patch(&v0), v2);
+        assert_eq!(squashed.revert(&v2), v0);
+    }
+
+    #[test]
+    fn test_subtree_differ() {
+        use std::sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        };
+
+        use crate::{
+            diff::chunk::{SubtreeDiffer, register_subtree_differ},
+            util::{nbt_serde, serde},
+        };
+
+        /// Stores both sides of the tag, counting the comparisons
+        struct ReplaceDiffer(AtomicUsize);
+        type Sides = (Option<Vec<u8>>, Option<Vec<u8>>);
+        impl SubtreeDiffer for ReplaceDiffer {
+            fn compare(&self, old: Option<&Value>, new: Option<&Value>) -> Vec<u8> {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                serde::ser((old.map(nbt_serde::ser), new.map(nbt_serde::ser)))
+            }
+            fn squash(&self, base: &[u8], squashing: &[u8]) -> Vec<u8> {
+                let (old, _): Sides = serde::try_de(base).unwrap();
+                let (_, new): Sides = serde::try_de(squashing).unwrap();
+                serde::ser((old, new))
+            }
+            fn patch(&self, diff: &[u8], _: Option<&Value>) -> Option<Value> {
+                let (_, new): Sides = serde::try_de(diff).unwrap();
+                new.map(|new| nbt_serde::de(&new))
+            }
+            fn revert(&self, diff: &[u8], _: Option<&Value>) -> Option<Value> {
+                let (old, _): Sides = serde::try_de(diff).unwrap();
+                old.map(|old| nbt_serde::de(&old))
+            }
+        }
+
+        let differ = Arc::new(ReplaceDiffer(AtomicUsize::new(0)));
+        register_subtree_differ("test_subtree_differ", differ.clone());
+        let old = nbt!({
+            "Status": "minecraft:full",
+            "sections": [],
+            "block_entities": [],
+            "test_subtree_differ": { "a": 1 },
+        });
+        let new = nbt!({
+            "Status": "minecraft:full",
+            "sections": [],
+            "block_entities": [],
+            "test_subtree_differ": { "a": 2 },
+        });
+        let diff = RegionChunkDiff::from_compare(&old, &new);
+        assert_eq!(differ.0.load(Ordering::Relaxed), 1);
+        assert!(diff.custom.contains_key("test_subtree_differ"));
+        assert!(diff.others.replaces.is_empty());
+        assert_eq!(diff.patch(&old), new);
+        assert_eq!(diff.revert(&new), old);
+    }
+
+    #[test]
+    fn test_describe_changes() {
+        let old = nbt!({
+            "Status": "minecraft:full",
+            "sections": [],
+            "block_entities": [
+                {
+                    "id": "minecraft:chest",
+                    "x": 3,
+                    "y": 64,
+                    "z": -7,
+                    "Items": []
+                }
+            ]
+        });
+        let new = nbt!({
+            "Status": "minecraft:full",
+            "sections": [],
+            "block_entities": [
+                {
+                    "id": "minecraft:chest",
+                    "x": 3,
+                    "y": 64,
+                    "z": -7,
+                    "Items": [
+                        {
+                            "Slot": 0_i8,
+                            "id": "minecraft:diamond",
+                            "count": 1
+                        }
+                    ]
+                }
+            ]
+        });
+        let diff = RegionChunkDiff::from_compare(&old, &new);
+        let changes = diff.describe_changes();
+        assert_eq!(
+            changes,
+            vec![ChangeDescription::BlockEntityUpdated((3, 64, -7))]
+        );
+        assert_eq!(
+            changes[0].to_string(),
+            "updated block entity at (3, 64, -7)"
+        );
+
+        assert!(
+            RegionChunkDiff::from_compare(&old, &old)
+                .describe_changes()
+                .is_empty()
+        );
+    }
     mod test_in_continuous_data {
         use std::path::PathBuf;
 
@@ -0,0 +1,266 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use bincode::{Decode, Encode};
+use fastnbt::Value;
+
+use crate::{
+    diff::{
+        Diff, DiffError,
+        base::{BlobDiff, MyersDiff},
+    },
+    util::nbt_serde::{de, ser},
+};
+
+use super::ChangeDescription;
+
+#[derive(Debug, Clone, Encode, Decode)]
+enum SectionDiff {
+    Create(BlobDiff),
+    Delete(BlobDiff),
+    Update(MyersDiff),
+}
+
+/// Diff of the `sections` list, matching sections by their `Y` index rather than by position so
+/// that a section added or removed when the height changes doesn't shift the others.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct SectionsDiff {
+    old_y_list: Vec<i8>,
+    new_y_list: Vec<i8>,
+    map: BTreeMap<i8, SectionDiff>,
+}
+
+static ERR_MSG: &str = "Failed to parse 'sections'";
+
+thread_local! {
+    // scratch buffer for patching/reverting sections, reused across chunks on a worker thread
+    static SECTION_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Sections by their `Y` index, and the indices in list order. Fails if the list is malformed
+/// or two sections share an index, which would make one of them unreachable.
+fn try_build_sections_map_and_y_list(
+    sections: &Value,
+) -> Result<(BTreeMap<i8, &Value>, Vec<i8>), DiffError> {
+    let invalid = |msg: &str| DiffError::InvalidInput(msg.to_string());
+    let Value::List(sections) = sections else {
+        return Err(invalid("'sections' should be Value::List"));
+    };
+    let mut map = BTreeMap::new();
+    let mut y_list = Vec::with_capacity(sections.len());
+    for section in sections {
+        let y = match section {
+            Value::Compound(kv) => match kv.get("Y") {
+                Some(Value::Byte(y)) => *y,
+                Some(_) => return Err(invalid("'section.Y' should be Value::Byte")),
+                None => return Err(invalid("'section' has no 'Y'")),
+            },
+            _ => return Err(invalid("'section' should be Value::Compound")),
+        };
+        if map.insert(y, section).is_some() {
+            return Err(DiffError::InvalidInput(format!(
+                "two sections have Y={}",
+                y
+            )));
+        }
+        y_list.push(y);
+    }
+    Ok((map, y_list))
+}
+fn build_sections_map_and_y_list(sections: &Value) -> (BTreeMap<i8, &Value>, Vec<i8>) {
+    try_build_sections_map_and_y_list(sections).unwrap_or_else(|e| panic!("{}: {}", ERR_MSG, e))
+}
+fn build_sections_value(mut map: BTreeMap<i8, Value>, y_list: &[i8]) -> Value {
+    Value::List(Vec::from_iter(
+        y_list.iter().map(|y| map.remove(y).expect(ERR_MSG)),
+    ))
+}
+
+impl SectionsDiff {
+    /// Check that `sections` holds the sections of the old side of this diff if `is_old`, or of
+    /// the new side otherwise.
+    pub fn check(&self, sections: &Value, is_old: bool) -> Result<(), DiffError> {
+        let (map, y_list) = try_build_sections_map_and_y_list(sections)?;
+        let expected = match is_old {
+            true => &self.old_y_list,
+            false => &self.new_y_list,
+        };
+        if &y_list != expected {
+            return Err(DiffError::Mismatch(format!(
+                "chunk has sections {:?}, the diff has {:?}",
+                y_list, expected
+            )));
+        }
+        for (y, diff) in &self.map {
+            if let SectionDiff::Update(diff) = diff {
+                let section = map
+                    .get(y)
+                    .ok_or_else(|| DiffError::Mismatch(format!("section Y={} is missing", y)))?;
+                let section = ser(section);
+                match is_old {
+                    true => diff.check_old(&section)?,
+                    false => diff.check_new(&section)?,
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Sections created, deleted or changed by this diff, from the bottom up.
+    pub fn describe_changes(&self) -> Vec<ChangeDescription> {
+        self.map
+            .iter()
+            .filter_map(|(y, diff)| match diff {
+                SectionDiff::Create(_) => Some(ChangeDescription::SectionCreated(*y)),
+                SectionDiff::Delete(_) => Some(ChangeDescription::SectionDeleted(*y)),
+                SectionDiff::Update(myers) if myers.replaces.is_empty() => None,
+                SectionDiff::Update(myers) => Some(ChangeDescription::SectionChanged {
+                    y: *y,
+                    removed: myers.removed_len(),
+                    inserted: myers.new_text.len(),
+                }),
+            })
+            .collect()
+    }
+}
+
+impl Diff<Value> for SectionsDiff {
+    fn from_compare(old: &Value, new: &Value) -> Self {
+        let (old_map, old_y_list) = build_sections_map_and_y_list(old);
+        let (new_map, new_y_list) = build_sections_map_and_y_list(new);
+        let ys = BTreeSet::from_iter(old_map.keys().chain(new_map.keys()));
+        let map = BTreeMap::from_iter(ys.into_iter().map(|y| {
+            let diff = match (old_map.get(y), new_map.get(y)) {
+                (None, None) => unreachable!(),
+                (None, Some(new)) => SectionDiff::Create(BlobDiff::from_create(&ser(new))),
+                (Some(old), None) => SectionDiff::Delete(BlobDiff::from_delete(&ser(old))),
+                (Some(old), Some(new)) => {
+                    SectionDiff::Update(MyersDiff::from_compare(&ser(old), &ser(new)))
+                }
+            };
+            (*y, diff)
+        }));
+        Self {
+            old_y_list,
+            new_y_list,
+            map,
+        }
+    }
+
+    fn from_squash(base: &Self, squashing: &Self) -> Self {
+        let ys = BTreeSet::from_iter(base.map.keys().chain(squashing.map.keys()));
+        let map = BTreeMap::from_iter(ys.into_iter().filter_map(|y| {
+            let squashed = match (base.map.get(y), squashing.map.get(y)) {
+                (None, None) => unreachable!(),
+                (None, Some(diff)) | (Some(diff), None) => Some(diff.clone()),
+                (Some(SectionDiff::Create(_)), Some(SectionDiff::Delete(_))) => None,
+                (Some(SectionDiff::Delete(base)), Some(SectionDiff::Create(squashing))) => {
+                    Some(SectionDiff::Update(MyersDiff::from_compare(
+                        base.get_old_text(),
+                        squashing.get_new_text(),
+                    )))
+                }
+                (Some(SectionDiff::Create(blob)), Some(SectionDiff::Update(myers))) => Some(
+                    SectionDiff::Create(BlobDiff::from_create(&myers.patch(blob.get_new_text()))),
+                ),
+                (Some(SectionDiff::Update(myers)), Some(SectionDiff::Delete(blob))) => Some(
+                    SectionDiff::Delete(BlobDiff::from_delete(&myers.revert(blob.get_old_text()))),
+                ),
+                (Some(SectionDiff::Update(base)), Some(SectionDiff::Update(squashing))) => {
+                    Some(SectionDiff::Update(MyersDiff::from_squash(base, squashing)))
+                }
+                (Some(base), Some(squashing)) => panic!(
+                    "Mismatched base diff {:?} and squashing diff {:?} for section Y={}",
+                    base, squashing, y
+                ),
+            };
+            squashed.map(|diff| (*y, diff))
+        }));
+        // the squashed diff spans base.old -> squashing.new, and sections created and then
+        // deleted in between are dropped from the map above
+        Self {
+            old_y_list: base.old_y_list.clone(),
+            new_y_list: squashing.new_y_list.clone(),
+            map,
+        }
+    }
+
+    fn patch(&self, old: &Value) -> Value {
+        let (old_map, _) = build_sections_map_and_y_list(old);
+        let mut map: BTreeMap<i8, Value> =
+            BTreeMap::from_iter(old_map.into_iter().map(|(y, v)| (y, v.clone())));
+        for (y, diff) in &self.map {
+            let new_section = match (map.get(y), diff) {
+                (None, SectionDiff::Create(diff)) => Some(de(&diff.patch0())),
+                (Some(_), SectionDiff::Delete(_)) => None,
+                (Some(old), SectionDiff::Update(diff)) => {
+                    Some(SECTION_BUFFER.with_borrow_mut(|new| {
+                        diff.patch_into(&ser(old), new);
+                        de(new)
+                    }))
+                }
+                (old, diff) => panic!("Unmatching {:?} and {:?} for section Y={}", old, diff, y),
+            };
+            match new_section {
+                Some(section) => map.insert(*y, section),
+                None => map.remove(y),
+            };
+        }
+        build_sections_value(map, &self.new_y_list)
+    }
+
+    fn revert(&self, new: &Value) -> Value {
+        let (new_map, _) = build_sections_map_and_y_list(new);
+        let mut map: BTreeMap<i8, Value> =
+            BTreeMap::from_iter(new_map.into_iter().map(|(y, v)| (y, v.clone())));
+        for (y, diff) in &self.map {
+            let old_section = match (diff, map.get(y)) {
+                (SectionDiff::Create(_), Some(_)) => None,
+                (SectionDiff::Delete(diff), None) => Some(de(&diff.revert0())),
+                (SectionDiff::Update(diff), Some(new)) => {
+                    Some(SECTION_BUFFER.with_borrow_mut(|old| {
+                        diff.revert_into(&ser(new), old);
+                        de(old)
+                    }))
+                }
+                (diff, new) => panic!("Unmatching {:?} and {:?} for section Y={}", diff, new, y),
+            };
+            match old_section {
+                Some(section) => map.insert(*y, section),
+                None => map.remove(y),
+            };
+        }
+        build_sections_value(map, &self.old_y_list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(y: Value) -> Value {
+        Value::Compound(BTreeMap::from([("Y".to_string(), y)]))
+    }
+
+    #[test]
+    fn test_try_build_sections_map() {
+        let sections = Value::List(vec![section(Value::Byte(-1)), section(Value::Byte(0))]);
+        let (map, y_list) = try_build_sections_map_and_y_list(&sections).unwrap();
+        assert_eq!(y_list, vec![-1, 0]);
+        assert_eq!(map.len(), 2);
+
+        for malformed in [
+            Value::Int(0),
+            Value::List(vec![Value::Int(0)]),
+            Value::List(vec![section(Value::Int(0))]),
+            Value::List(vec![Value::Compound(BTreeMap::new())]),
+            Value::List(vec![section(Value::Byte(3)), section(Value::Byte(3))]),
+        ] {
+            assert!(matches!(
+                try_build_sections_map_and_y_list(&malformed),
+                Err(DiffError::InvalidInput(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use fastnbt::Value;
+
+/// Differ for one top-level tag of region chunks, used by `RegionChunkDiff` in place of diffing
+/// the tag along with the other tags once registered with `register_subtree_differ`.
+///
+/// Diffs are passed around encoded, so that a `RegionChunkDiff` can store them without knowing
+/// their type. The tag is `None` on the side of a chunk that lacks it.
+pub trait SubtreeDiffer: Send + Sync {
+    fn compare(&self, old: Option<&Value>, new: Option<&Value>) -> Vec<u8>;
+    fn squash(&self, base: &[u8], squashing: &[u8]) -> Vec<u8>;
+    fn patch(&self, diff: &[u8], old: Option<&Value>) -> Option<Value>;
+    fn revert(&self, diff: &[u8], new: Option<&Value>) -> Option<Value>;
+}
+
+/// Tags with a differ of their own in `RegionChunkDiff`, which can't be replaced.
+const BUILTIN_TAGS: [&str; 2] = ["sections", "block_entities"];
+
+static DIFFERS: RwLock<BTreeMap<String, Arc<dyn SubtreeDiffer>>> = RwLock::new(BTreeMap::new());
+
+/// Diff the top-level `tag` of region chunks with `differ` from now on, replacing the differ
+/// registered for it before.
+///
+/// Patching, reverting and squashing a diff need the differs of the tags it was computed with,
+/// so the same differs have to be registered wherever its diff files are used.
+pub fn register_subtree_differ(tag: &str, differ: Arc<dyn SubtreeDiffer>) {
+    assert!(
+        !BUILTIN_TAGS.contains(&tag),
+        "'{}' is always diffed by the built-in differ",
+        tag
+    );
+    DIFFERS
+        .write()
+        .expect("A thread panicked while registering a differ")
+        .insert(tag.to_string(), differ);
+}
+
+pub(super) fn registered_differs() -> Vec<(String, Arc<dyn SubtreeDiffer>)> {
+    DIFFERS
+        .read()
+        .expect("A thread panicked while registering a differ")
+        .iter()
+        .map(|(tag, differ)| (tag.clone(), differ.clone()))
+        .collect()
+}
+
+pub(super) fn subtree_differ(tag: &str) -> Arc<dyn SubtreeDiffer> {
+    DIFFERS
+        .read()
+        .expect("A thread panicked while registering a differ")
+        .get(tag)
+        .unwrap_or_else(|| panic!("No differ registered for tag '{}'", tag))
+        .clone()
+}
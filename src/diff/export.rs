@@ -0,0 +1,240 @@
+//! Portable binary delta format for whole-file diffs.
+//!
+//! The format does not depend on this crate's bincode schema, so other tools can apply it.
+//! All integers are big-endian `u64`:
+//!
+//! ```text
+//! magic          8 bytes  b"RDIFFBSD"
+//! version        1 byte   1
+//! old_len        u64      length of the old file
+//! new_len        u64      length of the new file
+//! control_count  u64      number of control entries
+//! removed_len    u64      length of the removed stream
+//! extra_len      u64      length of the extra stream
+//! control        control_count * (copy: u64, remove: u64, insert: u64)
+//! removed        removed_len bytes
+//! extra          extra_len bytes
+//! ```
+//!
+//! To patch, for each control entry copy `copy` bytes from old, skip `remove` bytes of old and
+//! append the next `insert` bytes of the extra stream, then copy the rest of old. The skipped old
+//! bytes are stored in order in the removed stream, which makes the delta revertible as well.
+
+use thiserror::Error;
+
+use crate::diff::{
+    Diff,
+    base::{MyersDiff, Replace},
+};
+
+pub const EXPORT_MAGIC: &[u8; 8] = b"RDIFFBSD";
+pub const EXPORT_VERSION: u8 = 1;
+
+const HEADER_SIZE: usize = EXPORT_MAGIC.len() + 1 + 8 * 5;
+const CONTROL_SIZE: usize = 8 * 3;
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("Not a portable delta")]
+    BadMagic,
+    #[error("Unsupported portable delta version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Portable delta is truncated")]
+    Truncated,
+    #[error("Portable delta is inconsistent: {0}")]
+    Inconsistent(&'static str),
+}
+
+/// Encode `diff` in the portable delta format. `old_len` is the length of the file it patches.
+/// Panics if `diff` is patch-only, as the delta records the removed bytes.
+pub fn export(diff: &MyersDiff, old_len: usize) -> Vec<u8> {
+    assert!(!diff.patch_only, "Cannot export a patch-only MyersDiff");
+    let new_len = old_len - diff.old_text.len() + diff.new_text.len();
+    let mut out = Vec::with_capacity(
+        HEADER_SIZE
+            + diff.replaces.len() * CONTROL_SIZE
+            + diff.old_text.len()
+            + diff.new_text.len(),
+    );
+    out.extend_from_slice(EXPORT_MAGIC);
+    out.push(EXPORT_VERSION);
+    for len in [
+        old_len,
+        new_len,
+        diff.replaces.len(),
+        diff.old_text.len(),
+        diff.new_text.len(),
+    ] {
+        out.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    let mut old_ptr = 0;
+    for replace in &diff.replaces {
+        for len in [replace.old_idx - old_ptr, replace.old_len, replace.new_len] {
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+        old_ptr = replace.old_idx + replace.old_len;
+    }
+    out.extend_from_slice(&diff.old_text);
+    out.extend_from_slice(&diff.new_text);
+    out
+}
+
+/// Encode the byte-level change `diff` makes to `old` in the portable delta format.
+pub fn export_whole_file<D: Diff<Vec<u8>>>(diff: &D, old: &Vec<u8>) -> Vec<u8> {
+    let new = diff.patch(old);
+    export(&MyersDiff::from_compare(old, &new), old.len())
+}
+
+/// Decode a portable delta back into a `MyersDiff`.
+pub fn import(data: &[u8]) -> Result<MyersDiff, ExportError> {
+    if data.len() < HEADER_SIZE {
+        return Err(ExportError::Truncated);
+    }
+    if !data.starts_with(EXPORT_MAGIC) {
+        return Err(ExportError::BadMagic);
+    }
+    let version = data[EXPORT_MAGIC.len()];
+    if version != EXPORT_VERSION {
+        return Err(ExportError::UnsupportedVersion(version));
+    }
+    let read_u64 = |pos: usize| -> usize {
+        u64::from_be_bytes(data[pos..pos + 8].try_into().unwrap()) as usize
+    };
+    let fields_pos = EXPORT_MAGIC.len() + 1;
+    let [old_len, new_len, control_count, removed_len, extra_len] =
+        std::array::from_fn(|i| read_u64(fields_pos + i * 8));
+
+    let removed_pos = control_count
+        .checked_mul(CONTROL_SIZE)
+        .and_then(|len| len.checked_add(HEADER_SIZE))
+        .ok_or(ExportError::Truncated)?;
+    let extra_pos = removed_pos
+        .checked_add(removed_len)
+        .ok_or(ExportError::Truncated)?;
+    if extra_pos.checked_add(extra_len) != Some(data.len()) {
+        return Err(ExportError::Truncated);
+    }
+
+    let mut replaces = Vec::with_capacity(control_count);
+    let mut old_ptr: usize = 0;
+    let mut new_ptr: usize = 0;
+    let mut removed_sum: usize = 0;
+    let mut extra_sum: usize = 0;
+    let add = |a: usize, b: usize| {
+        a.checked_add(b)
+            .ok_or(ExportError::Inconsistent("control lengths overflow"))
+    };
+    for i in 0..control_count {
+        let pos = HEADER_SIZE + i * CONTROL_SIZE;
+        let (copy, remove, insert) = (read_u64(pos), read_u64(pos + 8), read_u64(pos + 16));
+        let replace = Replace {
+            old_idx: add(old_ptr, copy)?,
+            old_len: remove,
+            new_idx: add(new_ptr, copy)?,
+            new_len: insert,
+        };
+        old_ptr = add(replace.old_idx, remove)?;
+        new_ptr = add(replace.new_idx, insert)?;
+        removed_sum = add(removed_sum, remove)?;
+        extra_sum = add(extra_sum, insert)?;
+        replaces.push(replace);
+    }
+    if removed_sum != removed_len || extra_sum != extra_len {
+        return Err(ExportError::Inconsistent(
+            "control lengths do not match stream lengths",
+        ));
+    }
+    if old_ptr > old_len || Some(new_len) != add(old_len - removed_len, extra_len).ok() {
+        return Err(ExportError::Inconsistent(
+            "control lengths do not match file lengths",
+        ));
+    }
+
+    Ok(MyersDiff {
+        old_text: data[removed_pos..extra_pos].to_vec(),
+        new_text: data[extra_pos..].to_vec(),
+        replaces,
+        patch_only: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{
+        compress::CompressionType,
+        config::{Config, with_test_config},
+        diff::{chunk::RegionChunkDiff, file::MCADiff},
+        mca::MCABuilder,
+        util::test::{create_test_bytes, get_test_chunk_by_xz},
+    };
+
+    fn test_config() -> Config {
+        Config {
+            compression_type: CompressionType::No,
+            ..Config::for_test()
+        }
+    }
+
+    #[test]
+    fn test_export_import_myers() {
+        let mut iter = create_test_bytes(114514);
+        for _ in 0..1000 {
+            let old = iter.next().unwrap();
+            let new = iter.next().unwrap();
+            let diff = MyersDiff::from_compare(&old, &new);
+            let imported = import(&export(&diff, old.len())).unwrap();
+            assert_eq!(imported, diff);
+        }
+    }
+    #[test]
+    fn test_import_rejects_invalid() {
+        let diff = MyersDiff::from_compare(&vec![1, 2, 3], &vec![3, 2, 1, 2]);
+        let exported = export(&diff, 3);
+        assert!(matches!(
+            import(&exported[..exported.len() - 1]),
+            Err(ExportError::Truncated)
+        ));
+        let mut bad_magic = exported.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(import(&bad_magic), Err(ExportError::BadMagic)));
+        let mut bad_version = exported.clone();
+        bad_version[EXPORT_MAGIC.len()] = EXPORT_VERSION + 1;
+        assert!(matches!(
+            import(&bad_version),
+            Err(ExportError::UnsupportedVersion(_))
+        ));
+        let mut overflowing = exported.clone();
+        overflowing[HEADER_SIZE..HEADER_SIZE + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(matches!(
+            import(&overflowing),
+            Err(ExportError::Inconsistent(_))
+        ));
+    }
+    #[test]
+    fn test_export_mca_diff() {
+        with_test_config(test_config(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let chunk_old =
+                get_test_chunk_by_xz(&PathBuf::from(format!("{}/20250515.mca", dir)), 25, 29)
+                    .unwrap();
+            let chunk_new =
+                get_test_chunk_by_xz(&PathBuf::from(format!("{}/20250516.mca", dir)), 25, 29)
+                    .unwrap();
+            let mut builder = MCABuilder::new();
+            builder.set_chunk(25, 29, &chunk_old);
+            let old = builder.to_bytes(CompressionType::No).unwrap();
+            let mut builder = MCABuilder::new();
+            builder.set_chunk(25, 29, &chunk_new);
+            let new = builder.to_bytes(CompressionType::No).unwrap();
+
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&old, &new);
+            let patched = diff.patch(&old);
+            let imported = import(&export_whole_file(&diff, &old)).unwrap();
+            assert_eq!(imported.patch(&old), patched);
+            assert_eq!(imported.revert(&patched), old);
+        });
+    }
+}
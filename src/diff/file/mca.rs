@@ -1,15 +1,30 @@
-use crate::compress::CompressionType;
+use crate::config::get_config;
 use crate::mca::ChunkNbt;
 use crate::util::parallel::{parallel_process, parallel_process_with_cost_estimator};
-use crate::util::{IXZ, create_chunk_ixz_iter};
+use crate::util::{CHUNKS_PER_REGION, IXZ, REGION_DIM, create_chunk_ixz_iter};
 use crate::{
-    diff::{Diff, base::BlobDiff},
-    mca::{ChunkWithTimestamp, LazyChunk, MCABuilder, MCAReader},
-    util::nbt_serde::{de, ser},
+    diff::{
+        Diff, DiffError, TrySquash,
+        base::BlobDiff,
+        chunk::{EntitiesChunkDiff, PoiChunkDiff, RegionChunkDiff},
+    },
+    mca::{ChunkWithTimestamp, LazyChunk, MCABuilder, MCAError, MCAReader, SECTOR_SIZE},
+    util::nbt_serde::{copy_tags, de, ser, try_de},
+};
+use bincode::{
+    Decode, Encode,
+    de::Decoder,
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
 };
-use bincode::{Decode, Encode};
 use fastnbt::Value;
 use log::{Level, log_enabled};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Seek};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -28,28 +43,67 @@ where
     /// NotExists -> NotExists
     BothNotExist,
     /// NotExists -> Small
-    CreateSmall(i32, BlobDiff),
+    CreateSmall(i64, BlobDiff),
     /// Small -> NotExists
-    DeleteSmall(i32, BlobDiff),
+    DeleteSmall(i64, BlobDiff),
     /// Small -> Small with changed timestamp
-    UpdateSmall(i32, D),
+    UpdateSmall(i64, D),
     /// NotExists -> Large
-    CreateLarge(i32),
+    CreateLarge(i64),
     /// Large -> NotExists
-    DeleteLarge(i32),
+    DeleteLarge(i64),
     /// Large -> Large with changed timestamp
-    UpdateLarge(i32),
+    UpdateLarge(i64),
     /// Small -> Large
-    SmallToLarge(i32, BlobDiff),
+    SmallToLarge(i64, BlobDiff),
     /// Large -> Small
-    LargeToSmall(i32, BlobDiff),
+    LargeToSmall(i64, BlobDiff),
     /// Small -> Small or Large -> Large with same timestamp
     UpdateWithNoChange,
+    /// Small -> Small with the same nbt bytes and changed timestamp
+    TimestampOnly(i64),
+    /// Small -> Small with changed timestamp, stored as raw bytes since either nbt can't be
+    /// decoded
+    RawSmall(i64, BlobDiff),
 }
 impl<D> ChunkWithTimestampDiff<D>
 where
     D: Diff<Value>,
 {
+    /// Whether patching leaves the chunk as it is.
+    fn is_unchanged(&self) -> bool {
+        matches!(
+            self,
+            ChunkWithTimestampDiff::BothNotExist | ChunkWithTimestampDiff::UpdateWithNoChange
+        )
+    }
+    /// Whether patching and reverting keep the stored data of the chunk as it is, if only its
+    /// timestamp.
+    fn keeps_nbt(&self) -> bool {
+        matches!(
+            self,
+            ChunkWithTimestampDiff::UpdateWithNoChange
+                | ChunkWithTimestampDiff::TimestampOnly(..)
+                | ChunkWithTimestampDiff::UpdateLarge(..)
+        )
+    }
+    /// Name of the variant, e.g. `UpdateSmall`.
+    fn kind(&self) -> &'static str {
+        match self {
+            ChunkWithTimestampDiff::BothNotExist => "BothNotExist",
+            ChunkWithTimestampDiff::CreateSmall(..) => "CreateSmall",
+            ChunkWithTimestampDiff::DeleteSmall(..) => "DeleteSmall",
+            ChunkWithTimestampDiff::UpdateSmall(..) => "UpdateSmall",
+            ChunkWithTimestampDiff::CreateLarge(_) => "CreateLarge",
+            ChunkWithTimestampDiff::DeleteLarge(_) => "DeleteLarge",
+            ChunkWithTimestampDiff::UpdateLarge(_) => "UpdateLarge",
+            ChunkWithTimestampDiff::SmallToLarge(..) => "SmallToLarge",
+            ChunkWithTimestampDiff::LargeToSmall(..) => "LargeToSmall",
+            ChunkWithTimestampDiff::UpdateWithNoChange => "UpdateWithNoChange",
+            ChunkWithTimestampDiff::TimestampOnly(_) => "TimestampOnly",
+            ChunkWithTimestampDiff::RawSmall(..) => "RawSmall",
+        }
+    }
     pub fn get_description(&self) -> String {
         match self {
             ChunkWithTimestampDiff::BothNotExist => "report both old chunk and new chunk not exist",
@@ -64,16 +118,210 @@ where
             ChunkWithTimestampDiff::UpdateLarge(_) => "is a update large diff",
             ChunkWithTimestampDiff::SmallToLarge(_, _) => "is a small to large diff",
             ChunkWithTimestampDiff::LargeToSmall(_, _) => "is a large to small diff",
+            ChunkWithTimestampDiff::TimestampOnly(_) => "is a timestamp only diff",
+            ChunkWithTimestampDiff::RawSmall(_, _) => "is a raw small diff",
         }
         .to_string()
     }
 }
-#[derive(Debug, Clone, Encode, Decode)]
+/// Compact tag like `UpdateSmall(+60, sections=3, be=+2/-1/~0)`: the variant, its timestamp,
+/// or timestamp change for updates, and for small updates the summary of the chunk diff.
+impl<D: Diff<Value> + fmt::Display> fmt::Display for ChunkWithTimestampDiff<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkWithTimestampDiff::BothNotExist => write!(f, "BothNotExist"),
+            ChunkWithTimestampDiff::UpdateWithNoChange => write!(f, "UpdateWithNoChange"),
+            ChunkWithTimestampDiff::CreateSmall(ts, _) => write!(f, "CreateSmall({})", ts),
+            ChunkWithTimestampDiff::CreateLarge(ts) => write!(f, "CreateLarge({})", ts),
+            ChunkWithTimestampDiff::DeleteSmall(ts, _) => write!(f, "DeleteSmall({})", -ts),
+            ChunkWithTimestampDiff::DeleteLarge(ts) => write!(f, "DeleteLarge({})", -ts),
+            ChunkWithTimestampDiff::UpdateSmall(ts_diff, diff) => {
+                write!(f, "UpdateSmall({:+}, {})", ts_diff, diff)
+            }
+            ChunkWithTimestampDiff::UpdateLarge(ts_diff) => write!(f, "UpdateLarge({:+})", ts_diff),
+            ChunkWithTimestampDiff::SmallToLarge(ts_diff, _) => {
+                write!(f, "SmallToLarge({:+})", ts_diff)
+            }
+            ChunkWithTimestampDiff::LargeToSmall(ts_diff, _) => {
+                write!(f, "LargeToSmall({:+})", ts_diff)
+            }
+            ChunkWithTimestampDiff::TimestampOnly(ts_diff) => {
+                write!(f, "TimestampOnly({:+})", ts_diff)
+            }
+            ChunkWithTimestampDiff::RawSmall(ts_diff, _) => write!(f, "RawSmall({:+})", ts_diff),
+        }
+    }
+}
+/// Timestamps are `u32` epoch seconds, so they and their deltas are stored as `i64` to keep
+/// values past `i32::MAX` intact.
+fn ts_from_diff(ts_diff: i64) -> u32 {
+    u32::try_from(ts_diff).expect("Timestamp overflow")
+}
+fn add_ts_diff(ts: u32, ts_diff: i64) -> u32 {
+    ts_from_diff(ts as i64 + ts_diff)
+}
+
+/// Number of chunks of a region per kind of change in an `MCADiff`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffStats {
+    pub created: usize,
+    pub deleted: usize,
+    /// Chunks whose content or timestamp changed, including moves to or from an .mcc file
+    pub updated: usize,
+    /// Chunks that exist on both sides and are left as they are
+    pub unchanged: usize,
+}
+impl fmt::Display for DiffStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "created: {}, deleted: {}, updated: {}, unchanged: {}",
+            self.created, self.deleted, self.updated, self.unchanged
+        )
+    }
+}
+
+/// How often each combination of base and squashing chunk diff occurs when squashing two
+/// `MCADiff`s, counted over all the chunks of the region.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SquashCoverage {
+    /// Chunks per `(base, squashing)` variant names, e.g. `("CreateSmall", "UpdateSmall")`
+    pub combinations: BTreeMap<(&'static str, &'static str), usize>,
+    /// Chunks whose combination is impossible, i.e. the diffs are not adjacent
+    pub impossible: usize,
+}
+impl SquashCoverage {
+    pub fn total(&self) -> usize {
+        self.combinations.values().sum()
+    }
+    /// Count a chunk squashed from `base` and `squashing` into `squashed`.
+    fn count<D: Diff<Value>>(
+        &mut self,
+        base: &ChunkWithTimestampDiff<D>,
+        squashing: &ChunkWithTimestampDiff<D>,
+        squashed: &Result<ChunkWithTimestampDiff<D>, MCAError>,
+    ) {
+        *self
+            .combinations
+            .entry((base.kind(), squashing.kind()))
+            .or_default() += 1;
+        if let Err(MCAError::SquashFailed { .. }) = squashed {
+            self.impossible += 1;
+        }
+    }
+}
+/// One `base -> squashing: count` line per combination, most frequent first.
+impl fmt::Display for SquashCoverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut combinations: Vec<_> = self.combinations.iter().collect();
+        combinations.sort_by(|a, b| b.1.cmp(a.1));
+        for ((base, squashing), count) in combinations {
+            writeln!(f, "{} -> {}: {}", base, squashing, count)?;
+        }
+        write!(f, "impossible: {}", self.impossible)
+    }
+}
+
+/// Bytes a decoder claims for the chunk diffs of a region before reading any of them, i.e. every
+/// chunk diff at its size in memory, however few bytes the diff file spends on each.
+pub(crate) const DECODE_FLOOR: usize = CHUNKS_PER_REGION
+    * max(
+        size_of::<ChunkWithTimestampDiff<RegionChunkDiff>>(),
+        max(
+            size_of::<ChunkWithTimestampDiff<EntitiesChunkDiff>>(),
+            size_of::<ChunkWithTimestampDiff<PoiChunkDiff>>(),
+        ),
+    );
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+#[derive(Debug, Clone)]
 pub struct MCADiff<D>
 where
     D: Diff<Value>,
 {
     chunks: Vec<ChunkWithTimestampDiff<D>>,
+    /// Bit `i % 64` of word `i / 64` is set if chunk `i` is changed, see `affects`. Not encoded,
+    /// but rebuilt from `chunks` when decoding.
+    affected: [u64; CHUNKS_PER_REGION / 64],
+}
+
+impl<D: Diff<Value>> MCADiff<D> {
+    fn from_chunks(chunks: Vec<ChunkWithTimestampDiff<D>>) -> Self {
+        let mut affected = [0; CHUNKS_PER_REGION / 64];
+        for (i, chunk) in chunks.iter().enumerate() {
+            if !chunk.is_unchanged() {
+                affected[i / 64] |= 1 << (i % 64);
+            }
+        }
+        Self { chunks, affected }
+    }
+}
+
+impl<D: Diff<Value> + Encode> Encode for MCADiff<D> {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.chunks.encode(encoder)
+    }
+}
+
+impl<C, D: Diff<Value> + Decode<C>> Decode<C> for MCADiff<D> {
+    fn decode<De: Decoder<Context = C>>(decoder: &mut De) -> Result<Self, DecodeError> {
+        let chunks: Vec<_> = Vec::decode(decoder)?;
+        if chunks.len() != CHUNKS_PER_REGION {
+            return Err(DecodeError::Other("region diff does not cover every chunk"));
+        }
+        Ok(Self::from_chunks(chunks))
+    }
+}
+
+/// Inclusive box of chunk coordinates, parsed from `x0,z0-x1,z1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkRange {
+    pub x0: usize,
+    pub z0: usize,
+    pub x1: usize,
+    pub z1: usize,
+}
+impl ChunkRange {
+    pub fn contains(&self, x: usize, z: usize) -> bool {
+        (self.x0..=self.x1).contains(&x) && (self.z0..=self.z1).contains(&z)
+    }
+}
+impl FromStr for ChunkRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected `x0,z0-x1,z1`, got `{}`", s))?;
+        let (x0, z0) = parse_chunk_xz(from)?;
+        let (x1, z1) = parse_chunk_xz(to)?;
+        if x0 > x1 || z0 > z1 {
+            return Err(format!("empty chunk range `{}`", s));
+        }
+        Ok(Self { x0, z0, x1, z1 })
+    }
+}
+
+/// Parse the coordinates of a chunk within a region from `x,z`.
+pub fn parse_chunk_xz(xz: &str) -> Result<(usize, usize), String> {
+    let (x, z) = xz
+        .split_once(',')
+        .ok_or_else(|| format!("expected `x,z`, got `{}`", xz))?;
+    let parse = |v: &str| -> Result<usize, String> {
+        let v: usize = v
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid coordinate `{}`: {}", v, e))?;
+        if v < REGION_DIM {
+            Ok(v)
+        } else {
+            Err(format!("coordinate {} is out of 0..{}", v, REGION_DIM))
+        }
+    };
+    Ok((parse(x)?, parse(z)?))
 }
 
 fn log_cost_statistics<R>(result: &[(IXZ, R, Option<Duration>)]) {
@@ -117,6 +365,461 @@ fn enable_cost_stat() -> bool {
     log_enabled!(Level::Debug)
 }
 
+/// Whether two loaded chunks are the very same: both absent, or with the same timestamp and
+/// nbt bytes.
+fn same_chunk(a: &LazyChunk, b: &LazyChunk) -> bool {
+    match (a, b) {
+        (LazyChunk::Unloaded, _) | (_, LazyChunk::Unloaded) => panic!("Chunk is unloaded"),
+        (LazyChunk::NotExists, LazyChunk::NotExists) => true,
+        (LazyChunk::Some(a), LazyChunk::Some(b)) => {
+            a.timestamp == b.timestamp && a.nbt.eq_bytes(&b.nbt)
+        }
+        _ => false,
+    }
+}
+
+fn compare_chunk<D, R>(
+    reader_old: &MCAReader<R>,
+    reader_new: &MCAReader<R>,
+    x: usize,
+    z: usize,
+    ignored_tags: &[String],
+    skip_equal: bool,
+    compare_timestamps: bool,
+) -> ChunkWithTimestampDiff<D>
+where
+    D: Diff<Value>,
+    R: Read + Seek,
+{
+    // existence is decided by the header location, not the timestamp: a present chunk may
+    // legitimately carry a zero timestamp
+    let old = reader_old.get_chunk_lazily(x, z);
+    let new = reader_new.get_chunk_lazily(x, z);
+    match (old, new) {
+        (LazyChunk::Unloaded, _) => panic!("Old chunk is unloaded"),
+        (_, LazyChunk::Unloaded) => panic!("New chunk is unloaded"),
+        (LazyChunk::NotExists, LazyChunk::NotExists) => ChunkWithTimestampDiff::BothNotExist,
+        (LazyChunk::NotExists, LazyChunk::Some(chunk)) => match &chunk.nbt {
+            ChunkNbt::Large => ChunkWithTimestampDiff::CreateLarge(chunk.timestamp as i64),
+            ChunkNbt::Small(nbt) => ChunkWithTimestampDiff::CreateSmall(
+                chunk.timestamp as i64,
+                BlobDiff::from_create(&nbt),
+            ),
+        },
+        (LazyChunk::Some(chunk), LazyChunk::NotExists) => match &chunk.nbt {
+            ChunkNbt::Large => ChunkWithTimestampDiff::DeleteLarge(-(chunk.timestamp as i64)),
+            ChunkNbt::Small(nbt) => ChunkWithTimestampDiff::DeleteSmall(
+                -(chunk.timestamp as i64),
+                BlobDiff::from_delete(&nbt),
+            ),
+        },
+        (LazyChunk::Some(chunk_old), LazyChunk::Some(chunk_new)) => {
+            let ts_diff = chunk_new.timestamp as i64 - chunk_old.timestamp as i64;
+            // without trusting timestamps, only byte-equal chunks are known to be unchanged
+            if ts_diff == 0 && (compare_timestamps || chunk_old.nbt.eq_bytes(&chunk_new.nbt)) {
+                ChunkWithTimestampDiff::UpdateWithNoChange
+            } else {
+                if matches!(chunk_old.nbt, ChunkNbt::Large)
+                    || matches!(chunk_new.nbt, ChunkNbt::Large)
+                {
+                    // the nbt of a large chunk lives in its .mcc file, which is diffed separately
+                    log::warn!(
+                        "chunk ({}, {}) is stored in an external .mcc file, only its timestamp is diffed here",
+                        x,
+                        z
+                    );
+                }
+                match (&chunk_old.nbt, &chunk_new.nbt) {
+                    (ChunkNbt::Large, ChunkNbt::Large) => {
+                        ChunkWithTimestampDiff::UpdateLarge(ts_diff)
+                    }
+                    // only the timestamp was bumped, e.g. by saving an untouched chunk
+                    (ChunkNbt::Small(old), ChunkNbt::Small(new)) if skip_equal && old == new => {
+                        ChunkWithTimestampDiff::UpdateWithNoChange
+                    }
+                    (ChunkNbt::Small(old), ChunkNbt::Small(new)) if old == new => {
+                        ChunkWithTimestampDiff::TimestampOnly(ts_diff)
+                    }
+                    (ChunkNbt::Small(old), ChunkNbt::Small(new)) => {
+                        update_small(x, z, ts_diff, old, new, ignored_tags)
+                    }
+                    (ChunkNbt::Small(old), ChunkNbt::Large) => {
+                        ChunkWithTimestampDiff::SmallToLarge(ts_diff, BlobDiff::from_delete(&old))
+                    }
+                    (ChunkNbt::Large, ChunkNbt::Small(new)) => {
+                        ChunkWithTimestampDiff::LargeToSmall(ts_diff, BlobDiff::from_create(&new))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Diff of the small chunk at (`x`, `z`) from `old` to `new`, or a `RawSmall` with a warning if
+/// either can't be decoded, e.g. because it holds a tag type fastnbt doesn't know, so that one
+/// unusual chunk doesn't abort the whole region.
+fn update_small<D: Diff<Value>>(
+    x: usize,
+    z: usize,
+    ts_diff: i64,
+    old: &Vec<u8>,
+    new: &Vec<u8>,
+    ignored_tags: &[String],
+) -> ChunkWithTimestampDiff<D> {
+    match (try_de(old), try_de(new)) {
+        (Ok(old), Ok(mut new)) => {
+            let diff = in_chunk(x, z, || {
+                copy_tags(&old, &mut new, ignored_tags);
+                D::from_compare(&old, &new)
+            });
+            ChunkWithTimestampDiff::UpdateSmall(ts_diff, diff)
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            log::warn!(
+                "chunk ({}, {}) can't be decoded ({}), storing it as raw bytes",
+                x,
+                z,
+                e
+            );
+            ChunkWithTimestampDiff::RawSmall(ts_diff, BlobDiff::from_compare(old, new))
+        }
+    }
+}
+
+/// Squash the diffs of the chunk at (`x`, `z`). Fails if `squashing_diff` doesn't start from the
+/// state `base_diff` leaves the chunk in, or if a chunk it needs to decode is corrupt.
+fn squash_chunk<D: Diff<Value>>(
+    x: usize,
+    z: usize,
+    base_diff: &ChunkWithTimestampDiff<D>,
+    squashing_diff: &ChunkWithTimestampDiff<D>,
+) -> Result<ChunkWithTimestampDiff<D>, MCAError> {
+    let incompatible = || MCAError::SquashFailed {
+        x,
+        z,
+        reason: format!(
+            "base diff {}, while squashing diff {}",
+            base_diff.get_description(),
+            squashing_diff.get_description()
+        ),
+    };
+    let decode =
+        |nbt: &Vec<u8>| try_de(nbt).map_err(|source| MCAError::NBTParsingError { x, z, source });
+
+    Ok(match base_diff {
+        // any state --> NotExists --> any state
+        ChunkWithTimestampDiff::BothNotExist => match squashing_diff {
+            ChunkWithTimestampDiff::BothNotExist => ChunkWithTimestampDiff::BothNotExist,
+            ChunkWithTimestampDiff::CreateSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::CreateSmall(*s_ts_diff, s_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::CreateLarge(s_ts_diff) => {
+                ChunkWithTimestampDiff::CreateLarge(*s_ts_diff)
+            }
+            _ => return Err(incompatible()),
+        },
+        ChunkWithTimestampDiff::DeleteLarge(b_ts_diff) => match squashing_diff {
+            ChunkWithTimestampDiff::BothNotExist => ChunkWithTimestampDiff::DeleteLarge(*b_ts_diff),
+            ChunkWithTimestampDiff::CreateSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::LargeToSmall(b_ts_diff + s_ts_diff, s_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::CreateLarge(s_ts_diff) => {
+                ChunkWithTimestampDiff::UpdateLarge(b_ts_diff + s_ts_diff)
+            }
+            _ => return Err(incompatible()),
+        },
+        ChunkWithTimestampDiff::DeleteSmall(b_ts_diff, b_blob_diff) => match squashing_diff {
+            ChunkWithTimestampDiff::BothNotExist => {
+                ChunkWithTimestampDiff::DeleteSmall(*b_ts_diff, b_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::CreateSmall(s_ts_diff, s_blob_diff) => update_small(
+                x,
+                z,
+                b_ts_diff + s_ts_diff,
+                b_blob_diff.get_old_text(),
+                s_blob_diff.get_new_text(),
+                &[],
+            ),
+            ChunkWithTimestampDiff::CreateLarge(s_ts_diff) => {
+                ChunkWithTimestampDiff::SmallToLarge(b_ts_diff + s_ts_diff, b_blob_diff.clone())
+            }
+            _ => return Err(incompatible()),
+        },
+
+        // any state --> Small --> any state
+        ChunkWithTimestampDiff::UpdateSmall(b_ts_diff, b_chunk_diff) => match squashing_diff {
+            ChunkWithTimestampDiff::UpdateWithNoChange => {
+                ChunkWithTimestampDiff::UpdateSmall(*b_ts_diff, b_chunk_diff.clone())
+            }
+            ChunkWithTimestampDiff::TimestampOnly(s_ts_diff) => {
+                ChunkWithTimestampDiff::UpdateSmall(b_ts_diff + s_ts_diff, b_chunk_diff.clone())
+            }
+            ChunkWithTimestampDiff::UpdateSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::UpdateSmall(
+                    b_ts_diff + s_ts_diff,
+                    D::from_squash(b_chunk_diff, s_blob_diff),
+                )
+            }
+            ChunkWithTimestampDiff::RawSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::RawSmall(
+                    b_ts_diff + s_ts_diff,
+                    BlobDiff::from_compare(
+                        &ser(&b_chunk_diff.revert(&decode(s_blob_diff.get_old_text())?)),
+                        s_blob_diff.get_new_text(),
+                    ),
+                )
+            }
+            ChunkWithTimestampDiff::DeleteSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::DeleteSmall(
+                    b_ts_diff + s_ts_diff,
+                    BlobDiff::from_compare(
+                        &ser(&b_chunk_diff.revert(&decode(s_blob_diff.get_old_text())?)),
+                        s_blob_diff.get_new_text(),
+                    ),
+                )
+            }
+            ChunkWithTimestampDiff::SmallToLarge(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::SmallToLarge(
+                    b_ts_diff + s_ts_diff,
+                    BlobDiff::from_compare(
+                        &ser(&b_chunk_diff.revert(&decode(s_blob_diff.get_old_text())?)),
+                        s_blob_diff.get_new_text(),
+                    ),
+                )
+            }
+            _ => return Err(incompatible()),
+        },
+        ChunkWithTimestampDiff::CreateSmall(b_ts_diff, b_blob_diff) => match squashing_diff {
+            ChunkWithTimestampDiff::UpdateWithNoChange => {
+                ChunkWithTimestampDiff::CreateSmall(*b_ts_diff, b_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::TimestampOnly(s_ts_diff) => {
+                ChunkWithTimestampDiff::CreateSmall(b_ts_diff + s_ts_diff, b_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::UpdateSmall(s_ts_diff, s_chunk_diff) => {
+                ChunkWithTimestampDiff::CreateSmall(
+                    b_ts_diff + s_ts_diff,
+                    BlobDiff::from_compare(
+                        b_blob_diff.get_old_text(),
+                        &ser(&s_chunk_diff.patch(&decode(b_blob_diff.get_new_text())?)),
+                    ),
+                )
+            }
+            ChunkWithTimestampDiff::RawSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::CreateSmall(
+                    b_ts_diff + s_ts_diff,
+                    BlobDiff::from_create(s_blob_diff.get_new_text()),
+                )
+            }
+            ChunkWithTimestampDiff::DeleteSmall(..) => ChunkWithTimestampDiff::BothNotExist,
+            ChunkWithTimestampDiff::SmallToLarge(s_ts_diff, _) => {
+                ChunkWithTimestampDiff::CreateLarge(*s_ts_diff)
+            }
+            _ => return Err(incompatible()),
+        },
+        ChunkWithTimestampDiff::LargeToSmall(b_ts_diff, b_blob_diff) => match squashing_diff {
+            ChunkWithTimestampDiff::UpdateWithNoChange => {
+                ChunkWithTimestampDiff::LargeToSmall(*b_ts_diff, b_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::TimestampOnly(s_ts_diff) => {
+                ChunkWithTimestampDiff::LargeToSmall(b_ts_diff + s_ts_diff, b_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::UpdateSmall(s_ts_diff, s_chunk_diff) => {
+                ChunkWithTimestampDiff::LargeToSmall(
+                    b_ts_diff + s_ts_diff,
+                    BlobDiff::from_compare(
+                        b_blob_diff.get_old_text(),
+                        &ser(&s_chunk_diff.patch(&decode(b_blob_diff.get_new_text())?)),
+                    ),
+                )
+            }
+            ChunkWithTimestampDiff::RawSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::LargeToSmall(
+                    b_ts_diff + s_ts_diff,
+                    BlobDiff::from_create(s_blob_diff.get_new_text()),
+                )
+            }
+            ChunkWithTimestampDiff::DeleteSmall(s_ts_diff, _) => {
+                ChunkWithTimestampDiff::DeleteLarge(b_ts_diff + s_ts_diff)
+            }
+            ChunkWithTimestampDiff::SmallToLarge(s_ts_diff, _) => {
+                ChunkWithTimestampDiff::UpdateLarge(b_ts_diff + s_ts_diff)
+            }
+            _ => return Err(incompatible()),
+        },
+
+        ChunkWithTimestampDiff::TimestampOnly(b_ts_diff) => match squashing_diff {
+            ChunkWithTimestampDiff::UpdateWithNoChange => {
+                ChunkWithTimestampDiff::TimestampOnly(*b_ts_diff)
+            }
+            ChunkWithTimestampDiff::TimestampOnly(s_ts_diff) => {
+                ChunkWithTimestampDiff::TimestampOnly(b_ts_diff + s_ts_diff)
+            }
+            ChunkWithTimestampDiff::UpdateSmall(s_ts_diff, s_chunk_diff) => {
+                ChunkWithTimestampDiff::UpdateSmall(b_ts_diff + s_ts_diff, s_chunk_diff.clone())
+            }
+            ChunkWithTimestampDiff::RawSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::RawSmall(b_ts_diff + s_ts_diff, s_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::DeleteSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::DeleteSmall(b_ts_diff + s_ts_diff, s_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::SmallToLarge(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::SmallToLarge(b_ts_diff + s_ts_diff, s_blob_diff.clone())
+            }
+            _ => return Err(incompatible()),
+        },
+
+        ChunkWithTimestampDiff::RawSmall(b_ts_diff, b_blob_diff) => match squashing_diff {
+            ChunkWithTimestampDiff::UpdateWithNoChange => {
+                ChunkWithTimestampDiff::RawSmall(*b_ts_diff, b_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::TimestampOnly(s_ts_diff) => {
+                ChunkWithTimestampDiff::RawSmall(b_ts_diff + s_ts_diff, b_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::UpdateSmall(s_ts_diff, s_chunk_diff) => {
+                ChunkWithTimestampDiff::RawSmall(
+                    b_ts_diff + s_ts_diff,
+                    BlobDiff::from_compare(
+                        b_blob_diff.get_old_text(),
+                        &ser(&s_chunk_diff.patch(&decode(b_blob_diff.get_new_text())?)),
+                    ),
+                )
+            }
+            ChunkWithTimestampDiff::RawSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::RawSmall(
+                    b_ts_diff + s_ts_diff,
+                    BlobDiff::from_squash(b_blob_diff, s_blob_diff),
+                )
+            }
+            ChunkWithTimestampDiff::DeleteSmall(s_ts_diff, _) => {
+                ChunkWithTimestampDiff::DeleteSmall(
+                    b_ts_diff + s_ts_diff,
+                    BlobDiff::from_delete(b_blob_diff.get_old_text()),
+                )
+            }
+            ChunkWithTimestampDiff::SmallToLarge(s_ts_diff, _) => {
+                ChunkWithTimestampDiff::SmallToLarge(
+                    b_ts_diff + s_ts_diff,
+                    BlobDiff::from_delete(b_blob_diff.get_old_text()),
+                )
+            }
+            _ => return Err(incompatible()),
+        },
+
+        // any state --> Large --> any state
+        ChunkWithTimestampDiff::CreateLarge(b_ts_diff) => match squashing_diff {
+            ChunkWithTimestampDiff::UpdateWithNoChange => {
+                ChunkWithTimestampDiff::CreateLarge(*b_ts_diff)
+            }
+            ChunkWithTimestampDiff::UpdateLarge(s_ts_diff) => {
+                ChunkWithTimestampDiff::CreateLarge(b_ts_diff + s_ts_diff)
+            }
+            ChunkWithTimestampDiff::DeleteLarge(_) => ChunkWithTimestampDiff::BothNotExist,
+            ChunkWithTimestampDiff::LargeToSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::CreateSmall(b_ts_diff + s_ts_diff, s_blob_diff.clone())
+            }
+            _ => return Err(incompatible()),
+        },
+        ChunkWithTimestampDiff::UpdateLarge(b_ts_diff) => match squashing_diff {
+            ChunkWithTimestampDiff::UpdateWithNoChange => {
+                ChunkWithTimestampDiff::UpdateLarge(*b_ts_diff)
+            }
+            ChunkWithTimestampDiff::UpdateLarge(s_ts_diff) => {
+                ChunkWithTimestampDiff::UpdateLarge(b_ts_diff + s_ts_diff)
+            }
+            ChunkWithTimestampDiff::DeleteLarge(s_ts_diff) => {
+                ChunkWithTimestampDiff::DeleteLarge(b_ts_diff + s_ts_diff)
+            }
+            ChunkWithTimestampDiff::LargeToSmall(s_ts_diff, s_blob_diff) => {
+                ChunkWithTimestampDiff::LargeToSmall(b_ts_diff + s_ts_diff, s_blob_diff.clone())
+            }
+            _ => return Err(incompatible()),
+        },
+        ChunkWithTimestampDiff::SmallToLarge(b_ts_diff, b_blob_diff) => match squashing_diff {
+            ChunkWithTimestampDiff::UpdateWithNoChange => {
+                ChunkWithTimestampDiff::SmallToLarge(*b_ts_diff, b_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::UpdateLarge(s_ts_diff) => {
+                ChunkWithTimestampDiff::SmallToLarge(b_ts_diff + s_ts_diff, b_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::DeleteLarge(s_ts_diff) => {
+                ChunkWithTimestampDiff::DeleteSmall(b_ts_diff + s_ts_diff, b_blob_diff.clone())
+            }
+            ChunkWithTimestampDiff::LargeToSmall(s_ts_diff, s_blob_diff) => update_small(
+                x,
+                z,
+                b_ts_diff + s_ts_diff,
+                b_blob_diff.get_old_text(),
+                s_blob_diff.get_new_text(),
+                &[],
+            ),
+            _ => return Err(incompatible()),
+        },
+
+        // no change
+        ChunkWithTimestampDiff::UpdateWithNoChange => match squashing_diff {
+            ChunkWithTimestampDiff::UpdateWithNoChange
+            | ChunkWithTimestampDiff::UpdateLarge(..)
+            | ChunkWithTimestampDiff::DeleteLarge(..)
+            | ChunkWithTimestampDiff::UpdateSmall(..)
+            | ChunkWithTimestampDiff::DeleteSmall(..)
+            | ChunkWithTimestampDiff::SmallToLarge(..)
+            | ChunkWithTimestampDiff::LargeToSmall(..) => base_diff.clone(),
+            ChunkWithTimestampDiff::TimestampOnly(_) | ChunkWithTimestampDiff::RawSmall(..) => {
+                squashing_diff.clone()
+            }
+            _ => return Err(incompatible()),
+        },
+    })
+}
+
+/// Run `f` on the chunk at (`x`, `z`), prefixing the message of a panic in it with the chunk
+/// coordinates, which the chunk diffs themselves don't know.
+fn in_chunk<T>(x: usize, z: usize, f: impl FnOnce() -> T) -> T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(output) => output,
+        Err(payload) => panic!("chunk ({}, {}): {}", x, z, panic_message(&*payload)),
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(msg), _) => msg.to_string(),
+        (_, Some(msg)) => msg.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
+
+fn estimate_compare_cost<R: Read + Seek>(
+    reader_old: &MCAReader<R>,
+    reader_new: &MCAReader<R>,
+    x: usize,
+    z: usize,
+) -> usize {
+    let old = reader_old.get_chunk_lazily(x, z);
+    let new = reader_new.get_chunk_lazily(x, z);
+    match (old, new) {
+        (LazyChunk::Some(chunk_old), LazyChunk::Some(chunk_new))
+            if chunk_old.timestamp != chunk_new.timestamp =>
+        {
+            match (&chunk_old.nbt, &chunk_new.nbt) {
+                (ChunkNbt::Small(old), ChunkNbt::Small(new)) => {
+                    use std::cmp::{max, min};
+                    let old = old.len();
+                    let new = new.len();
+                    max(old, new) - min(old, new)
+                }
+                _ => 0,
+            }
+        }
+        _ => 0,
+    }
+}
+
 static ERR_MSG_OLD: &str = "Failed to parse old mca file";
 static ERR_MSG_NEW: &str = "Failed to parse new mca file";
 
@@ -127,484 +830,151 @@ where
     fn from_compare(old: &Vec<u8>, new: &Vec<u8>) -> Self {
         let reader_old = Arc::new(MCAReader::from_bytes(old).expect(ERR_MSG_OLD));
         let reader_new = Arc::new(MCAReader::from_bytes(new).expect(ERR_MSG_NEW));
+        let config = get_config();
+        let (ignored_tags, skip_equal, compare_ts) = (
+            config.ignored_tags,
+            config.skip_equal_chunks,
+            config.compare_timestamps,
+        );
 
         let results = parallel_process_with_cost_estimator(
             create_chunk_ixz_iter(),
             |(_, x, z)| {
-                let old_ts = reader_old.get_timestamp(*x, *z);
-                let new_ts = reader_new.get_timestamp(*x, *z);
-                let ts_diff = new_ts as i32 - old_ts as i32;
-
-                let chunk = {
-                    if old_ts == 0 && new_ts == 0 {
-                        ChunkWithTimestampDiff::BothNotExist
-                    } else {
-                        let old = reader_old.get_chunk_lazily(*x, *z);
-                        let new = reader_new.get_chunk_lazily(*x, *z);
-                        if ts_diff == 0 {
-                            match (old, new) {
-                                (LazyChunk::NotExists, LazyChunk::NotExists) => {
-                                    ChunkWithTimestampDiff::BothNotExist
-                                }
-                                _ => ChunkWithTimestampDiff::UpdateWithNoChange,
-                            }
-                        } else {
-                            match (old, new) {
-                                (LazyChunk::Unloaded, _) => panic!("Old chunk is unloaded"),
-                                (_, LazyChunk::Unloaded) => panic!("New chunk is unloaded"),
-                                (LazyChunk::NotExists, LazyChunk::NotExists) => {
-                                    ChunkWithTimestampDiff::BothNotExist
-                                }
-                                (LazyChunk::NotExists, LazyChunk::Some(chunk)) => {
-                                    match &chunk.nbt {
-                                        ChunkNbt::Large => ChunkWithTimestampDiff::CreateLarge(
-                                            chunk.timestamp as i32,
-                                        ),
-                                        ChunkNbt::Small(nbt) => {
-                                            ChunkWithTimestampDiff::CreateSmall(
-                                                chunk.timestamp as i32,
-                                                BlobDiff::from_create(&nbt),
-                                            )
-                                        }
-                                    }
-                                }
-                                (LazyChunk::Some(chunk), LazyChunk::NotExists) => {
-                                    match &chunk.nbt {
-                                        ChunkNbt::Large => ChunkWithTimestampDiff::DeleteLarge(
-                                            -(chunk.timestamp as i32),
-                                        ),
-                                        ChunkNbt::Small(nbt) => {
-                                            ChunkWithTimestampDiff::DeleteSmall(
-                                                -(chunk.timestamp as i32),
-                                                BlobDiff::from_delete(&nbt),
-                                            )
-                                        }
-                                    }
-                                }
-                                (LazyChunk::Some(chunk_old), LazyChunk::Some(chunk_new)) => {
-                                    let ts_diff =
-                                        chunk_new.timestamp as i32 - chunk_old.timestamp as i32;
-                                    if ts_diff == 0 {
-                                        ChunkWithTimestampDiff::UpdateWithNoChange
-                                    } else {
-                                        match (&chunk_old.nbt, &chunk_new.nbt) {
-                                            (ChunkNbt::Large, ChunkNbt::Large) => {
-                                                ChunkWithTimestampDiff::UpdateLarge(ts_diff)
-                                            }
-                                            (ChunkNbt::Small(old), ChunkNbt::Small(new)) => {
-                                                ChunkWithTimestampDiff::UpdateSmall(
-                                                    ts_diff,
-                                                    D::from_compare(&de(&old), &de(&new)),
-                                                )
-                                            }
-                                            (ChunkNbt::Small(old), ChunkNbt::Large) => {
-                                                ChunkWithTimestampDiff::SmallToLarge(
-                                                    ts_diff,
-                                                    BlobDiff::from_delete(&old),
-                                                )
-                                            }
-                                            (ChunkNbt::Large, ChunkNbt::Small(new)) => {
-                                                ChunkWithTimestampDiff::SmallToLarge(
-                                                    ts_diff,
-                                                    BlobDiff::from_create(&new),
-                                                )
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                };
-                chunk
-            },
-            |(_, x, z)| {
-                let old_ts = reader_old.get_timestamp(*x, *z);
-                let new_ts = reader_new.get_timestamp(*x, *z);
-                let ts_diff = new_ts as i32 - old_ts as i32;
-
-                let chunk = match (old_ts, new_ts, ts_diff) {
-                    (0, 0, _) => 0,
-                    (_, _, 0) => 0,
-                    _ => {
-                        let old = reader_old.get_chunk_lazily(*x, *z);
-                        let new = reader_new.get_chunk_lazily(*x, *z);
-                        match (old, new) {
-                            (LazyChunk::Some(chunk_old), LazyChunk::Some(chunk_new)) => {
-                                let old = &chunk_old.nbt;
-                                let new = &chunk_new.nbt;
-                                match (old, new) {
-                                    (ChunkNbt::Small(old), ChunkNbt::Small(new)) => {
-                                        use std::cmp::{max, min};
-                                        let old = old.len();
-                                        let new = new.len();
-                                        max(old, new) - min(old, new)
-                                    }
-                                    _ => 0,
-                                }
-                            }
-                            _ => 0,
-                        }
-                    }
-                };
-                chunk
+                compare_chunk(
+                    &reader_old,
+                    &reader_new,
+                    *x,
+                    *z,
+                    &ignored_tags,
+                    skip_equal,
+                    compare_ts,
+                )
             },
+            |(_, x, z)| estimate_compare_cost(&reader_old, &reader_new, *x, *z),
         );
 
         if enable_cost_stat() {
             log_cost_statistics(&results);
         }
 
-        let mut chunks = vec![ChunkWithTimestampDiff::BothNotExist; 1024];
+        let mut chunks = vec![ChunkWithTimestampDiff::BothNotExist; CHUNKS_PER_REGION];
         for ((i, _, _), chunk, _) in results {
             chunks[i] = chunk;
         }
 
-        Self { chunks }
+        Self::from_chunks(chunks)
     }
 
     fn from_squash(base: &Self, squashing: &Self) -> Self {
-        let results = parallel_process(create_chunk_ixz_iter(), |(i, _, _)| {
-            let base_diff = &base.chunks[*i];
-            let squashing_diff = &squashing.chunks[*i];
-
-            let squashed = match base_diff {
-                // any state --> NotExists --> any state
-                ChunkWithTimestampDiff::BothNotExist => match squashing_diff {
-                    ChunkWithTimestampDiff::BothNotExist => ChunkWithTimestampDiff::BothNotExist,
-                    ChunkWithTimestampDiff::CreateSmall(s_ts_diff, s_blob_diff) => {
-                        ChunkWithTimestampDiff::CreateSmall(*s_ts_diff, s_blob_diff.clone())
-                    }
-                    ChunkWithTimestampDiff::CreateLarge(s_ts_diff) => {
-                        ChunkWithTimestampDiff::CreateLarge(*s_ts_diff)
-                    }
-                    _ => unreachable!(
-                        "Impossible case: base diff {}, while squashing diff {}",
-                        base_diff.get_description(),
-                        squashing_diff.get_description()
-                    ),
-                },
-                ChunkWithTimestampDiff::DeleteLarge(b_ts_diff) => match squashing_diff {
-                    ChunkWithTimestampDiff::BothNotExist => {
-                        ChunkWithTimestampDiff::DeleteLarge(*b_ts_diff)
-                    }
-                    ChunkWithTimestampDiff::CreateSmall(s_ts_diff, s_blob_diff) => {
-                        ChunkWithTimestampDiff::LargeToSmall(
-                            b_ts_diff + s_ts_diff,
-                            s_blob_diff.clone(),
-                        )
-                    }
-                    ChunkWithTimestampDiff::CreateLarge(s_ts_diff) => {
-                        ChunkWithTimestampDiff::UpdateLarge(b_ts_diff + s_ts_diff)
-                    }
-                    _ => unreachable!(
-                        "Impossible case: base diff {}, while squashing diff {}",
-                        base_diff.get_description(),
-                        squashing_diff.get_description()
-                    ),
-                },
-                ChunkWithTimestampDiff::DeleteSmall(b_ts_diff, b_blob_diff) => match squashing_diff
-                {
-                    ChunkWithTimestampDiff::BothNotExist => {
-                        ChunkWithTimestampDiff::DeleteSmall(*b_ts_diff, b_blob_diff.clone())
-                    }
-                    ChunkWithTimestampDiff::CreateSmall(s_ts_diff, s_blob_diff) => {
-                        ChunkWithTimestampDiff::UpdateSmall(
-                            b_ts_diff + s_ts_diff,
-                            D::from_compare(
-                                &de(b_blob_diff.get_old_text()),
-                                &de(s_blob_diff.get_new_text()),
-                            ),
-                        )
-                    }
-                    ChunkWithTimestampDiff::CreateLarge(s_ts_diff) => {
-                        ChunkWithTimestampDiff::SmallToLarge(
-                            b_ts_diff + s_ts_diff,
-                            b_blob_diff.clone(),
-                        )
-                    }
-                    _ => unreachable!(
-                        "Impossible case: base diff {}, while squashing diff {}",
-                        base_diff.get_description(),
-                        squashing_diff.get_description()
-                    ),
-                },
-
-                // any state --> Small --> any state
-                ChunkWithTimestampDiff::UpdateSmall(b_ts_diff, b_chunk_diff) => {
-                    match squashing_diff {
-                        ChunkWithTimestampDiff::UpdateWithNoChange => {
-                            ChunkWithTimestampDiff::UpdateSmall(*b_ts_diff, b_chunk_diff.clone())
-                        }
-                        ChunkWithTimestampDiff::UpdateSmall(s_ts_diff, s_blob_diff) => {
-                            ChunkWithTimestampDiff::UpdateSmall(
-                                b_ts_diff + s_ts_diff,
-                                D::from_squash(b_chunk_diff, s_blob_diff),
-                            )
-                        }
-                        ChunkWithTimestampDiff::DeleteSmall(s_ts_diff, s_blob_diff) => {
-                            ChunkWithTimestampDiff::DeleteSmall(
-                                b_ts_diff + s_ts_diff,
-                                BlobDiff::from_compare(
-                                    &ser(&b_chunk_diff.revert(&de(s_blob_diff.get_old_text()))),
-                                    s_blob_diff.get_new_text(),
-                                ),
-                            )
-                        }
-                        ChunkWithTimestampDiff::SmallToLarge(s_ts_diff, s_blob_diff) => {
-                            ChunkWithTimestampDiff::SmallToLarge(
-                                b_ts_diff + s_ts_diff,
-                                BlobDiff::from_compare(
-                                    &ser(&b_chunk_diff.revert(&de(s_blob_diff.get_old_text()))),
-                                    s_blob_diff.get_new_text(),
-                                ),
-                            )
-                        }
-                        _ => unreachable!(
-                            "Impossible case: base diff {}, while squashing diff {}",
-                            base_diff.get_description(),
-                            squashing_diff.get_description()
-                        ),
-                    }
-                }
-                ChunkWithTimestampDiff::CreateSmall(b_ts_diff, b_blob_diff) => match squashing_diff
-                {
-                    ChunkWithTimestampDiff::UpdateWithNoChange => {
-                        ChunkWithTimestampDiff::CreateSmall(*b_ts_diff, b_blob_diff.clone())
-                    }
-                    ChunkWithTimestampDiff::UpdateSmall(s_ts_diff, s_chunk_diff) => {
-                        ChunkWithTimestampDiff::CreateSmall(
-                            b_ts_diff + s_ts_diff,
-                            BlobDiff::from_compare(
-                                b_blob_diff.get_old_text(),
-                                &ser(&s_chunk_diff.patch(&de(b_blob_diff.get_new_text()))),
-                            ),
-                        )
-                    }
-                    ChunkWithTimestampDiff::DeleteSmall(..) => ChunkWithTimestampDiff::BothNotExist,
-                    ChunkWithTimestampDiff::SmallToLarge(s_ts_diff, _) => {
-                        ChunkWithTimestampDiff::CreateLarge(*s_ts_diff)
-                    }
-                    _ => unreachable!(
-                        "Impossible case: base diff {}, while squashing diff {}",
-                        base_diff.get_description(),
-                        squashing_diff.get_description()
-                    ),
-                },
-                ChunkWithTimestampDiff::LargeToSmall(b_ts_diff, b_blob_diff) => {
-                    match squashing_diff {
-                        ChunkWithTimestampDiff::UpdateWithNoChange => {
-                            ChunkWithTimestampDiff::LargeToSmall(*b_ts_diff, b_blob_diff.clone())
-                        }
-                        ChunkWithTimestampDiff::UpdateSmall(s_ts_diff, s_chunk_diff) => {
-                            ChunkWithTimestampDiff::LargeToSmall(
-                                b_ts_diff + s_ts_diff,
-                                BlobDiff::from_compare(
-                                    b_blob_diff.get_old_text(),
-                                    &ser(&s_chunk_diff.patch(&de(b_blob_diff.get_new_text()))),
-                                ),
-                            )
-                        }
-                        ChunkWithTimestampDiff::DeleteSmall(s_ts_diff, _) => {
-                            ChunkWithTimestampDiff::DeleteLarge(b_ts_diff + s_ts_diff)
-                        }
-                        ChunkWithTimestampDiff::SmallToLarge(s_ts_diff, _) => {
-                            ChunkWithTimestampDiff::UpdateLarge(b_ts_diff + s_ts_diff)
-                        }
-                        _ => unreachable!(
-                            "Impossible case: base diff {}, while squashing diff {}",
-                            base_diff.get_description(),
-                            squashing_diff.get_description()
-                        ),
-                    }
-                }
+        Self::try_squash(base, squashing).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-                // any state --> Large --> any state
-                ChunkWithTimestampDiff::CreateLarge(b_ts_diff) => match squashing_diff {
-                    ChunkWithTimestampDiff::UpdateWithNoChange => {
-                        ChunkWithTimestampDiff::CreateLarge(*b_ts_diff)
-                    }
-                    ChunkWithTimestampDiff::UpdateLarge(s_ts_diff) => {
-                        ChunkWithTimestampDiff::CreateLarge(b_ts_diff + s_ts_diff)
-                    }
-                    ChunkWithTimestampDiff::DeleteLarge(_) => ChunkWithTimestampDiff::BothNotExist,
-                    ChunkWithTimestampDiff::LargeToSmall(s_ts_diff, s_blob_diff) => {
-                        ChunkWithTimestampDiff::CreateSmall(
-                            b_ts_diff + s_ts_diff,
-                            s_blob_diff.clone(),
-                        )
-                    }
-                    _ => unreachable!(
-                        "Impossible case: base diff {}, while squashing diff {}",
-                        base_diff.get_description(),
-                        squashing_diff.get_description()
-                    ),
-                },
-                ChunkWithTimestampDiff::UpdateLarge(b_ts_diff) => match squashing_diff {
-                    ChunkWithTimestampDiff::UpdateWithNoChange => {
-                        ChunkWithTimestampDiff::UpdateLarge(*b_ts_diff)
-                    }
-                    ChunkWithTimestampDiff::UpdateLarge(s_ts_diff) => {
-                        ChunkWithTimestampDiff::UpdateLarge(b_ts_diff + s_ts_diff)
-                    }
-                    ChunkWithTimestampDiff::DeleteLarge(s_ts_diff) => {
-                        ChunkWithTimestampDiff::DeleteLarge(b_ts_diff + s_ts_diff)
-                    }
-                    ChunkWithTimestampDiff::LargeToSmall(s_ts_diff, s_blob_diff) => {
-                        ChunkWithTimestampDiff::LargeToSmall(
-                            b_ts_diff + s_ts_diff,
-                            s_blob_diff.clone(),
-                        )
-                    }
-                    _ => unreachable!(
-                        "Impossible case: base diff {}, while squashing diff {}",
-                        base_diff.get_description(),
-                        squashing_diff.get_description()
-                    ),
-                },
-                ChunkWithTimestampDiff::SmallToLarge(b_ts_diff, b_blob_diff) => {
-                    match squashing_diff {
-                        ChunkWithTimestampDiff::UpdateWithNoChange => {
-                            ChunkWithTimestampDiff::SmallToLarge(*b_ts_diff, b_blob_diff.clone())
-                        }
-                        ChunkWithTimestampDiff::UpdateLarge(s_ts_diff) => {
-                            ChunkWithTimestampDiff::SmallToLarge(
-                                b_ts_diff + s_ts_diff,
-                                b_blob_diff.clone(),
-                            )
-                        }
-                        ChunkWithTimestampDiff::DeleteLarge(s_ts_diff) => {
-                            ChunkWithTimestampDiff::DeleteSmall(
-                                b_ts_diff + s_ts_diff,
-                                b_blob_diff.clone(),
-                            )
-                        }
-                        ChunkWithTimestampDiff::LargeToSmall(s_ts_diff, s_blob_diff) => {
-                            ChunkWithTimestampDiff::UpdateSmall(
-                                b_ts_diff + s_ts_diff,
-                                D::from_compare(
-                                    &de(b_blob_diff.get_old_text()),
-                                    &de(s_blob_diff.get_new_text()),
-                                ),
-                            )
-                        }
-                        _ => unreachable!(
-                            "Impossible case: base diff {}, while squashing diff {}",
-                            base_diff.get_description(),
-                            squashing_diff.get_description()
-                        ),
-                    }
-                }
+    fn patch(&self, old: &Vec<u8>) -> Vec<u8> {
+        let reader = Arc::new(MCAReader::from_bytes(old).expect(ERR_MSG_OLD));
+        let enable_cost_stat = log_enabled!(Level::Debug);
 
-                // no change
-                ChunkWithTimestampDiff::UpdateWithNoChange => match squashing_diff {
-                    ChunkWithTimestampDiff::UpdateWithNoChange
-                    | ChunkWithTimestampDiff::UpdateLarge(..)
-                    | ChunkWithTimestampDiff::DeleteLarge(..)
-                    | ChunkWithTimestampDiff::UpdateSmall(..)
-                    | ChunkWithTimestampDiff::DeleteSmall(..)
-                    | ChunkWithTimestampDiff::SmallToLarge(..)
-                    | ChunkWithTimestampDiff::LargeToSmall(..) => base_diff.clone(),
-                    _ => unreachable!(
-                        "Impossible case: base diff {}, while squashing diff {}",
-                        base_diff.get_description(),
-                        squashing_diff.get_description()
-                    ),
-                },
-            };
-            squashed
+        let results = parallel_process(create_chunk_ixz_iter(), |(i, x, z)| {
+            self.patch_chunk_of(&reader, *i, *x, *z)
         });
 
-        if enable_cost_stat() {
+        if enable_cost_stat {
             log_cost_statistics(&results);
         }
 
-        let mut squashed_chunks = vec![ChunkWithTimestampDiff::BothNotExist; 1024];
-        for ((i, _, _), chunk, _) in results {
-            squashed_chunks[i] = chunk;
+        let mut builder = MCABuilder::new();
+        let preserve = get_config().preserve_empty_sectors;
+        if preserve {
+            builder.set_layout(reader.layout().expect(ERR_MSG_OLD));
         }
-
-        Self {
-            chunks: squashed_chunks,
+        for ((i, x, z), new_chunk, _) in &results {
+            if let Some(chunk) = new_chunk {
+                builder.set_chunk(*x, *z, &chunk);
+                if preserve && self.chunks[*i].keeps_nbt() {
+                    builder.set_chunk_unchanged(*x, *z);
+                }
+            }
         }
+
+        builder
+            .to_bytes(get_config().compression_type)
+            .expect("Failed to build bytes")
     }
 
-    fn patch(&self, old: &Vec<u8>) -> Vec<u8> {
-        let reader = Arc::new(MCAReader::from_bytes(old).expect(ERR_MSG_OLD));
+    fn revert(&self, new: &Vec<u8>) -> Vec<u8> {
+        let reader = Arc::new(MCAReader::from_bytes(new).expect(ERR_MSG_NEW));
         let enable_cost_stat = log_enabled!(Level::Debug);
 
         let results = parallel_process(create_chunk_ixz_iter(), |(i, x, z)| {
-            let old_chunk = reader.get_chunk_lazily(*x, *z);
+            let new_chunk = reader.get_chunk_lazily(*x, *z);
             let chunk_diff = &self.chunks[*i];
 
-            let new_chunk = match old_chunk {
-                LazyChunk::Unloaded => panic!("Old chunk is unloaded"),
+            let old_chunk = match new_chunk {
+                LazyChunk::Unloaded => panic!("New chunk is unloaded"),
                 LazyChunk::NotExists => match chunk_diff {
                     ChunkWithTimestampDiff::BothNotExist => None,
-                    ChunkWithTimestampDiff::CreateSmall(ts_diff, chunk_diff) => {
-                        Some(ChunkWithTimestamp {
-                            timestamp: *ts_diff as u32,
-                            nbt: ChunkNbt::Small(chunk_diff.patch(&Vec::new())),
-                        })
-                    }
-                    ChunkWithTimestampDiff::CreateLarge(ts_diff) => {
-                        assert!(*ts_diff != 0);
+                    ChunkWithTimestampDiff::DeleteSmall(ts_diff, blob_diff) => {
                         Some(ChunkWithTimestamp {
-                            timestamp: *ts_diff as u32,
-                            nbt: ChunkNbt::Large,
+                            timestamp: ts_from_diff(-ts_diff),
+                            nbt: ChunkNbt::Small(blob_diff.revert0()),
                         })
                     }
+                    ChunkWithTimestampDiff::DeleteLarge(ts_diff) => Some(ChunkWithTimestamp {
+                        timestamp: ts_from_diff(-ts_diff),
+                        nbt: ChunkNbt::Large,
+                    }),
                     _ => panic!(
                         "Invalid diff for non-existing chunk: {}",
                         chunk_diff.get_description()
                     ),
                 },
-                LazyChunk::Some(old_chunk) => match &old_chunk.nbt {
+                LazyChunk::Some(new_chunk) => match &new_chunk.nbt {
                     ChunkNbt::Small(nbt) => match chunk_diff {
-                        ChunkWithTimestampDiff::DeleteSmall(..) => None,
+                        ChunkWithTimestampDiff::CreateSmall(..) => None,
                         ChunkWithTimestampDiff::UpdateSmall(ts_diff, chunk_diff) => {
                             Some(ChunkWithTimestamp {
-                                timestamp: old_chunk
-                                    .timestamp
-                                    .checked_add_signed(*ts_diff)
-                                    .expect("Timestamp overflow"),
-                                nbt: ChunkNbt::Small(ser(&chunk_diff.patch(&de(&nbt)))),
+                                timestamp: add_ts_diff(new_chunk.timestamp, -*ts_diff),
+                                nbt: ChunkNbt::Small(in_chunk(*x, *z, || {
+                                    ser(&chunk_diff.revert(&de(&nbt)))
+                                })),
                             })
                         }
-                        ChunkWithTimestampDiff::SmallToLarge(ts_diff, _) => {
+                        ChunkWithTimestampDiff::LargeToSmall(ts_diff, _) => {
                             Some(ChunkWithTimestamp {
-                                timestamp: old_chunk
-                                    .timestamp
-                                    .checked_add_signed(*ts_diff)
-                                    .expect("Timestamp overflow"),
+                                timestamp: add_ts_diff(new_chunk.timestamp, -*ts_diff),
                                 nbt: ChunkNbt::Large,
                             })
                         }
-                        ChunkWithTimestampDiff::UpdateWithNoChange => Some(old_chunk.clone()),
+                        ChunkWithTimestampDiff::TimestampOnly(ts_diff) => {
+                            Some(ChunkWithTimestamp {
+                                timestamp: add_ts_diff(new_chunk.timestamp, -*ts_diff),
+                                nbt: new_chunk.nbt.clone(),
+                            })
+                        }
+                        ChunkWithTimestampDiff::RawSmall(ts_diff, blob_diff) => {
+                            Some(ChunkWithTimestamp {
+                                timestamp: add_ts_diff(new_chunk.timestamp, -*ts_diff),
+                                nbt: ChunkNbt::Small(blob_diff.revert0()),
+                            })
+                        }
+                        ChunkWithTimestampDiff::UpdateWithNoChange => Some(new_chunk.clone()),
                         _ => panic!(
                             "Invalid diff for existing small chunk: {}",
                             chunk_diff.get_description()
                         ),
                     },
                     ChunkNbt::Large => match chunk_diff {
-                        ChunkWithTimestampDiff::DeleteLarge(..) => None,
+                        ChunkWithTimestampDiff::CreateLarge(_) => None,
                         ChunkWithTimestampDiff::UpdateLarge(ts_diff) => Some(ChunkWithTimestamp {
-                            timestamp: old_chunk
-                                .timestamp
-                                .checked_add_signed(*ts_diff)
-                                .expect("Timestamp overflow"),
+                            timestamp: add_ts_diff(new_chunk.timestamp, -*ts_diff),
                             nbt: ChunkNbt::Large,
                         }),
-                        ChunkWithTimestampDiff::LargeToSmall(ts_diff, blob_diff) => {
+                        ChunkWithTimestampDiff::SmallToLarge(ts_diff, blob_diff) => {
                             Some(ChunkWithTimestamp {
-                                timestamp: old_chunk
-                                    .timestamp
-                                    .checked_add_signed(*ts_diff)
-                                    .expect("Timestamp overflow"),
-                                nbt: ChunkNbt::Small(blob_diff.patch0()),
+                                timestamp: add_ts_diff(new_chunk.timestamp, -*ts_diff),
+                                nbt: ChunkNbt::Small(blob_diff.revert0()),
                             })
                         }
-                        ChunkWithTimestampDiff::UpdateWithNoChange => Some(old_chunk.clone()),
+                        ChunkWithTimestampDiff::UpdateWithNoChange => Some(new_chunk.clone()),
                         _ => panic!(
                             "Invalid diff for existing large chunk: {}",
                             chunk_diff.get_description()
@@ -612,7 +982,7 @@ where
                     },
                 },
             };
-            new_chunk
+            old_chunk
         });
 
         if enable_cost_stat {
@@ -620,114 +990,423 @@ where
         }
 
         let mut builder = MCABuilder::new();
-        for ((_, x, z), new_chunk, _) in &results {
-            if let Some(chunk) = new_chunk {
+        let preserve = get_config().preserve_empty_sectors;
+        if preserve {
+            builder.set_layout(reader.layout().expect(ERR_MSG_NEW));
+        }
+        for ((i, x, z), old_chunk, _) in &results {
+            if let Some(chunk) = old_chunk {
                 builder.set_chunk(*x, *z, &chunk);
+                if preserve && self.chunks[*i].keeps_nbt() {
+                    builder.set_chunk_unchanged(*x, *z);
+                }
             }
         }
 
         builder
-            .to_bytes(CompressionType::Zlib)
+            .to_bytes(get_config().compression_type)
             .expect("Failed to build bytes")
     }
+}
 
-    fn revert(&self, new: &Vec<u8>) -> Vec<u8> {
-        let reader = Arc::new(MCAReader::from_bytes(new).expect(ERR_MSG_NEW));
-        let enable_cost_stat = log_enabled!(Level::Debug);
+impl<D> TrySquash<Vec<u8>> for MCADiff<D>
+where
+    D: Diff<Value> + Send + Sync + bincode::Decode<MCADiff<D>>,
+{
+    fn try_squash(base: &Self, squashing: &Self) -> Result<Self, DiffError> {
+        MCADiff::try_squash(base, squashing).map_err(|e| DiffError::NotSquashable(e.to_string()))
+    }
+}
 
-        let results = parallel_process(create_chunk_ixz_iter(), |(i, x, z)| {
-            let new_chunk = reader.get_chunk_lazily(*x, *z);
-            let chunk_diff = &self.chunks[*i];
+impl<D> MCADiff<D>
+where
+    D: Diff<Value> + Send + Sync + bincode::Decode<MCADiff<D>>,
+{
+    /// Patch the chunk at (`x`, `z`), index `i`, of the region read by `reader`, giving `None`
+    /// if the patched chunk does not exist.
+    fn patch_chunk_of<R: Read + Seek>(
+        &self,
+        reader: &MCAReader<R>,
+        i: usize,
+        x: usize,
+        z: usize,
+    ) -> Option<ChunkWithTimestamp> {
+        let old_chunk = reader.get_chunk_lazily(x, z);
+        let chunk_diff = &self.chunks[i];
 
-            let old_chunk = match new_chunk {
-                LazyChunk::Unloaded => panic!("New chunk is unloaded"),
-                LazyChunk::NotExists => match chunk_diff {
-                    ChunkWithTimestampDiff::BothNotExist => None,
-                    ChunkWithTimestampDiff::DeleteSmall(ts_diff, blob_diff) => {
+        match old_chunk {
+            LazyChunk::Unloaded => panic!("Old chunk is unloaded"),
+            LazyChunk::NotExists => match chunk_diff {
+                ChunkWithTimestampDiff::BothNotExist => None,
+                ChunkWithTimestampDiff::CreateSmall(ts_diff, chunk_diff) => {
+                    Some(ChunkWithTimestamp {
+                        timestamp: ts_from_diff(*ts_diff),
+                        nbt: ChunkNbt::Small(chunk_diff.patch(&Vec::new())),
+                    })
+                }
+                ChunkWithTimestampDiff::CreateLarge(ts_diff) => Some(ChunkWithTimestamp {
+                    timestamp: ts_from_diff(*ts_diff),
+                    nbt: ChunkNbt::Large,
+                }),
+                _ => panic!(
+                    "Invalid diff for non-existing chunk: {}",
+                    chunk_diff.get_description()
+                ),
+            },
+            LazyChunk::Some(old_chunk) => match &old_chunk.nbt {
+                ChunkNbt::Small(nbt) => match chunk_diff {
+                    ChunkWithTimestampDiff::DeleteSmall(..) => None,
+                    ChunkWithTimestampDiff::UpdateSmall(ts_diff, chunk_diff) => {
                         Some(ChunkWithTimestamp {
-                            timestamp: -ts_diff as u32,
-                            nbt: ChunkNbt::Small(blob_diff.revert0()),
+                            timestamp: add_ts_diff(old_chunk.timestamp, *ts_diff),
+                            nbt: ChunkNbt::Small(in_chunk(x, z, || {
+                                ser(&chunk_diff.patch(&de(&nbt)))
+                            })),
                         })
                     }
-                    ChunkWithTimestampDiff::DeleteLarge(ts_diff) => Some(ChunkWithTimestamp {
-                        timestamp: -ts_diff as u32,
+                    ChunkWithTimestampDiff::SmallToLarge(ts_diff, _) => Some(ChunkWithTimestamp {
+                        timestamp: add_ts_diff(old_chunk.timestamp, *ts_diff),
                         nbt: ChunkNbt::Large,
                     }),
+                    ChunkWithTimestampDiff::TimestampOnly(ts_diff) => Some(ChunkWithTimestamp {
+                        timestamp: add_ts_diff(old_chunk.timestamp, *ts_diff),
+                        nbt: old_chunk.nbt.clone(),
+                    }),
+                    ChunkWithTimestampDiff::RawSmall(ts_diff, blob_diff) => {
+                        Some(ChunkWithTimestamp {
+                            timestamp: add_ts_diff(old_chunk.timestamp, *ts_diff),
+                            nbt: ChunkNbt::Small(blob_diff.patch0()),
+                        })
+                    }
+                    ChunkWithTimestampDiff::UpdateWithNoChange => Some(old_chunk.clone()),
                     _ => panic!(
-                        "Invalid diff for non-existing chunk: {}",
+                        "Invalid diff for existing small chunk: {}",
                         chunk_diff.get_description()
                     ),
                 },
-                LazyChunk::Some(new_chunk) => match &new_chunk.nbt {
-                    ChunkNbt::Small(nbt) => match chunk_diff {
-                        ChunkWithTimestampDiff::CreateSmall(..) => None,
-                        ChunkWithTimestampDiff::UpdateSmall(ts_diff, chunk_diff) => {
-                            Some(ChunkWithTimestamp {
-                                timestamp: new_chunk
-                                    .timestamp
-                                    .checked_add_signed(-*ts_diff)
-                                    .expect("Timestamp overflow"),
-                                nbt: ChunkNbt::Small(ser(&chunk_diff.revert(&de(&nbt)))),
-                            })
-                        }
-                        ChunkWithTimestampDiff::LargeToSmall(ts_diff, _) => {
-                            Some(ChunkWithTimestamp {
-                                timestamp: new_chunk
-                                    .timestamp
-                                    .checked_add_signed(-*ts_diff)
-                                    .expect("Timestamp overflow"),
-                                nbt: ChunkNbt::Large,
-                            })
-                        }
-                        ChunkWithTimestampDiff::UpdateWithNoChange => Some(new_chunk.clone()),
-                        _ => panic!(
-                            "Invalid diff for existing small chunk: {}",
-                            chunk_diff.get_description()
-                        ),
-                    },
-                    ChunkNbt::Large => match chunk_diff {
-                        ChunkWithTimestampDiff::CreateLarge(_) => None,
-                        ChunkWithTimestampDiff::UpdateLarge(ts_diff) => Some(ChunkWithTimestamp {
-                            timestamp: new_chunk
-                                .timestamp
-                                .checked_add_signed(-*ts_diff)
-                                .expect("Timestamp overflow"),
-                            nbt: ChunkNbt::Large,
-                        }),
-                        ChunkWithTimestampDiff::SmallToLarge(ts_diff, blob_diff) => {
-                            Some(ChunkWithTimestamp {
-                                timestamp: new_chunk
-                                    .timestamp
-                                    .checked_add_signed(-*ts_diff)
-                                    .expect("Timestamp overflow"),
-                                nbt: ChunkNbt::Small(blob_diff.revert0()),
-                            })
-                        }
-                        ChunkWithTimestampDiff::UpdateWithNoChange => Some(new_chunk.clone()),
-                        _ => panic!(
-                            "Invalid diff for existing large chunk: {}",
-                            chunk_diff.get_description()
-                        ),
-                    },
+                ChunkNbt::Large => match chunk_diff {
+                    ChunkWithTimestampDiff::DeleteLarge(..) => None,
+                    ChunkWithTimestampDiff::UpdateLarge(ts_diff) => Some(ChunkWithTimestamp {
+                        timestamp: add_ts_diff(old_chunk.timestamp, *ts_diff),
+                        nbt: ChunkNbt::Large,
+                    }),
+                    ChunkWithTimestampDiff::LargeToSmall(ts_diff, blob_diff) => {
+                        Some(ChunkWithTimestamp {
+                            timestamp: add_ts_diff(old_chunk.timestamp, *ts_diff),
+                            nbt: ChunkNbt::Small(blob_diff.patch0()),
+                        })
+                    }
+                    ChunkWithTimestampDiff::UpdateWithNoChange => Some(old_chunk.clone()),
+                    _ => panic!(
+                        "Invalid diff for existing large chunk: {}",
+                        chunk_diff.get_description()
+                    ),
                 },
-            };
-            old_chunk
-        });
+            },
+        }
+    }
+    /// Patch only the chunk at (`x`, `z`) of the `old` region, without rebuilding the region,
+    /// giving `None` if the patched chunk does not exist.
+    pub fn patch_chunk(&self, old: &[u8], x: usize, z: usize) -> Option<ChunkWithTimestamp> {
+        let reader = MCAReader::from_bytes(old).expect(ERR_MSG_OLD);
+        self.patch_chunk_of(&reader, x + REGION_DIM * z, x, z)
+    }
+    /// Whether this diff changes the chunk at (`x`, `z`). Patching and reverting leave the other
+    /// chunks as they are.
+    pub fn affects(&self, x: usize, z: usize) -> bool {
+        let i = x + REGION_DIM * z;
+        self.affected[i / 64] & (1 << (i % 64)) != 0
+    }
+    pub fn stats(&self) -> DiffStats {
+        let mut stats = DiffStats::default();
+        for chunk in &self.chunks {
+            match chunk {
+                ChunkWithTimestampDiff::BothNotExist => {}
+                ChunkWithTimestampDiff::UpdateWithNoChange => stats.unchanged += 1,
+                ChunkWithTimestampDiff::CreateSmall(..)
+                | ChunkWithTimestampDiff::CreateLarge(_) => stats.created += 1,
+                ChunkWithTimestampDiff::DeleteSmall(..)
+                | ChunkWithTimestampDiff::DeleteLarge(_) => stats.deleted += 1,
+                ChunkWithTimestampDiff::UpdateSmall(..)
+                | ChunkWithTimestampDiff::TimestampOnly(_)
+                | ChunkWithTimestampDiff::RawSmall(..)
+                | ChunkWithTimestampDiff::UpdateLarge(_)
+                | ChunkWithTimestampDiff::SmallToLarge(..)
+                | ChunkWithTimestampDiff::LargeToSmall(..) => stats.updated += 1,
+            }
+        }
+        stats
+    }
+    /// Count the combinations of chunk diffs that squashing `squashing` onto `base` goes
+    /// through by squashing each chunk on the current thread. Impossible combinations are
+    /// counted rather than failed on.
+    pub fn squash_coverage(base: &Self, squashing: &Self) -> SquashCoverage {
+        let mut coverage = SquashCoverage::default();
+        for (i, x, z) in create_chunk_ixz_iter() {
+            let (b, s) = (&base.chunks[i], &squashing.chunks[i]);
+            coverage.count(b, s, &squash_chunk(x, z, b, s));
+        }
+        coverage
+    }
+    /// The `stats` followed by a line per changed chunk with its coordinates and compact tag.
+    pub fn render_stats(&self) -> String
+    where
+        D: fmt::Display,
+    {
+        let mut out = self.stats().to_string();
+        for (i, x, z) in create_chunk_ixz_iter() {
+            if !self.chunks[i].is_unchanged() {
+                out.push_str(&format!("\n({}, {}) {}", x, z, self.chunks[i]));
+            }
+        }
+        out
+    }
+    /// Same as `from_compare`, but reuses the chunk diffs of `prev_diff` (which was computed
+    /// from `prev_old` to `prev_new`) wherever they are still valid.
+    ///
+    /// A previous chunk diff is reused when the old and new chunks have the same timestamps
+    /// and bytes as the ones in `prev_old` and `prev_new`, which is cheap to check.
+    pub fn from_compare_incremental(
+        old: &[u8],
+        new: &[u8],
+        prev_diff: &Self,
+        prev_old: &[u8],
+        prev_new: &[u8],
+    ) -> Self {
+        let reader_old = Arc::new(MCAReader::from_bytes(old).expect(ERR_MSG_OLD));
+        let reader_new = Arc::new(MCAReader::from_bytes(new).expect(ERR_MSG_NEW));
+        let config = get_config();
+        let (ignored_tags, skip_equal, compare_ts) = (
+            config.ignored_tags,
+            config.skip_equal_chunks,
+            config.compare_timestamps,
+        );
+        let reader_prev_old = Arc::new(MCAReader::from_bytes(prev_old).expect(ERR_MSG_OLD));
+        let reader_prev_new = Arc::new(MCAReader::from_bytes(prev_new).expect(ERR_MSG_NEW));
 
-        if enable_cost_stat {
+        let reusable = |x: usize, z: usize| {
+            same_chunk(
+                reader_old.get_chunk_lazily(x, z),
+                reader_prev_old.get_chunk_lazily(x, z),
+            ) && same_chunk(
+                reader_new.get_chunk_lazily(x, z),
+                reader_prev_new.get_chunk_lazily(x, z),
+            )
+        };
+
+        let results = parallel_process_with_cost_estimator(
+            create_chunk_ixz_iter(),
+            |(i, x, z)| {
+                if reusable(*x, *z) {
+                    (prev_diff.chunks[*i].clone(), true)
+                } else {
+                    (
+                        compare_chunk(
+                            &reader_old,
+                            &reader_new,
+                            *x,
+                            *z,
+                            &ignored_tags,
+                            skip_equal,
+                            compare_ts,
+                        ),
+                        false,
+                    )
+                }
+            },
+            |(_, x, z)| estimate_compare_cost(&reader_old, &reader_new, *x, *z),
+        );
+
+        if enable_cost_stat() {
             log_cost_statistics(&results);
         }
 
+        let mut reused = 0;
+        let mut chunks = vec![ChunkWithTimestampDiff::BothNotExist; CHUNKS_PER_REGION];
+        for ((i, _, _), (chunk, is_reused), _) in results {
+            chunks[i] = chunk;
+            reused += is_reused as usize;
+        }
+        log::debug!(
+            "reused {} of {} chunk diffs from previous diff",
+            reused,
+            CHUNKS_PER_REGION
+        );
+
+        Self::from_chunks(chunks)
+    }
+    /// Estimated length of the region file produced by patching this diff onto `old`, for
+    /// preallocating writers.
+    ///
+    /// Chunks that keep their nbt keep the sectors they have in `old`, and so do updated chunks,
+    /// whose size rarely changes much. Chunks stored whole take the sectors of their new nbt
+    /// uncompressed, an upper bound, and chunks in an .mcc file a single sector.
+    pub fn output_size_hint(&self, old: &[u8]) -> usize {
+        let header_size = SECTOR_SIZE * 2;
+        let old_sectors = |i: usize| old.get(i * 4 + 3).map_or(0, |&count| count as usize);
+        // 4-byte length and compression type in front of the nbt
+        let new_sectors =
+            |blob_diff: &BlobDiff| (5 + blob_diff.get_new_text().len()).div_ceil(SECTOR_SIZE);
+        let sectors: usize = self
+            .chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| match chunk {
+                ChunkWithTimestampDiff::BothNotExist
+                | ChunkWithTimestampDiff::DeleteSmall(..)
+                | ChunkWithTimestampDiff::DeleteLarge(..) => 0,
+                ChunkWithTimestampDiff::UpdateWithNoChange
+                | ChunkWithTimestampDiff::TimestampOnly(..)
+                | ChunkWithTimestampDiff::UpdateSmall(..)
+                | ChunkWithTimestampDiff::UpdateLarge(..) => old_sectors(i),
+                ChunkWithTimestampDiff::CreateSmall(_, blob_diff)
+                | ChunkWithTimestampDiff::LargeToSmall(_, blob_diff)
+                | ChunkWithTimestampDiff::RawSmall(_, blob_diff) => new_sectors(blob_diff),
+                ChunkWithTimestampDiff::CreateLarge(..)
+                | ChunkWithTimestampDiff::SmallToLarge(..) => 1,
+            })
+            .sum();
+        header_size + sectors * SECTOR_SIZE
+    }
+    /// Same as `revert`, but `new` is only required for chunks whose old content is not fully
+    /// recorded in the diff, i.e. anything but created, deleted and absent chunks.
+    ///
+    /// Without `new`, this fails with `MCAError::NewChunkRequired` on the first such chunk.
+    pub fn revert_standalone(&self, new: Option<&[u8]>) -> Result<Vec<u8>, MCAError> {
+        if let Some(new) = new {
+            return Ok(self.revert(&new.to_vec()));
+        }
+
+        let mut old_chunks = Vec::with_capacity(CHUNKS_PER_REGION);
+        for (i, x, z) in create_chunk_ixz_iter() {
+            let old_chunk = match &self.chunks[i] {
+                ChunkWithTimestampDiff::BothNotExist
+                | ChunkWithTimestampDiff::CreateSmall(..)
+                | ChunkWithTimestampDiff::CreateLarge(..) => None,
+                ChunkWithTimestampDiff::DeleteSmall(ts_diff, blob_diff) => {
+                    Some(ChunkWithTimestamp {
+                        timestamp: ts_from_diff(-ts_diff),
+                        nbt: ChunkNbt::Small(blob_diff.revert0()),
+                    })
+                }
+                ChunkWithTimestampDiff::DeleteLarge(ts_diff) => Some(ChunkWithTimestamp {
+                    timestamp: ts_from_diff(-ts_diff),
+                    nbt: ChunkNbt::Large,
+                }),
+                _ => return Err(MCAError::NewChunkRequired { x, z }),
+            };
+            old_chunks.push(((x, z), old_chunk));
+        }
+
         let mut builder = MCABuilder::new();
-        for ((_, x, z), old_chunk, _) in &results {
+        for ((x, z), old_chunk) in &old_chunks {
             if let Some(chunk) = old_chunk {
-                builder.set_chunk(*x, *z, &chunk);
+                builder.set_chunk(*x, *z, chunk);
             }
         }
+        builder.to_bytes(get_config().compression_type)
+    }
+    /// Combine two diffs of the same old and new files that each changed a different set of
+    /// chunks, e.g. ones from `from_compare_within` over disjoint ranges.
+    ///
+    /// Fails with `MCAError::OverlappingChunk` if a chunk is changed by both diffs.
+    pub fn merge_disjoint(a: &Self, b: &Self) -> Result<Self, MCAError> {
+        let chunks = create_chunk_ixz_iter()
+            .map(|(i, x, z)| match (a.affects(x, z), b.affects(x, z)) {
+                (_, false) => Ok(a.chunks[i].clone()),
+                (false, true) => Ok(b.chunks[i].clone()),
+                (true, true) => Err(MCAError::OverlappingChunk { x, z }),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self::from_chunks(chunks))
+    }
+    /// Same as `from_squash`, but fails instead of panicking when a chunk can't be squashed,
+    /// e.g. because the diffs aren't adjacent or one of them is corrupt. Of all such chunks,
+    /// the error is about the first one in region order.
+    pub fn try_squash(base: &Self, squashing: &Self) -> Result<Self, MCAError> {
+        let results = parallel_process(create_chunk_ixz_iter(), |(i, x, z)| {
+            squash_chunk(*x, *z, &base.chunks[*i], &squashing.chunks[*i])
+        });
 
-        builder
-            .to_bytes(CompressionType::Zlib)
-            .expect("Failed to build bytes")
+        if enable_cost_stat() {
+            log_cost_statistics(&results);
+        }
+
+        if log_enabled!(Level::Debug) {
+            let mut coverage = SquashCoverage::default();
+            for ((i, _, _), chunk, _) in &results {
+                coverage.count(&base.chunks[*i], &squashing.chunks[*i], chunk);
+            }
+            log::debug!("squash combinations:\n{}", coverage);
+        }
+
+        let mut squashed_chunks = vec![ChunkWithTimestampDiff::BothNotExist; CHUNKS_PER_REGION];
+        let mut first_error: Option<(usize, MCAError)> = None;
+        for ((i, _, _), chunk, _) in results {
+            match chunk {
+                Ok(chunk) => squashed_chunks[i] = chunk,
+                Err(e) => {
+                    if first_error.as_ref().is_none_or(|(first, _)| i < *first) {
+                        first_error = Some((i, e));
+                    }
+                }
+            }
+        }
+        match first_error {
+            Some((_, e)) => Err(e),
+            None => Ok(Self::from_chunks(squashed_chunks)),
+        }
+    }
+    /// Same as `from_compare`, but only compares the chunks within `range`. Chunks outside of it
+    /// are assumed to be unchanged.
+    pub fn from_compare_within(old: &[u8], new: &[u8], range: &ChunkRange) -> Self {
+        let reader_old = Arc::new(MCAReader::from_bytes(old).expect(ERR_MSG_OLD));
+        let reader_new = Arc::new(MCAReader::from_bytes(new).expect(ERR_MSG_NEW));
+        let config = get_config();
+        let (ignored_tags, skip_equal, compare_ts) = (
+            config.ignored_tags,
+            config.skip_equal_chunks,
+            config.compare_timestamps,
+        );
+
+        let results = parallel_process_with_cost_estimator(
+            create_chunk_ixz_iter(),
+            |(_, x, z)| {
+                if range.contains(*x, *z) {
+                    compare_chunk(
+                        &reader_old,
+                        &reader_new,
+                        *x,
+                        *z,
+                        &ignored_tags,
+                        skip_equal,
+                        compare_ts,
+                    )
+                } else if let LazyChunk::Some(_) = reader_old.get_chunk_lazily(*x, *z) {
+                    ChunkWithTimestampDiff::UpdateWithNoChange
+                } else {
+                    ChunkWithTimestampDiff::BothNotExist
+                }
+            },
+            |(_, x, z)| {
+                if range.contains(*x, *z) {
+                    estimate_compare_cost(&reader_old, &reader_new, *x, *z)
+                } else {
+                    0
+                }
+            },
+        );
+
+        if enable_cost_stat() {
+            log_cost_statistics(&results);
+        }
+
+        let mut chunks = vec![ChunkWithTimestampDiff::BothNotExist; CHUNKS_PER_REGION];
+        for ((i, _, _), chunk, _) in results {
+            chunks[i] = chunk;
+        }
+
+        Self::from_chunks(chunks)
     }
 }
 
@@ -736,17 +1415,19 @@ mod tests {
     use std::{fs, path::PathBuf};
 
     use super::*;
+    use crate::compress::CompressionType;
     use crate::diff::chunk::RegionChunkDiff;
     use crate::{
         config::{Config, with_test_config},
-        mca::{LazyChunk, MCAReader},
-        util::test::{all_file_iter, assert_mca_eq, rearranged_nbt},
+        mca::{LARGE_FLAG, LazyChunk, MCAReader, SECTOR_SIZE},
+        util::{
+            nbt_serde,
+            serde::{self, ser},
+            test::{all_file_iter, assert_mca_eq, get_test_chunk_by_xz, rearranged_nbt},
+        },
     };
 
-    static TEST_CONFIG: Config = Config {
-        log_config: crate::config::LogConfig::NoLog,
-        threads: 16,
-    };
+    static TEST_CONFIG: Config = Config::for_test();
 
     #[test]
     #[ignore = "replace test mca files"]
@@ -861,4 +1542,879 @@ mod tests {
             }
         });
     }
+    #[test]
+    fn test_diff_incremental() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let v2 = fs::read(format!("{}/20250513.mca", dir)).unwrap();
+
+            let prev_diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+            let incremental = MCADiff::from_compare_incremental(&v0, &v2, &prev_diff, &v0, &v1);
+            let full: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v2);
+            assert_eq!(ser(incremental), ser(full));
+
+            // a chunk rewritten without touching its timestamp is diffed again
+            let (reader_v0, reader_v1) = (
+                MCAReader::from_bytes(&v0).unwrap(),
+                MCAReader::from_bytes(&v1).unwrap(),
+            );
+            let mut rewritten = None;
+            let mut chunks = Vec::new();
+            for (_, x, z) in create_chunk_ixz_iter() {
+                let LazyChunk::Some(chunk) = reader_v1.get_chunk_lazily(x, z) else {
+                    continue;
+                };
+                let mut chunk = chunk.clone();
+                if let (None, LazyChunk::Some(old_chunk)) =
+                    (rewritten, reader_v0.get_chunk_lazily(x, z))
+                    && !old_chunk.nbt.eq_bytes(&chunk.nbt)
+                {
+                    chunk.nbt = old_chunk.nbt.clone();
+                    rewritten = Some((x, z));
+                }
+                chunks.push((x, z, chunk));
+            }
+            let mut builder = MCABuilder::new();
+            for (x, z, chunk) in &chunks {
+                builder.set_chunk(*x, *z, chunk);
+            }
+            assert!(rewritten.is_some());
+            let v1b = builder.to_bytes(CompressionType::Zlib).unwrap();
+            let incremental = MCADiff::from_compare_incremental(&v0, &v1b, &prev_diff, &v0, &v1);
+            let full: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1b);
+            assert_eq!(ser(incremental), ser(full));
+        });
+    }
+    #[test]
+    fn test_diff_zero_timestamp_chunk() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let mut chunk = get_test_chunk_by_xz(
+                &PathBuf::from(
+                    "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+                ),
+                25,
+                29,
+            )
+            .unwrap();
+            chunk.timestamp = 0;
+            let mut builder = MCABuilder::new();
+            builder.set_chunk(25, 29, &chunk);
+            let with_chunk = builder.to_bytes(CompressionType::Zlib).unwrap();
+            let without_chunk = MCABuilder::new().to_bytes(CompressionType::Zlib).unwrap();
+            let i = 25 + 29 * 32;
+
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&with_chunk, &without_chunk);
+            assert!(matches!(
+                diff.chunks[i],
+                ChunkWithTimestampDiff::DeleteSmall(..)
+            ));
+            assert_mca_eq(&without_chunk, &diff.patch(&with_chunk));
+            assert_mca_eq(&with_chunk, &diff.revert(&without_chunk));
+
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&without_chunk, &with_chunk);
+            assert!(matches!(
+                diff.chunks[i],
+                ChunkWithTimestampDiff::CreateSmall(..)
+            ));
+            assert_mca_eq(&with_chunk, &diff.patch(&without_chunk));
+            assert_mca_eq(&without_chunk, &diff.revert(&with_chunk));
+        });
+    }
+    #[test]
+    fn test_display() {
+        let old = fastnbt::nbt!({
+            "Status": "minecraft:full",
+            "sections": [{ "Y": 0_i8 }, { "Y": 1_i8 }],
+            "block_entities": [
+                { "id": "minecraft:chest", "x": 3, "y": 64, "z": -7, "Items": [] }
+            ]
+        });
+        let new = fastnbt::nbt!({
+            "Status": "minecraft:full",
+            "sections": [{ "Y": 0_i8, "BlockLight": [1_i8] }, { "Y": 1_i8 }],
+            "block_entities": [
+                { "id": "minecraft:furnace", "x": 0, "y": 64, "z": 0 },
+                { "id": "minecraft:furnace", "x": 1, "y": 64, "z": 0 }
+            ]
+        });
+        let diff =
+            ChunkWithTimestampDiff::UpdateSmall(60, RegionChunkDiff::from_compare(&old, &new));
+        assert_eq!(
+            diff.to_string(),
+            "UpdateSmall(+60, sections=1, be=+2/-1/~0)"
+        );
+        let diff = ChunkWithTimestampDiff::<RegionChunkDiff>::UpdateLarge(-5);
+        assert_eq!(diff.to_string(), "UpdateLarge(-5)");
+    }
+    #[test]
+    fn test_stats() {
+        let mut chunks = vec![ChunkWithTimestampDiff::BothNotExist; CHUNKS_PER_REGION];
+        chunks[0] = ChunkWithTimestampDiff::CreateLarge(100);
+        chunks[1] = ChunkWithTimestampDiff::UpdateLarge(60);
+        chunks[2] = ChunkWithTimestampDiff::UpdateWithNoChange;
+        chunks[32] = ChunkWithTimestampDiff::DeleteLarge(-100);
+        let diff = MCADiff::<RegionChunkDiff>::from_chunks(chunks);
+        let stats = DiffStats {
+            created: 1,
+            deleted: 1,
+            updated: 1,
+            unchanged: 1,
+        };
+        assert_eq!(diff.stats(), stats);
+        assert_eq!(
+            diff.render_stats(),
+            "created: 1, deleted: 1, updated: 1, unchanged: 1\n\
+             (0, 0) CreateLarge(100)\n\
+             (1, 0) UpdateLarge(+60)\n\
+             (0, 1) DeleteLarge(100)"
+        );
+    }
+    #[test]
+    fn test_squash_coverage() {
+        let mut base = vec![ChunkWithTimestampDiff::BothNotExist; CHUNKS_PER_REGION];
+        let mut squashing = vec![ChunkWithTimestampDiff::BothNotExist; CHUNKS_PER_REGION];
+        base[0] = ChunkWithTimestampDiff::CreateLarge(100);
+        squashing[0] = ChunkWithTimestampDiff::UpdateLarge(60);
+        base[1] = ChunkWithTimestampDiff::UpdateLarge(60);
+        squashing[1] = ChunkWithTimestampDiff::DeleteLarge(-160);
+        base[2] = ChunkWithTimestampDiff::UpdateWithNoChange;
+        squashing[2] = ChunkWithTimestampDiff::UpdateWithNoChange;
+        let base = MCADiff::<RegionChunkDiff>::from_chunks(base);
+        let squashing = MCADiff::<RegionChunkDiff>::from_chunks(squashing);
+
+        let coverage = MCADiff::squash_coverage(&base, &squashing);
+        assert_eq!(coverage.total(), CHUNKS_PER_REGION);
+        assert_eq!(coverage.impossible, 0);
+        assert_eq!(
+            coverage.combinations[&("BothNotExist", "BothNotExist")],
+            CHUNKS_PER_REGION - 3
+        );
+        assert_eq!(coverage.combinations[&("CreateLarge", "UpdateLarge")], 1);
+        assert_eq!(
+            coverage.to_string(),
+            "BothNotExist -> BothNotExist: 1021\n\
+             CreateLarge -> UpdateLarge: 1\n\
+             UpdateLarge -> DeleteLarge: 1\n\
+             UpdateWithNoChange -> UpdateWithNoChange: 1\n\
+             impossible: 0"
+        );
+        with_test_config(TEST_CONFIG.clone(), || {
+            MCADiff::from_squash(&base, &squashing)
+        });
+
+        // squashing a diff onto itself creates a chunk that already exists
+        let coverage = MCADiff::squash_coverage(&base, &base);
+        assert_eq!(coverage.total(), CHUNKS_PER_REGION);
+        assert_eq!(coverage.impossible, 1);
+    }
+    #[test]
+    fn test_panic_names_chunk() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let old = get_test_chunk_by_xz(
+                &PathBuf::from(
+                    "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+                ),
+                25,
+                29,
+            )
+            .unwrap();
+            // a chunk without block entities or sections can't be diffed
+            let new = ChunkWithTimestamp {
+                timestamp: old.timestamp + 1,
+                nbt: ChunkNbt::Small(nbt_serde::ser(&Value::Compound(Default::default()))),
+            };
+            let region = |chunk: &ChunkWithTimestamp| {
+                let mut builder = MCABuilder::new();
+                builder.set_chunk(25, 29, chunk);
+                builder.to_bytes(CompressionType::Zlib).unwrap()
+            };
+            let (old, new) = (region(&old), region(&new));
+
+            let payload =
+                catch_unwind(|| MCADiff::<RegionChunkDiff>::from_compare(&old, &new)).unwrap_err();
+            let msg = payload.downcast_ref::<String>().unwrap();
+            assert!(msg.starts_with("chunk (25, 29): "), "{}", msg);
+        });
+    }
+    #[test]
+    fn test_diff_large_chunk() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let small = get_test_chunk_by_xz(
+                &PathBuf::from(
+                    "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+                ),
+                25,
+                29,
+            )
+            .unwrap();
+            let large = ChunkWithTimestamp {
+                timestamp: small.timestamp + 60,
+                nbt: ChunkNbt::Large,
+            };
+            let large_later = ChunkWithTimestamp {
+                timestamp: small.timestamp + 120,
+                nbt: ChunkNbt::Large,
+            };
+            let region_with = |chunk| {
+                let mut builder = MCABuilder::new();
+                builder.set_chunk(25, 29, chunk);
+                builder.to_bytes(CompressionType::Zlib).unwrap()
+            };
+            let small = region_with(&small);
+            let large = region_with(&large);
+            let large_later = region_with(&large_later);
+            let i = 25 + 29 * 32;
+
+            for (old, new) in [(&small, &large), (&large, &small), (&large, &large_later)] {
+                let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(old, new);
+                assert!(!matches!(
+                    diff.chunks[i],
+                    ChunkWithTimestampDiff::UpdateSmall(..)
+                ));
+                assert_mca_eq(new, &diff.patch(old));
+                assert_mca_eq(old, &diff.revert(new));
+            }
+        });
+    }
+    #[test]
+    fn test_chunk_range_parse() {
+        assert_eq!(
+            "1,2-30,31".parse::<ChunkRange>(),
+            Ok(ChunkRange {
+                x0: 1,
+                z0: 2,
+                x1: 30,
+                z1: 31
+            })
+        );
+        assert!("0,0-32,0".parse::<ChunkRange>().is_err());
+        assert!("5,0-4,0".parse::<ChunkRange>().is_err());
+        assert!("0,0".parse::<ChunkRange>().is_err());
+        assert!("a,0-1,1".parse::<ChunkRange>().is_err());
+    }
+    #[test]
+    fn test_diff_within_range() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let old = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let new = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let range: ChunkRange = "8,8-23,23".parse().unwrap();
+
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare_within(&old, &new, &range);
+            for (i, x, z) in create_chunk_ixz_iter() {
+                if !range.contains(x, z) {
+                    assert!(matches!(
+                        diff.chunks[i],
+                        ChunkWithTimestampDiff::UpdateWithNoChange
+                            | ChunkWithTimestampDiff::BothNotExist
+                    ));
+                }
+            }
+
+            let mut reader_old = MCAReader::from_bytes(&old).unwrap();
+            let mut reader_new = MCAReader::from_bytes(&new).unwrap();
+            let expected_chunks: Vec<_> = create_chunk_ixz_iter()
+                .map(|(_, x, z)| {
+                    let reader = if range.contains(x, z) {
+                        &mut reader_new
+                    } else {
+                        &mut reader_old
+                    };
+                    reader.get_chunk(x, z).unwrap().cloned()
+                })
+                .collect();
+            let mut builder = MCABuilder::new();
+            for ((_, x, z), chunk) in create_chunk_ixz_iter().zip(&expected_chunks) {
+                if let Some(chunk) = chunk {
+                    builder.set_chunk(x, z, chunk);
+                }
+            }
+            let expected = builder.to_bytes(CompressionType::Zlib).unwrap();
+            assert_mca_eq(&expected, &diff.patch(&old));
+        });
+    }
+    #[test]
+    fn test_output_size_hint() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let files: Vec<_> = (11..=16)
+                .map(|day| fs::read(format!("{}/202505{}.mca", dir, day)).unwrap())
+                .collect();
+            for pair in files.windows(2) {
+                let (old, new) = (&pair[0], &pair[1]);
+                let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(old, new);
+                let hint = diff.output_size_hint(old);
+                let actual = diff.patch(old).len();
+                assert!(
+                    hint <= actual * 2 && actual <= hint * 2,
+                    "hint {} is too far from actual size {}",
+                    hint,
+                    actual
+                );
+            }
+        });
+    }
+    #[test]
+    fn test_diff_timestamp_past_i32_max() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let chunk = get_test_chunk_by_xz(
+                &PathBuf::from(
+                    "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+                ),
+                25,
+                29,
+            )
+            .unwrap();
+            let region_with_ts = |timestamp| {
+                let chunk = ChunkWithTimestamp {
+                    timestamp,
+                    nbt: chunk.nbt.clone(),
+                };
+                let mut builder = MCABuilder::new();
+                builder.set_chunk(25, 29, &chunk);
+                builder.to_bytes(CompressionType::Zlib).unwrap()
+            };
+            let empty = MCABuilder::new().to_bytes(CompressionType::Zlib).unwrap();
+            let v1 = region_with_ts(0x9000_0000);
+            let v2 = region_with_ts(0xA000_0000);
+            let i = 25 + 29 * 32;
+
+            let create: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&empty, &v1);
+            assert!(matches!(
+                create.chunks[i],
+                ChunkWithTimestampDiff::CreateSmall(..)
+            ));
+            assert_mca_eq(&v1, &create.patch(&empty));
+            assert_mca_eq(&empty, &create.revert(&v1));
+
+            let update: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v1, &v2);
+            assert!(matches!(
+                update.chunks[i],
+                ChunkWithTimestampDiff::UpdateSmall(..)
+            ));
+            assert_mca_eq(&v2, &update.patch(&v1));
+            assert_mca_eq(&v1, &update.revert(&v2));
+
+            let squashed = MCADiff::from_squash(&create, &update);
+            assert_mca_eq(&v2, &squashed.patch(&empty));
+            assert_mca_eq(&empty, &squashed.revert(&v2));
+        });
+    }
+    #[test]
+    fn test_diff_ignored_tags() {
+        let config = Config {
+            ignored_tags: vec!["InhabitedTime".to_string()],
+            ..TEST_CONFIG.clone()
+        };
+        with_test_config(config, || {
+            let chunk = get_test_chunk_by_xz(
+                &PathBuf::from(
+                    "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+                ),
+                25,
+                29,
+            )
+            .unwrap();
+            let ChunkNbt::Small(nbt) = &chunk.nbt else {
+                panic!("test chunk is not small");
+            };
+            let old_nbt = de(nbt);
+            let mut new_nbt = old_nbt.clone();
+            let Value::Compound(map) = &mut new_nbt else {
+                panic!("chunk nbt is not a compound");
+            };
+            let inhabited_time = match map.get("InhabitedTime") {
+                Some(Value::Long(t)) => *t,
+                _ => panic!("chunk has no InhabitedTime"),
+            };
+            map.insert(
+                "InhabitedTime".to_string(),
+                Value::Long(inhabited_time + 1000),
+            );
+
+            let mut normalized_nbt = new_nbt.clone();
+            copy_tags(&old_nbt, &mut normalized_nbt, &get_config().ignored_tags);
+            assert_eq!(
+                ser(RegionChunkDiff::from_compare(&old_nbt, &normalized_nbt)),
+                ser(RegionChunkDiff::from_compare(&old_nbt, &old_nbt))
+            );
+
+            let old_chunk = chunk.clone();
+            let new_chunk = ChunkWithTimestamp {
+                timestamp: chunk.timestamp + 60,
+                nbt: ChunkNbt::Small(crate::util::nbt_serde::ser(&new_nbt)),
+            };
+            let region_with = |chunk| {
+                let mut builder = MCABuilder::new();
+                builder.set_chunk(25, 29, chunk);
+                builder.to_bytes(CompressionType::Zlib).unwrap()
+            };
+            let old = region_with(&old_chunk);
+            let new = region_with(&new_chunk);
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&old, &new);
+            let patched = diff.patch(&old);
+            let reader = MCAReader::from_bytes(&patched).unwrap();
+            let patched_chunk = reader.get_chunk(25, 29).unwrap().unwrap();
+            assert_eq!(patched_chunk.timestamp, new_chunk.timestamp);
+            let ChunkNbt::Small(patched_nbt) = &patched_chunk.nbt else {
+                panic!("patched chunk is not small");
+            };
+            assert_eq!(de(patched_nbt), old_nbt);
+        });
+    }
+    #[test]
+    fn test_diff_skip_equal_chunks() {
+        let config = Config {
+            skip_equal_chunks: true,
+            ..TEST_CONFIG.clone()
+        };
+        with_test_config(config, || {
+            let chunk = get_test_chunk_by_xz(
+                &PathBuf::from(
+                    "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+                ),
+                25,
+                29,
+            )
+            .unwrap();
+            let touched = ChunkWithTimestamp {
+                timestamp: chunk.timestamp + 1,
+                nbt: chunk.nbt.clone(),
+            };
+            let region_with = |chunk| {
+                let mut builder = MCABuilder::new();
+                builder.set_chunk(25, 29, chunk);
+                builder.to_bytes(CompressionType::Zlib).unwrap()
+            };
+            let old = region_with(&chunk);
+            let new = region_with(&touched);
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&old, &new);
+            assert!(matches!(
+                diff.chunks[25 + 29 * 32],
+                ChunkWithTimestampDiff::UpdateWithNoChange
+            ));
+            assert_mca_eq(&old, &diff.patch(&old));
+        });
+
+        let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+        let v0 = fs::read(format!("{}/20250514.mca", dir)).unwrap();
+        let v1 = fs::read(format!("{}/20250515.mca", dir)).unwrap();
+        let patch_with = |skip_equal_chunks| {
+            let config = Config {
+                skip_equal_chunks,
+                ..TEST_CONFIG.clone()
+            };
+            with_test_config(config, || {
+                MCADiff::<RegionChunkDiff>::from_compare(&v0, &v1).patch(&v0)
+            })
+        };
+        let (skipped, full) = (patch_with(true), patch_with(false));
+        let skipped = MCAReader::from_bytes(&skipped).unwrap();
+        let full = MCAReader::from_bytes(&full).unwrap();
+        for (_, x, z) in create_chunk_ixz_iter() {
+            let nbt =
+                |reader: &MCAReader<_>| reader.get_chunk(x, z).unwrap().map(|c| c.nbt.clone());
+            assert_eq!(nbt(&skipped), nbt(&full), "chunk ({x}, {z})");
+        }
+    }
+    #[test]
+    fn test_diff_poi_regions() {
+        use crate::diff::chunk::PoiChunkDiff;
+        use fastnbt::nbt;
+
+        with_test_config(TEST_CONFIG.clone(), || {
+            let poi_chunk = |timestamp, sections| ChunkWithTimestamp {
+                timestamp,
+                nbt: ChunkNbt::Small(nbt_serde::ser(&nbt!({
+                    "DataVersion": 4189,
+                    "Sections": sections,
+                }))),
+            };
+            let home = poi_chunk(
+                1747000000,
+                nbt!({
+                    "4": {
+                        "Valid": 1_i8,
+                        "Records": [
+                            { "type": "minecraft:home", "pos": [I; 3, 70, 5], "free_tickets": 1 }
+                        ]
+                    }
+                }),
+            );
+            let claimed = poi_chunk(
+                1747000100,
+                nbt!({
+                    "4": {
+                        "Valid": 1_i8,
+                        "Records": [
+                            { "type": "minecraft:home", "pos": [I; 3, 70, 5], "free_tickets": 0 }
+                        ]
+                    }
+                }),
+            );
+            let portal = poi_chunk(
+                1747000100,
+                nbt!({
+                    "-1": {
+                        "Valid": 1_i8,
+                        "Records": [
+                            { "type": "minecraft:nether_portal", "pos": [I; 40, -10, 8], "free_tickets": 0 }
+                        ]
+                    }
+                }),
+            );
+            let mut builder = MCABuilder::new();
+            builder.set_chunk(0, 0, &home);
+            let old = builder.to_bytes(CompressionType::Zlib).unwrap();
+            let mut builder = MCABuilder::new();
+            builder.set_chunk(0, 0, &claimed);
+            builder.set_chunk(2, 0, &portal);
+            let new = builder.to_bytes(CompressionType::Zlib).unwrap();
+
+            let diff: MCADiff<PoiChunkDiff> = MCADiff::from_compare(&old, &new);
+            assert_mca_eq(&new, &diff.patch(&old));
+            assert_mca_eq(&old, &diff.revert(&new));
+        });
+    }
+    #[test]
+    fn test_diff_timestamp_only() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let chunk = get_test_chunk_by_xz(
+                &PathBuf::from(
+                    "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+                ),
+                25,
+                29,
+            )
+            .unwrap();
+            let touched = |secs| ChunkWithTimestamp {
+                timestamp: chunk.timestamp + secs,
+                nbt: chunk.nbt.clone(),
+            };
+            let region_with = |chunk| {
+                let mut builder = MCABuilder::new();
+                builder.set_chunk(25, 29, chunk);
+                builder.to_bytes(CompressionType::Zlib).unwrap()
+            };
+            let (v1, v2) = (touched(60), touched(90));
+            let (v0, v1, v2) = (region_with(&chunk), region_with(&v1), region_with(&v2));
+            let timestamp = |region: &Vec<u8>| {
+                let reader = MCAReader::from_bytes(region).unwrap();
+                reader.get_chunk(25, 29).unwrap().unwrap().timestamp
+            };
+
+            let diff_v01: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+            assert!(matches!(
+                diff_v01.chunks[25 + 29 * 32],
+                ChunkWithTimestampDiff::TimestampOnly(60)
+            ));
+            assert_eq!(timestamp(&diff_v01.patch(&v0)), chunk.timestamp + 60);
+            assert_mca_eq(&v1, &diff_v01.patch(&v0));
+            assert_mca_eq(&v0, &diff_v01.revert(&v1));
+
+            let diff_v12: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v1, &v2);
+            let squashed = MCADiff::from_squash(&diff_v01, &diff_v12);
+            assert!(matches!(
+                squashed.chunks[25 + 29 * 32],
+                ChunkWithTimestampDiff::TimestampOnly(90)
+            ));
+            assert_mca_eq(&v2, &squashed.patch(&v0));
+        });
+    }
+    #[test]
+    fn test_compare_timestamps() {
+        let chunk = get_test_chunk_by_xz(
+            &PathBuf::from("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca"),
+            25,
+            29,
+        )
+        .unwrap();
+        let ChunkNbt::Small(nbt) = &chunk.nbt else {
+            panic!("test chunk should be small");
+        };
+        let mut value = nbt_serde::de(nbt);
+        let Value::Compound(kv) = &mut value else {
+            panic!("chunk should be a compound");
+        };
+        kv.insert("InhabitedTime".to_string(), Value::Long(114514));
+        // saved at the same time, but with other content
+        let changed = ChunkWithTimestamp {
+            timestamp: chunk.timestamp,
+            nbt: ChunkNbt::Small(nbt_serde::ser(&value)),
+        };
+        let region_with = |chunk| {
+            let mut builder = MCABuilder::new();
+            builder.set_chunk(25, 29, chunk);
+            builder.to_bytes(CompressionType::Zlib).unwrap()
+        };
+        let (v0, v1) = (region_with(&chunk), region_with(&changed));
+
+        let diff: MCADiff<RegionChunkDiff> =
+            with_test_config(TEST_CONFIG.clone(), || MCADiff::from_compare(&v0, &v1));
+        assert!(!diff.affects(25, 29));
+
+        let config = Config {
+            compare_timestamps: false,
+            ..TEST_CONFIG.clone()
+        };
+        with_test_config(config, || {
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+            assert!(matches!(
+                diff.chunks[25 + 29 * 32],
+                ChunkWithTimestampDiff::UpdateSmall(0, _)
+            ));
+            assert_mca_eq(&v1, &diff.patch(&v0));
+            assert_mca_eq(&v0, &diff.revert(&v1));
+
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v0);
+            assert!(!diff.affects(25, 29));
+        });
+    }
+    #[test]
+    fn test_diff_undecodable_chunk() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let chunk = get_test_chunk_by_xz(
+                &PathBuf::from(
+                    "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+                ),
+                25,
+                29,
+            )
+            .unwrap();
+            // 0xff is not a tag type fastnbt knows
+            let garbage = |secs, byte| ChunkWithTimestamp {
+                timestamp: chunk.timestamp + secs,
+                nbt: ChunkNbt::Small(vec![0xff, byte, 0, 0]),
+            };
+            let region_with = |chunk| {
+                let mut builder = MCABuilder::new();
+                builder.set_chunk(25, 29, chunk);
+                builder.to_bytes(CompressionType::Zlib).unwrap()
+            };
+            let (v1, v2) = (garbage(60, 1), garbage(90, 2));
+            let (v0, v1, v2) = (region_with(&chunk), region_with(&v1), region_with(&v2));
+
+            let diff_v01: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+            assert!(matches!(
+                diff_v01.chunks[25 + 29 * 32],
+                ChunkWithTimestampDiff::RawSmall(60, _)
+            ));
+            assert_mca_eq(&v1, &diff_v01.patch(&v0));
+            assert_mca_eq(&v0, &diff_v01.revert(&v1));
+
+            let diff_v12: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v1, &v2);
+            let squashed = MCADiff::from_squash(&diff_v01, &diff_v12);
+            assert!(matches!(
+                squashed.chunks[25 + 29 * 32],
+                ChunkWithTimestampDiff::RawSmall(90, _)
+            ));
+            assert_mca_eq(&v2, &squashed.patch(&v0));
+            assert_mca_eq(&v0, &squashed.revert(&v2));
+        });
+    }
+    #[test]
+    fn test_revert_standalone() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let chunk = get_test_chunk_by_xz(
+                &PathBuf::from(
+                    "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+                ),
+                25,
+                29,
+            )
+            .unwrap();
+            let mut builder = MCABuilder::new();
+            builder.set_chunk(25, 29, &chunk);
+            let with_chunk = builder.to_bytes(CompressionType::Zlib).unwrap();
+            let without_chunk = MCABuilder::new().to_bytes(CompressionType::Zlib).unwrap();
+
+            let delete: MCADiff<RegionChunkDiff> =
+                MCADiff::from_compare(&with_chunk, &without_chunk);
+            assert_mca_eq(&with_chunk, &delete.revert_standalone(None).unwrap());
+
+            let mut later = chunk.clone();
+            later.timestamp += 60;
+            let mut builder = MCABuilder::new();
+            builder.set_chunk(25, 29, &later);
+            let with_later_chunk = builder.to_bytes(CompressionType::Zlib).unwrap();
+            let update: MCADiff<RegionChunkDiff> =
+                MCADiff::from_compare(&with_chunk, &with_later_chunk);
+            assert!(matches!(
+                update.revert_standalone(None),
+                Err(MCAError::NewChunkRequired { x: 25, z: 29 })
+            ));
+            assert_mca_eq(
+                &with_chunk,
+                &update.revert_standalone(Some(&with_later_chunk)).unwrap(),
+            );
+        });
+    }
+    #[test]
+    fn test_patch_without_compression() {
+        let config = Config {
+            compression_type: CompressionType::No,
+            ..TEST_CONFIG.clone()
+        };
+        with_test_config(config, || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let old = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let new = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&old, &new);
+            let patched = diff.patch(&old);
+            assert_mca_eq(&new, &patched);
+
+            let mut present = 0;
+            for i in 0..1024 {
+                let location = &patched[i * 4..i * 4 + 4];
+                let sector_offset = u32::from_be_bytes([0, location[0], location[1], location[2]]);
+                if sector_offset != 0 {
+                    let compression_magic = patched[sector_offset as usize * SECTOR_SIZE + 4];
+                    assert_eq!(
+                        compression_magic & !LARGE_FLAG,
+                        CompressionType::No.to_magic()
+                    );
+                    present += 1;
+                }
+            }
+            assert!(present > 0);
+        });
+    }
+    #[test]
+    fn test_diff_from_empty() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let new =
+                fs::read("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca")
+                    .unwrap();
+            let empty = MCABuilder::new().to_bytes(CompressionType::Zlib).unwrap();
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&empty, &new);
+            assert!(diff.chunks.iter().all(|chunk| matches!(
+                chunk,
+                ChunkWithTimestampDiff::BothNotExist
+                    | ChunkWithTimestampDiff::CreateSmall(..)
+                    | ChunkWithTimestampDiff::CreateLarge(..)
+            )));
+            assert_mca_eq(&new, &diff.patch(&empty));
+            assert_mca_eq(&empty, &diff.revert(&new));
+        });
+    }
+    #[test]
+    fn test_diff_serial_matches_parallel() {
+        let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+        let old = fs::read(format!("{}/20250515.mca", dir)).unwrap();
+        let new = fs::read(format!("{}/20250516.mca", dir)).unwrap();
+        let diff_with = |threads| {
+            let config = Config {
+                threads,
+                ..TEST_CONFIG.clone()
+            };
+            with_test_config(config, || {
+                ser(MCADiff::<RegionChunkDiff>::from_compare(&old, &new))
+            })
+        };
+        assert_eq!(diff_with(1), diff_with(16));
+    }
+    #[test]
+    fn test_merge_disjoint() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let old = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let new = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let north: ChunkRange = "0,0-31,15".parse().unwrap();
+            let south: ChunkRange = "0,16-31,31".parse().unwrap();
+
+            let diff_north: MCADiff<RegionChunkDiff> =
+                MCADiff::from_compare_within(&old, &new, &north);
+            let diff_south: MCADiff<RegionChunkDiff> =
+                MCADiff::from_compare_within(&old, &new, &south);
+            let merged = MCADiff::merge_disjoint(&diff_north, &diff_south).unwrap();
+            assert_mca_eq(&new, &merged.patch(&old));
+            assert_mca_eq(&old, &merged.revert(&new));
+
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&old, &new);
+            assert!(matches!(
+                MCADiff::merge_disjoint(&diff, &diff),
+                Err(MCAError::OverlappingChunk { .. })
+            ));
+        });
+    }
+    #[test]
+    fn test_try_squash_incompatible() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let base = vec![ChunkWithTimestampDiff::BothNotExist; CHUNKS_PER_REGION];
+            let mut squashing = vec![ChunkWithTimestampDiff::BothNotExist; CHUNKS_PER_REGION];
+            // deleting chunks that don't exist after the base diff
+            for (x, z) in [(7, 9), (3, 5)] {
+                squashing[x + REGION_DIM * z] = ChunkWithTimestampDiff::DeleteLarge(-1);
+            }
+            let base: MCADiff<RegionChunkDiff> = MCADiff::from_chunks(base);
+            let squashing: MCADiff<RegionChunkDiff> = MCADiff::from_chunks(squashing);
+
+            match MCADiff::try_squash(&base, &squashing) {
+                Err(MCAError::SquashFailed { x, z, reason }) => {
+                    assert_eq!((x, z), (3, 5));
+                    assert!(reason.contains("delete large"), "{}", reason);
+                }
+                other => panic!("expected SquashFailed, got {:?}", other.map(|_| ())),
+            }
+            let valid = MCADiff::try_squash(&base, &base).unwrap();
+            assert!(valid.chunks.iter().all(|chunk| chunk.is_unchanged()));
+        });
+    }
+    #[test]
+    fn test_diff_shuffled_chunks() {
+        let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+        let old = fs::read(format!("{}/20250515.mca", dir)).unwrap();
+        let new = fs::read(format!("{}/20250516.mca", dir)).unwrap();
+        let diff_with = |shuffle_seed| {
+            let config = Config {
+                shuffle_seed,
+                ..TEST_CONFIG.clone()
+            };
+            with_test_config(config, || {
+                ser(MCADiff::<RegionChunkDiff>::from_compare(&old, &new))
+            })
+        };
+        let diff = diff_with(None);
+        assert_eq!(diff, diff_with(Some(114514)));
+        assert_eq!(diff, diff_with(Some(1919810)));
+    }
+    #[test]
+    fn test_affects() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250514.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250515.mca", dir)).unwrap();
+            let v2 = fs::read(format!("{}/20250516.mca", dir)).unwrap();
+            let diff_v01: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+            let diff_v12: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v1, &v2);
+            let squashed = MCADiff::from_squash(&diff_v01, &diff_v12);
+            for diff in [&diff_v01, &diff_v12, &squashed] {
+                for (i, x, z) in create_chunk_ixz_iter() {
+                    let changed = !matches!(
+                        diff.chunks[i],
+                        ChunkWithTimestampDiff::BothNotExist
+                            | ChunkWithTimestampDiff::UpdateWithNoChange
+                    );
+                    assert_eq!(diff.affects(x, z), changed, "chunk ({x}, {z})");
+                }
+                let decoded: MCADiff<RegionChunkDiff> = serde::de(&ser(diff));
+                assert_eq!(decoded.affected, diff.affected);
+            }
+            assert!(create_chunk_ixz_iter().any(|(_, x, z)| squashed.affects(x, z)));
+        });
+    }
+    #[test]
+    fn test_decode_unchanged_region() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250514.mca", dir)).unwrap();
+            // a few bytes per chunk, but every chunk diff is claimed at its in-memory size
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v0);
+            let decoded: MCADiff<RegionChunkDiff> = serde::de(&ser(&diff));
+            assert_eq!(decoded.stats(), diff.stats());
+            assert_mca_eq(&v0, &decoded.patch(&v0));
+        });
+    }
 }
@@ -3,8 +3,9 @@ use fastnbt::Value;
 
 use crate::{
     compress::CompressionType,
-    diff::{Diff, base::BlobDiff},
-    util::nbt_serde::{de, ser},
+    config::get_config,
+    diff::{Diff, DiffError, TrySquash, base::BlobDiff},
+    util::nbt_serde::{copy_tags, de, ser},
 };
 
 #[derive(Debug, Clone, Encode, Decode)]
@@ -46,9 +47,10 @@ where
                 let old_nbt: Value = de(&CompressionType::Zlib
                     .decompress_all(old)
                     .expect("Failed to decompress old MCC file for update"));
-                let new_nbt: Value = de(&CompressionType::Zlib
+                let mut new_nbt: Value = de(&CompressionType::Zlib
                     .decompress_all(new)
                     .expect("Failed to decompress new MCC file for update"));
+                copy_tags(&old_nbt, &mut new_nbt, &get_config().ignored_tags);
                 Self::Update(D::from_compare(&old_nbt, &new_nbt))
             }
         }
@@ -58,37 +60,7 @@ where
     where
         Self: Sized,
     {
-        match (base, squashing) {
-            // Create -> Update => Create
-            (Self::Create(base_blob), Self::Update(squashing_chunk)) => {
-                let base_nbt = de(&base_blob.patch0());
-                let squashed_nbt = squashing_chunk.patch(&base_nbt);
-                Self::Create(BlobDiff::from_create(&ser(&squashed_nbt)))
-            }
-            // Create -> Delete => No Diff (panic because it shouldn't happen in practice)
-            (Self::Create(_), Self::Delete(_)) => {
-                panic!(
-                    "Squashing a Create then Delete diff results in no change, which is illogical for a single file diff."
-                )
-            }
-            // Update -> Update => Update
-            (Self::Update(base_chunk), Self::Update(squashing_chunk)) => {
-                Self::Update(D::from_squash(base_chunk, squashing_chunk))
-            }
-            // Update -> Delete => Delete
-            (Self::Update(base_chunk), Self::Delete(squashing_blob)) => {
-                let squashing_nbt = de(&squashing_blob.revert0());
-                let base_nbt = base_chunk.revert(&squashing_nbt);
-                Self::Delete(BlobDiff::from_delete(&ser(&base_nbt)))
-            }
-            // Delete -> Create => Update
-            (Self::Delete(base_blob), Self::Create(squashing_blob)) => {
-                let old_nbt = de(&base_blob.revert0());
-                let new_nbt = de(&squashing_blob.patch0());
-                Self::Update(D::from_compare(&old_nbt, &new_nbt))
-            }
-            _ => panic!("Invalid squash combination for MCCDiff"),
-        }
+        Self::try_squash(base, squashing).unwrap_or_else(|e| panic!("{}", e))
     }
 
     fn patch(&self, old: &Vec<u8>) -> Vec<u8> {
@@ -142,18 +114,58 @@ where
     }
 }
 
+impl<D> TrySquash<Vec<u8>> for MCCDiff<D>
+where
+    D: Diff<Value> + bincode::Decode<MCCDiff<D>>,
+{
+    fn try_squash(base: &Self, squashing: &Self) -> Result<Self, DiffError> {
+        Ok(match (base, squashing) {
+            // Create -> Update => Create
+            (Self::Create(base_blob), Self::Update(squashing_chunk)) => {
+                let base_nbt = de(&base_blob.patch0());
+                let squashed_nbt = squashing_chunk.patch(&base_nbt);
+                Self::Create(BlobDiff::from_create(&ser(&squashed_nbt)))
+            }
+            // Create -> Delete => No Diff (an error because it shouldn't happen in practice)
+            (Self::Create(_), Self::Delete(_)) => {
+                return Err(DiffError::NotSquashable(
+                    "a Create then Delete diff leaves no file to diff".to_string(),
+                ));
+            }
+            // Update -> Update => Update
+            (Self::Update(base_chunk), Self::Update(squashing_chunk)) => {
+                Self::Update(D::from_squash(base_chunk, squashing_chunk))
+            }
+            // Update -> Delete => Delete
+            (Self::Update(base_chunk), Self::Delete(squashing_blob)) => {
+                let squashing_nbt = de(&squashing_blob.revert0());
+                let base_nbt = base_chunk.revert(&squashing_nbt);
+                Self::Delete(BlobDiff::from_delete(&ser(&base_nbt)))
+            }
+            // Delete -> Create => Update
+            (Self::Delete(base_blob), Self::Create(squashing_blob)) => {
+                let old_nbt = de(&base_blob.revert0());
+                let new_nbt = de(&squashing_blob.patch0());
+                Self::Update(D::from_compare(&old_nbt, &new_nbt))
+            }
+            _ => {
+                return Err(DiffError::NotSquashable(
+                    "invalid squash combination for MCCDiff".to_string(),
+                ));
+            }
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{Config, LogConfig, with_test_config};
+    use crate::config::{Config, with_test_config};
     use crate::diff::chunk::RegionChunkDiff;
     use crate::util::test::assert_mcc_eq;
     use std::fs;
 
-    static TEST_CONFIG: Config = Config {
-        log_config: LogConfig::NoLog,
-        threads: 16,
-    };
+    static TEST_CONFIG: Config = Config::for_test();
 
     fn read_mcc_file(version: &str) -> Vec<u8> {
         let path = format!(
@@ -1,5 +1,6 @@
 mod mca;
 mod mcc;
 
-pub use mca::MCADiff;
+pub(crate) use mca::DECODE_FLOOR;
+pub use mca::{ChunkRange, DiffStats, MCADiff, SquashCoverage, parse_chunk_xz};
 pub use mcc::MCCDiff;
@@ -1,8 +1,10 @@
 pub mod base;
-pub mod file;
 pub mod chunk;
+pub mod export;
+pub mod file;
 
 use bincode::{Decode, Encode};
+use thiserror::Error;
 
 pub trait Diff<T>: Encode + Decode<Self> + Clone {
     fn from_compare(old: &T, new: &T) -> Self
@@ -14,3 +16,28 @@ pub trait Diff<T>: Encode + Decode<Self> + Clone {
     fn patch(&self, old: &T) -> T;
     fn revert(&self, new: &T) -> T;
 }
+
+#[derive(Error, Debug)]
+pub enum DiffError {
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+    #[error("Input does not match the diff: {0}")]
+    Mismatch(String),
+    #[error("Diff is patch-only and cannot be reverted")]
+    PatchOnly,
+    #[error("Diffs cannot be squashed: {0}")]
+    NotSquashable(String),
+}
+
+/// Fallible `patch` and `revert`, for inputs that may not be the ones the diff was computed
+/// from. Returns an error where `Diff` would panic or produce garbage.
+pub trait TryDiff<T>: Diff<T> {
+    fn try_patch(&self, old: &T) -> Result<T, DiffError>;
+    fn try_revert(&self, new: &T) -> Result<T, DiffError>;
+}
+
+/// Fallible `from_squash`, for diffs that may not be adjacent. Returns an error where
+/// `Diff::from_squash` would panic.
+pub trait TrySquash<T>: Diff<T> {
+    fn try_squash(base: &Self, squashing: &Self) -> Result<Self, DiffError>;
+}
@@ -5,22 +5,40 @@ pub mod logging;
 pub mod mca;
 pub mod util;
 
+use bincode::{Decode, Encode};
 use clap::{Args, Parser, Subcommand, ValueEnum};
+use fastnbt::Value;
 use std::{
+    any::Any,
+    cmp::Ordering,
+    fmt,
     fs::{self, File},
-    io::{Cursor, Write},
-    path::PathBuf,
+    io::{self, BufRead, BufWriter, Cursor, Write},
+    panic::{AssertUnwindSafe, catch_unwind},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
 };
+use thiserror::Error;
 
 use crate::{
     compress::CompressionType,
     config::{Config, LogConfig, init_config},
     diff::{
-        Diff,
-        chunk::{EntitiesChunkDiff, RegionChunkDiff},
-        file::{MCADiff, MCCDiff},
+        Diff, DiffError, TrySquash,
+        chunk::{EntitiesChunkDiff, PoiChunkDiff, RegionChunkDiff},
+        file::{ChunkRange, DiffStats, MCADiff, MCCDiff, parse_chunk_xz},
+    },
+    mca::{
+        ChunkNbt, ChunkWithTimestamp, MCABuilder, MCAError, MCAReader, is_region_truncated,
+        mca_chunks_eq, repack, validate_changed_chunks,
+    },
+    util::{
+        REGION_DIM,
+        nbt_serde::{self, to_snbt},
+        reference::{Ref, hash_ref},
+        serde::{de, ser, ser_into},
     },
-    util::serde::{de, ser},
 };
 
 #[derive(Debug, Parser)]
@@ -37,10 +55,71 @@ struct Cli {
     #[arg(short, long, default_value_t = 8)]
     threads: usize,
 
-    /// Compression type
+    /// Stack size in MiB of each worker thread. Raise it if deeply nested NBT, e.g. from mods,
+    /// overflows the stack
+    #[arg(long, value_name = "MIB", default_value_t = 8)]
+    thread_stack_size: usize,
+
+    /// Compression type of the diff file and of the chunks in patched/reverted files
     #[arg(short, long, default_value = "zlib")]
     compression_type: CompressionType,
 
+    /// Compression type of the diff file, overriding --compression-type
+    #[arg(long, value_name = "COMPRESSION_TYPE")]
+    diff_compression: Option<CompressionType>,
+
+    /// Compression type of the chunks in patched/reverted files, overriding --compression-type
+    #[arg(long, value_name = "COMPRESSION_TYPE")]
+    chunk_compression: Option<CompressionType>,
+
+    /// Dotted NBT tag path (e.g. `InhabitedTime`) to leave out of chunk diffs; patched chunks
+    /// keep the base's value. Can be repeated
+    #[arg(long = "ignore-tag", value_name = "PATH")]
+    ignore_tags: Vec<String>,
+
+    /// Times to re-read an input region file that is truncated or fails to read, e.g. because
+    /// the game is still saving it. Missing files are never retried
+    #[arg(long, default_value_t = 0)]
+    retry_read: u32,
+
+    /// Delay in milliseconds before each re-read
+    #[arg(long, default_value_t = 500)]
+    retry_delay: u64,
+
+    /// Process chunks in an order shuffled with this seed instead of the slowest first. Does
+    /// not change the output
+    #[arg(long, value_name = "SEED")]
+    shuffle_chunks: Option<u64>,
+
+    /// Record chunks that are byte-equal but saved at a different time as unchanged instead of
+    /// recording their new timestamp. Patched chunks then keep the old timestamp
+    #[arg(long)]
+    skip_equal_chunks: bool,
+
+    /// Take chunks saved at the same time as unchanged without comparing them. Set to false
+    /// for worlds whose chunk timestamps can't be trusted, e.g. after copying them around
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    compare_timestamps: bool,
+
+    /// Write the chunks of patched/reverted region files back to the sectors they had in the
+    /// input, keeping the bytes between them and copying unchanged chunks as stored, instead of
+    /// packing them
+    #[arg(long)]
+    preserve_empty_sectors: bool,
+
+    /// Combined size in bytes of the old and new data above which they are stored whole instead
+    /// of being diffed with Myers, bounding the time spent on large, dissimilar data
+    #[arg(long, value_name = "BYTES")]
+    algorithm_threshold: Option<usize>,
+
+    /// Size in KiB of the buffer output files are written through
+    #[arg(long, value_name = "KIB", default_value_t = 1024)]
+    output_buffer_size: usize,
+
+    /// Print a JSON object describing the result on completion, for scripts
+    #[arg(long)]
+    json_result: bool,
+
     /// Use verbose output (-vv very verbose, -vvv very verbose to file)
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
@@ -56,16 +135,65 @@ enum Commands {
     Revert(RevertArgs),
     /// Squashing two adjacent differences
     Squash(SquashArgs),
+    /// Print the NBT of a chunk in a region file as SNBT
+    DumpNbt(DumpNbtArgs),
+    /// Rewrite a region file with its chunks stored contiguously
+    Repack(RepackArgs),
+    /// Print how many chunks changed between two files, and how, without writing a diff file
+    DiffStat(DiffStatArgs),
 }
 
 #[derive(Debug, Args)]
 struct DiffArgs {
     /// Path to old file
-    old: String,
+    #[arg(required_unless_present = "stdin_manifest")]
+    old: Option<String>,
     /// Path to new file
-    new: String,
+    #[arg(required_unless_present = "stdin_manifest")]
+    new: Option<String>,
     /// Path to save diff file
-    diff: String,
+    #[arg(required_unless_present = "stdin_manifest")]
+    diff: Option<String>,
+    /// Read the jobs to diff from stdin, one `OLD NEW DIFF` line each, and run them all in this
+    /// process, reporting each job's result
+    #[arg(long, conflicts_with_all = ["old", "verify_after", "validate_input", "chunks", "from_empty", "split_bytes", "base_old"])]
+    stdin_manifest: bool,
+    /// Patch and revert the computed diff before writing it, aborting if the round-trip fails
+    #[arg(long)]
+    verify_after: bool,
+    /// Decode the NBT of every changed chunk of OLD and NEW before diffing, aborting with the
+    /// coordinates of the first one that fails (.mca files only)
+    #[arg(long)]
+    validate_input: bool,
+    /// Only compare the chunks within this inclusive box and treat the others as unchanged
+    /// (.mca files only)
+    #[arg(long, value_name = "X0,Z0-X1,Z1")]
+    chunks: Option<ChunkRange>,
+    /// Diff against an empty region instead of reading OLD, which must be given as `-`, so
+    /// that patching an empty file reconstructs NEW (.mca files only)
+    #[arg(long)]
+    from_empty: bool,
+    /// Write the diff file as parts of at most N bytes, named `<DIFF>.part<i>`, with a manifest
+    /// at DIFF. Other commands read the parts through the manifest
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    split_bytes: Option<u64>,
+    /// Reconstruct OLD, which must be given as `-`, by patching this file with --base-diff,
+    /// instead of reading it, so that only the first state of a chain needs to be kept
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "base_diff",
+        conflicts_with = "from_empty"
+    )]
+    base_old: Option<String>,
+    /// Diff file from --base-old to the state to diff against
+    #[arg(long, value_name = "PATH", requires = "base_old")]
+    base_diff: Option<String>,
+    /// Read OLD and NEW as zip archives, e.g. world backups, and diff the region file at this
+    /// path inside them, such as `world/region/r.0.0.mca`, without extracting it
+    #[cfg(feature = "zip")]
+    #[arg(long, value_name = "PATH", conflicts_with = "stdin_manifest")]
+    entry: Option<String>,
 }
 
 #[derive(Debug, Args)]
@@ -75,7 +203,29 @@ struct PatchArgs {
     /// Path to diff file
     diff: String,
     /// Path to save patched file
-    patched: String,
+    #[arg(required_unless_present = "in_place", conflicts_with = "in_place")]
+    patched: Option<String>,
+    /// Write the patched file over the old file, atomically
+    #[arg(long)]
+    in_place: bool,
+    /// Write the whole patched file, or only the NBT of the chunk given by --chunk, patched
+    /// without rebuilding the region (.mca files only)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Region, conflicts_with = "in_place")]
+    output_format: OutputFormat,
+    /// Chunk to write with --output-format nbt or snbt
+    #[arg(long, value_name = "X,Z", value_parser = parse_chunk_xz)]
+    chunk: Option<(usize, usize)>,
+}
+
+/// What `patch` writes.
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// The patched file
+    Region,
+    /// The uncompressed NBT of a single patched chunk
+    Nbt,
+    /// The NBT of a single patched chunk as SNBT
+    Snbt,
 }
 
 #[derive(Debug, Args)]
@@ -98,6 +248,32 @@ struct SquashArgs {
     squashed: String,
 }
 
+#[derive(Debug, Args)]
+struct DumpNbtArgs {
+    /// Path to region file
+    region: String,
+    /// Chunk x coordinate within the region
+    x: usize,
+    /// Chunk z coordinate within the region
+    z: usize,
+}
+
+#[derive(Debug, Args)]
+struct RepackArgs {
+    /// Path to region file
+    region: String,
+    /// Path to save repacked region file
+    repacked: String,
+}
+
+#[derive(Debug, Args)]
+struct DiffStatArgs {
+    /// Path to old file
+    old: String,
+    /// Path to new file
+    new: String,
+}
+
 #[derive(Debug, Clone, ValueEnum)]
 pub enum FileType {
     /// Minecraft Region File > region/*.mca
@@ -106,6 +282,8 @@ pub enum FileType {
     RegionMcc,
     /// Minecraft Entities File > entities/*.mca
     EntitiesMca,
+    /// Minecraft POI File > poi/*.mca
+    PoiMca,
 }
 
 static ERR_MSG_READ: &str = "Failed to read file";
@@ -113,143 +291,1870 @@ static ERR_MSG_CREATE: &str = "Failed to create file";
 static ERR_MSG_WRITE: &str = "Failed to write file";
 static ERR_MSG_COMPRESS: &str = "Failed to compress data";
 static ERR_MSG_DECOMPRESS: &str = "Failed to decompress data";
+static ERR_MSG_VERIFY: &str = "Diff verification failed, refusing to write diff file";
+static ERR_MSG_NOT_ADJACENT: &str = "Base diff does not end where squashing diff begins";
+
+/// Magic prefix of every diff file, followed by its format version and, optionally, the
+/// reference hashes of its old and new states.
+pub const DIFF_REF_MAGIC: &[u8; 8] = b"RDIFFREF";
+/// Version of the diff file format written by this build, stored after `DIFF_REF_MAGIC`. Bump
+/// it whenever serialized diffs change incompatibly. Files without the magic are taken as v0.
+///
+/// - v1: format version and, optionally, refs header
+pub const DIFF_FORMAT_VERSION: u16 = 1;
+
+/// A diff file written in a format this build cannot read.
+#[derive(Error, Debug)]
+pub enum UnsupportedDiffFormat {
+    #[error(
+        "diff was written by format v{found}, this build supports up to v{supported}; \
+         upgrade region-diff to read it"
+    )]
+    Newer { found: u16, supported: u16 },
+    #[error(
+        "diff was written by format v{found}, this build only supports v{supported}; \
+         recompute the diff or read it with an older region-diff"
+    )]
+    Older { found: u16, supported: u16 },
+    #[error("diff file header is truncated or corrupt")]
+    Corrupt,
+}
+
+/// Reference hashes of the old and new states a diff connects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffRefs {
+    pub old: Ref,
+    pub new: Ref,
+}
+
+impl DiffRefs {
+    pub fn from_states(old: &[u8], new: &[u8]) -> Self {
+        Self {
+            old: hash_ref(old),
+            new: hash_ref(new),
+        }
+    }
+    /// Refs of `base` squashed with `squashing`, or `None` if `base` does not end where
+    /// `squashing` begins.
+    pub fn squash(base: &Self, squashing: &Self) -> Option<Self> {
+        (base.new == squashing.old).then_some(Self {
+            old: base.old,
+            new: squashing.new,
+        })
+    }
+}
+
+/// Header recording the format version and `refs`, if any, in front of a serialized diff.
+fn refs_header(refs: Option<&DiffRefs>) -> Vec<u8> {
+    let header = [
+        DIFF_REF_MAGIC.as_slice(),
+        &DIFF_FORMAT_VERSION.to_be_bytes(),
+    ]
+    .concat();
+    match refs {
+        Some(refs) => [header.as_slice(), &[1], &refs.old, &refs.new].concat(),
+        None => [header.as_slice(), &[0]].concat(),
+    }
+}
+
+/// Prepend the format version and `refs` to a serialized diff.
+pub fn wrap_diff(refs: Option<&DiffRefs>, diff: Vec<u8>) -> Vec<u8> {
+    [refs_header(refs), diff].concat()
+}
+
+/// Write `diff` with its `refs` to `writer` as a compressed diff file, encoding straight into
+/// the compressor without buffering the whole serialized diff.
+pub fn write_diff<D: Encode>(
+    writer: &mut impl Write,
+    refs: Option<&DiffRefs>,
+    diff: D,
+    compression_type: &CompressionType,
+) {
+    compression_type
+        .compress_with(writer, |encoder| {
+            encoder.write_all(&refs_header(refs))?;
+            ser_into(diff, &mut &mut *encoder);
+            Ok(())
+        })
+        .expect(ERR_MSG_COMPRESS);
+}
+
+/// Split a diff file into its refs, if it has any, and the serialized diff. Fails if the file
+/// was written in another format than `DIFF_FORMAT_VERSION`, including files that predate the
+/// header.
+pub fn unwrap_diff(data: Vec<u8>) -> Result<(Option<DiffRefs>, Vec<u8>), UnsupportedDiffFormat> {
+    let version_end = DIFF_REF_MAGIC.len() + 2;
+    if data.len() < version_end || !data.starts_with(DIFF_REF_MAGIC) {
+        return Err(UnsupportedDiffFormat::Older {
+            found: 0,
+            supported: DIFF_FORMAT_VERSION,
+        });
+    }
+    let version = u16::from_be_bytes([data[DIFF_REF_MAGIC.len()], data[DIFF_REF_MAGIC.len() + 1]]);
+    match version.cmp(&DIFF_FORMAT_VERSION) {
+        Ordering::Greater => {
+            return Err(UnsupportedDiffFormat::Newer {
+                found: version,
+                supported: DIFF_FORMAT_VERSION,
+            });
+        }
+        Ordering::Less => {
+            return Err(UnsupportedDiffFormat::Older {
+                found: version,
+                supported: DIFF_FORMAT_VERSION,
+            });
+        }
+        Ordering::Equal => (),
+    }
+    match data.get(version_end) {
+        Some(1) if data.len() >= version_end + 65 => {
+            let refs = &data[version_end + 1..version_end + 65];
+            let refs = DiffRefs {
+                old: refs[..32].try_into().unwrap(),
+                new: refs[32..].try_into().unwrap(),
+            };
+            Ok((Some(refs), data[version_end + 65..].to_vec()))
+        }
+        Some(0) => Ok((None, data[version_end + 1..].to_vec())),
+        _ => Err(UnsupportedDiffFormat::Corrupt),
+    }
+}
+
+/// Squash two diff files, keeping their refs. Fails if either file can't be read, if both carry
+/// refs and `base` does not end where `squashing` begins, or if the diffs themselves can't be
+/// squashed. Refs are dropped if either file was written without them.
+pub fn squash_diff_file<D: TrySquash<Vec<u8>> + Encode + Decode<()>>(
+    base: Vec<u8>,
+    squashing: Vec<u8>,
+) -> Result<Vec<u8>, DiffError> {
+    let (base_refs, base) =
+        unwrap_diff(base).map_err(|e| DiffError::InvalidInput(e.to_string()))?;
+    let (squashing_refs, squashing) =
+        unwrap_diff(squashing).map_err(|e| DiffError::InvalidInput(e.to_string()))?;
+    let refs = match (base_refs, squashing_refs) {
+        (Some(base_refs), Some(squashing_refs)) => Some(
+            DiffRefs::squash(&base_refs, &squashing_refs)
+                .ok_or_else(|| DiffError::NotSquashable(ERR_MSG_NOT_ADJACENT.to_string()))?,
+        ),
+        _ => {
+            log::info!("diff file without refs, skip adjacency check");
+            None
+        }
+    };
+    let base: D = de(&base);
+    let squashing: D = de(&squashing);
+    Ok(wrap_diff(
+        refs.as_ref(),
+        ser(D::try_squash(&base, &squashing)?),
+    ))
+}
+
+/// Read a file with `read`, retrying up to `retries` times after `delay` while reading fails or
+/// `is_complete` rejects the content. A missing file is returned as an error right away. After
+/// the last retry the content is returned as is and left to the parser to reject.
+fn read_with_retry(
+    mut read: impl FnMut() -> io::Result<Vec<u8>>,
+    is_complete: impl Fn(&[u8]) -> bool,
+    retries: u32,
+    delay: Duration,
+) -> io::Result<Vec<u8>> {
+    let mut attempt = 0;
+    loop {
+        let result = read();
+        let retry_reason = match &result {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return result,
+            Err(e) => e.to_string(),
+            Ok(bytes) if !is_complete(bytes) => "file is truncated".to_string(),
+            Ok(_) => return result,
+        };
+        if attempt >= retries {
+            return result;
+        }
+        attempt += 1;
+        log::info!(
+            "{}, retrying ({}/{}) in {:?}...",
+            retry_reason,
+            attempt,
+            retries,
+            delay
+        );
+        thread::sleep(delay);
+    }
+}
+
+/// Create the output file at `path`, written through a buffer of `buffer_size` bytes so that the
+/// many small writes of the compressors don't each cost a syscall. Flush it when done, as errors
+/// are lost when a `BufWriter` flushes on drop.
+fn create_output(path: &str, buffer_size: usize) -> io::Result<BufWriter<File>> {
+    Ok(BufWriter::with_capacity(buffer_size, File::create(path)?))
+}
+
+/// Replace the file at `path` with `data` by writing a temporary file next to it and renaming it
+/// over `path`, so that readers see either the old or the new file and never a partial one.
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(data)?;
+    file.as_file().sync_all()?;
+    file.persist(path)?;
+    Ok(())
+}
+
+/// Magic bytes of the footer ending every diff file written by this build, followed by the
+/// CRC32 of the bytes before the footer.
+pub const DIFF_CHECKSUM_MAGIC: &[u8; 8] = b"RDIFFSUM";
+
+fn checksum_footer(crc: u32) -> Vec<u8> {
+    [DIFF_CHECKSUM_MAGIC.as_slice(), &crc.to_be_bytes()].concat()
+}
+
+/// Strip the checksum footer from a diff file, failing if the file doesn't match it or has no
+/// footer, e.g. because it is truncated. Diffs of the formats this build reads always have one.
+pub fn strip_checksum(mut data: Vec<u8>) -> io::Result<Vec<u8>> {
+    let footer_len = DIFF_CHECKSUM_MAGIC.len() + 4;
+    if data.len() < footer_len || &data[data.len() - footer_len..][..8] != DIFF_CHECKSUM_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "diff file has no checksum footer, it is truncated or not a diff file",
+        ));
+    }
+    let body_len = data.len() - footer_len;
+    let expected = u32::from_be_bytes(data[body_len + 8..].try_into().unwrap());
+    if crc32fast::hash(&data[..body_len]) != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "diff file does not match its checksum, it is corrupt",
+        ));
+    }
+    data.truncate(body_len);
+    Ok(data)
+}
+
+/// Diff file being written to a temporary file next to its path, which `commit` ends with a
+/// checksum footer and renames to the path, so that a crash never leaves a partial diff file
+/// behind.
+struct DiffOutput {
+    file: BufWriter<tempfile::NamedTempFile>,
+    hasher: crc32fast::Hasher,
+    path: PathBuf,
+}
+
+impl DiffOutput {
+    fn create(path: &str, buffer_size: usize) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        Ok(Self {
+            file: BufWriter::with_capacity(buffer_size, tempfile::NamedTempFile::new_in(dir)?),
+            hasher: crc32fast::Hasher::new(),
+            path,
+        })
+    }
+    fn commit(mut self) -> io::Result<()> {
+        self.file
+            .write_all(&checksum_footer(self.hasher.finalize()))?;
+        let file = self.file.into_inner().map_err(|e| e.into_error())?;
+        file.as_file().sync_all()?;
+        file.persist(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Write for DiffOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Read the file at `entry` inside the zip archive at `archive` into memory.
+#[cfg(feature = "zip")]
+fn read_zip_entry(archive: &str, entry: &str) -> io::Result<Vec<u8>> {
+    use std::io::Read;
+    use zip::{ZipArchive, result::ZipError};
+
+    let mut archive = ZipArchive::new(File::open(archive)?).map_err(io::Error::other)?;
+    let mut file = archive.by_name(entry).map_err(|e| match e {
+        ZipError::FileNotFound => {
+            io::Error::new(io::ErrorKind::NotFound, format!("no entry {}", entry))
+        }
+        e => io::Error::other(e),
+    })?;
+    let mut data = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Decompress a whole region file stored gzip-wrapped (e.g. `r.0.0.mca.gz` from a backup tool),
+/// and return any other file as is. A region header can't start with the gzip magic, as that would
+/// place the first chunk gigabytes into the file.
+fn ungzip_region(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        log::debug!("region file is gzip-wrapped, decompressing...");
+        CompressionType::Gzip
+            .decompress_all(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    } else {
+        Ok(data)
+    }
+}
+
+/// Check whether two files of `file_type` have the same content.
+fn is_same_content(file_type: &FileType, a: &Vec<u8>, b: &Vec<u8>) -> bool {
+    match file_type {
+        FileType::RegionMca | FileType::EntitiesMca | FileType::PoiMca => {
+            mca_chunks_eq(a, b).expect("Failed to parse mca file")
+        }
+        FileType::RegionMcc => {
+            if a.is_empty() || b.is_empty() {
+                return a.is_empty() && b.is_empty();
+            }
+            let a = CompressionType::Zlib
+                .decompress_all(a)
+                .expect(ERR_MSG_DECOMPRESS);
+            let b = CompressionType::Zlib
+                .decompress_all(b)
+                .expect(ERR_MSG_DECOMPRESS);
+            nbt_serde::de(&a) == nbt_serde::de(&b)
+        }
+    }
+}
+
+/// Check that `diff` patches `old` to `new` and reverts `new` to `old`.
+fn verify_diff<D: Diff<Vec<u8>>>(
+    file_type: &FileType,
+    diff: &D,
+    old: &Vec<u8>,
+    new: &Vec<u8>,
+) -> bool {
+    log::info!("verifying...");
+    #[allow(unused_mut)]
+    let mut patched = diff.patch(old);
+    #[cfg(any(test, feature = "fault-injection"))]
+    if fault_injection::break_verify() {
+        // as if the diff lost every chunk
+        patched = Vec::new();
+    }
+    is_same_content(file_type, &patched, new) && is_same_content(file_type, &diff.revert(new), old)
+}
+
+/// Hooks that break results on purpose, to exercise the checks that should catch them.
+#[cfg(any(test, feature = "fault-injection"))]
+pub mod fault_injection {
+    use std::cell::Cell;
+
+    thread_local! {
+        static BREAK_VERIFY: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Make `--verify-after` on this thread see a patched file that differs from NEW.
+    pub fn set_break_verify(enabled: bool) {
+        BREAK_VERIFY.with(|b| b.set(enabled));
+    }
+    pub(crate) fn break_verify() -> bool {
+        BREAK_VERIFY.with(|b| b.get())
+    }
+}
+
+/// What the commands need from the diff of a file type beyond `Diff`. Diffs of .mca files work
+/// chunk by chunk, while those of .mcc files hold a single chunk and support none of this.
+trait FileDiff: TrySquash<Vec<u8>> + Encode + Decode<()> {
+    /// Diff of the chunks in `range` only.
+    fn from_compare_within(old: &Vec<u8>, new: &Vec<u8>, range: &ChunkRange) -> Self;
+    /// Patch only the chunk at (`x`, `z`), giving `None` if the patched chunk does not exist.
+    fn patch_chunk(&self, old: &[u8], x: usize, z: usize) -> Option<ChunkWithTimestamp>;
+    /// Chunk change counts.
+    fn chunk_stats(&self) -> Option<DiffStats>;
+    /// Chunk change counts rendered with the changed chunks.
+    fn render_chunk_stats(&self) -> Option<String>;
+}
+
+impl<D> FileDiff for MCADiff<D>
+where
+    D: Diff<Value>
+        + Send
+        + Sync
+        + fmt::Display
+        + Encode
+        + Decode<()>
+        + Decode<MCADiff<D>>
+        + 'static,
+{
+    fn from_compare_within(old: &Vec<u8>, new: &Vec<u8>, range: &ChunkRange) -> Self {
+        MCADiff::from_compare_within(old, new, range)
+    }
+    fn patch_chunk(&self, old: &[u8], x: usize, z: usize) -> Option<ChunkWithTimestamp> {
+        MCADiff::patch_chunk(self, old, x, z)
+    }
+    fn chunk_stats(&self) -> Option<DiffStats> {
+        Some(self.stats())
+    }
+    fn render_chunk_stats(&self) -> Option<String> {
+        Some(self.render_stats())
+    }
+}
+
+impl<D> FileDiff for MCCDiff<D>
+where
+    D: Diff<Value> + Encode + Decode<()> + Decode<MCCDiff<D>>,
+{
+    fn from_compare_within(_: &Vec<u8>, _: &Vec<u8>, _: &ChunkRange) -> Self {
+        panic!("--chunks only applies to .mca files")
+    }
+    fn patch_chunk(&self, _: &[u8], _: usize, _: usize) -> Option<ChunkWithTimestamp> {
+        panic!("--output-format nbt or snbt only applies to .mca files")
+    }
+    fn chunk_stats(&self) -> Option<DiffStats> {
+        None
+    }
+    fn render_chunk_stats(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Evaluate `$body` with `$D` standing for the diff type of files of `$file_type`.
+macro_rules! with_diff_type {
+    ($file_type:expr, $D:ident => $body:expr) => {
+        match $file_type {
+            FileType::RegionMca => {
+                type $D = MCADiff<RegionChunkDiff>;
+                $body
+            }
+            FileType::RegionMcc => {
+                type $D = MCCDiff<RegionChunkDiff>;
+                $body
+            }
+            FileType::EntitiesMca => {
+                type $D = MCADiff<EntitiesChunkDiff>;
+                $body
+            }
+            FileType::PoiMca => {
+                type $D = MCADiff<PoiChunkDiff>;
+                $body
+            }
+        }
+    };
+}
+
+/// Diff `old` against `new`, only the chunks in `range` if given, and check that the diff
+/// patches and reverts them if `verify`.
+fn compare_verified<D: FileDiff>(
+    file_type: &FileType,
+    old: &Vec<u8>,
+    new: &Vec<u8>,
+    range: Option<&ChunkRange>,
+    verify: bool,
+) -> D {
+    let diff = match range {
+        Some(range) => D::from_compare_within(old, new, range),
+        None => D::from_compare(old, new),
+    };
+    if verify {
+        assert!(
+            verify_diff(file_type, &diff, old, new),
+            "{}",
+            ERR_MSG_VERIFY
+        );
+    }
+    diff
+}
+
+/// Diff `old` against `new` and write the diff file, with its refs, to `writer`.
+fn write_compared_diff(
+    file_type: &FileType,
+    writer: &mut impl Write,
+    old: &Vec<u8>,
+    new: &Vec<u8>,
+    compression_type: &CompressionType,
+) {
+    let refs = DiffRefs::from_states(old, new);
+    with_diff_type!(file_type, D => {
+        let diff: D = compare_verified(file_type, old, new, None, false);
+        write_diff(writer, Some(&refs), diff, compression_type)
+    })
+}
+
+/// Patch `old` with the serialized `diff`, giving the whole patched file, or the NBT of the
+/// chunk at `chunk` in `output_format`.
+fn patch_output<D: FileDiff>(
+    diff: &Vec<u8>,
+    old: &Vec<u8>,
+    output_format: &OutputFormat,
+    chunk: Option<(usize, usize)>,
+) -> Vec<u8> {
+    let diff: D = de(diff);
+    match output_format {
+        OutputFormat::Region => diff.patch(old),
+        OutputFormat::Nbt | OutputFormat::Snbt => {
+            let (x, z) = chunk.expect("--chunk is required with --output-format nbt or snbt");
+            let nbt = patched_chunk_nbt(diff.patch_chunk(old, x, z), x, z);
+            match output_format {
+                OutputFormat::Snbt => to_snbt(&nbt_serde::de(&nbt)).into_bytes(),
+                _ => nbt,
+            }
+        }
+    }
+}
+
+/// Run the diff jobs listed in `manifest`, one `OLD NEW DIFF` line each; blank lines and lines
+/// starting with `#` are skipped. A failing job, including one that panics, does not stop the
+/// others. Returns each job's line with its outcome.
+fn run_diff_batch(
+    manifest: impl BufRead,
+    file_type: &FileType,
+    compression_type: &CompressionType,
+    read_input: impl Fn(&str) -> io::Result<Vec<u8>>,
+    buffer_size: usize,
+) -> io::Result<Vec<(String, Result<(), String>)>> {
+    let mut results = Vec::new();
+    for line in manifest.lines() {
+        let line = line?;
+        let job = line.trim();
+        if job.is_empty() || job.starts_with('#') {
+            continue;
+        }
+        let result = match job.split_whitespace().collect::<Vec<_>>()[..] {
+            [old, new, diff] => catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+                let old = read_input(old).map_err(|e| format!("cannot read {}: {}", old, e))?;
+                let new = read_input(new).map_err(|e| format!("cannot read {}: {}", new, e))?;
+                let mut output = DiffOutput::create(diff, buffer_size)
+                    .map_err(|e| format!("cannot create {}: {}", diff, e))?;
+                write_compared_diff(file_type, &mut output, &old, &new, compression_type);
+                output
+                    .commit()
+                    .map_err(|e| format!("cannot write {}: {}", diff, e))
+            }))
+            .unwrap_or_else(|payload| Err(panic_message(payload))),
+            _ => Err("expected `OLD NEW DIFF`".to_string()),
+        };
+        results.push((job.to_string(), result));
+    }
+    Ok(results)
+}
+
+/// The message a panic was raised with.
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .unwrap_or(&"unknown panic")
+            .to_string(),
+    }
+}
+
+/// Magic prefix of the manifest written in place of a diff file split with `--split-bytes`.
+pub const SPLIT_MANIFEST_MAGIC: &[u8; 8] = b"RDIFFSPL";
+
+/// Split a diff file into parts of at most `part_size` bytes. Returns the manifest to write in
+/// place of the file and the parts to write next to it as `<file>.part<i>`.
+pub fn split_diff(data: &[u8], part_size: usize) -> (Vec<u8>, Vec<&[u8]>) {
+    let parts: Vec<&[u8]> = data.chunks(part_size).collect();
+    let manifest = [
+        SPLIT_MANIFEST_MAGIC.as_slice(),
+        &(parts.len() as u32).to_be_bytes(),
+        &(data.len() as u64).to_be_bytes(),
+    ]
+    .concat();
+    (manifest, parts)
+}
+
+/// Reassemble a diff file split by `split_diff`, reading part `i` with `read_part(i)`. `data`
+/// that is not a manifest is returned as is.
+pub fn join_split_diff(
+    data: Vec<u8>,
+    mut read_part: impl FnMut(usize) -> io::Result<Vec<u8>>,
+) -> io::Result<Vec<u8>> {
+    let manifest_len = SPLIT_MANIFEST_MAGIC.len() + 12;
+    if data.len() != manifest_len || !data.starts_with(SPLIT_MANIFEST_MAGIC) {
+        return Ok(data);
+    }
+    let count = u32::from_be_bytes(data[8..12].try_into().unwrap()) as usize;
+    let size = u64::from_be_bytes(data[12..20].try_into().unwrap()) as usize;
+    let mut joined = Vec::with_capacity(size);
+    for i in 0..count {
+        joined.extend(read_part(i)?);
+    }
+    if joined.len() != size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "split diff parts add up to {} bytes, but the manifest expects {}",
+                joined.len(),
+                size
+            ),
+        ));
+    }
+    Ok(joined)
+}
+
+/// Diff `old` against `new` and squash the result onto the diff file `base_diff`, compressed with
+/// `compression_type`, which must end at `old`. Returns the diff file from the start of
+/// `base_diff` to `new`, compressed and checksummed the same way, without writing the diff from
+/// `old` to `new` in between. Refs are kept if `base_diff` has them.
+pub fn extend_diff<D: TrySquash<Vec<u8>> + Encode + Decode<()>>(
+    base_diff: Vec<u8>,
+    old: &Vec<u8>,
+    new: &Vec<u8>,
+    compression_type: &CompressionType,
+) -> Result<Vec<u8>, DiffError> {
+    let base_diff = strip_checksum(base_diff)
+        .map_err(|e| DiffError::InvalidInput(e.to_string()))
+        .and_then(|data| {
+            compression_type
+                .decompress_all(data)
+                .map_err(|e| DiffError::InvalidInput(e.to_string()))
+        })?;
+    let (base_refs, base) =
+        unwrap_diff(base_diff).map_err(|e| DiffError::InvalidInput(e.to_string()))?;
+    let refs = match base_refs {
+        Some(base_refs) if base_refs.new != hash_ref(old) => {
+            return Err(DiffError::NotSquashable(ERR_MSG_NOT_ADJACENT.to_string()));
+        }
+        Some(base_refs) => Some(DiffRefs {
+            old: base_refs.old,
+            new: hash_ref(new),
+        }),
+        None => None,
+    };
+    let base: D = de(&base);
+    let squashed = D::try_squash(&base, &D::from_compare(old, new))?;
+    let mut file = Vec::new();
+    write_diff(&mut file, refs.as_ref(), squashed, compression_type);
+    file.extend(checksum_footer(crc32fast::hash(&file)));
+    Ok(file)
+}
+
+/// Patch `base_old` with a decompressed diff file to reconstruct the state the diff ends at.
+/// Also returns the ref of that state recorded in the file, which differs from the hash of the
+/// reconstructed bytes whenever patching lays the file out differently.
+pub fn patch_diff_file<D: Diff<Vec<u8>> + Decode<()>>(
+    base_old: &Vec<u8>,
+    diff: Vec<u8>,
+) -> (Option<Ref>, Vec<u8>) {
+    let (refs, diff) = unwrap_diff(diff).unwrap_or_else(|e| panic!("{}", e));
+    if let Some(refs) = &refs {
+        assert!(
+            refs.old == hash_ref(base_old),
+            "base diff does not start at the base old file"
+        );
+    }
+    let diff: D = de(&diff);
+    (refs.map(|refs| refs.new), diff.patch(base_old))
+}
+
+/// NBT of `chunk`, the chunk at (`x`, `z`) of a patched region, failing if it doesn't exist or
+/// is stored in an external .mcc file.
+fn patched_chunk_nbt(chunk: Option<ChunkWithTimestamp>, x: usize, z: usize) -> Vec<u8> {
+    match chunk {
+        None => panic!("chunk ({}, {}) does not exist after patching", x, z),
+        Some(ChunkWithTimestamp {
+            nbt: ChunkNbt::Large,
+            ..
+        }) => panic!(
+            "chunk ({}, {}) is stored in an external .mcc file, patch that file instead",
+            x, z
+        ),
+        Some(ChunkWithTimestamp {
+            nbt: ChunkNbt::Small(nbt),
+            ..
+        }) => nbt,
+    }
+}
+
+/// Describe the chunk at (`x`, `z`) of a region file, printing its NBT as SNBT. Fails if the
+/// coordinates are outside of the region or the region can't be read.
+pub fn dump_chunk_nbt(region: &[u8], x: usize, z: usize) -> Result<String, MCAError> {
+    if x >= REGION_DIM || z >= REGION_DIM {
+        return Err(MCAError::OutOfRegion { x, z });
+    }
+    let reader = MCAReader::from_bytes(region)?;
+    Ok(match reader.get_chunk(x, z)? {
+        None => format!("chunk ({}, {}) does not exist", x, z),
+        Some(chunk) => match &chunk.nbt {
+            ChunkNbt::Large => format!(
+                "chunk ({}, {}) is stored in an external .mcc file, dump that file instead",
+                x, z
+            ),
+            ChunkNbt::Small(nbt) => format!(
+                "chunk ({}, {}) at timestamp {}\n{}",
+                x,
+                z,
+                chunk.timestamp,
+                to_snbt(&nbt_serde::de(nbt))
+            ),
+        },
+    })
+}
+
+/// Compare two region files in memory. Returns the change statistics of their diff, and those
+/// rendered with the changed chunks.
+pub fn diff_stat(old: &Vec<u8>, new: &Vec<u8>, filetype: &FileType) -> (DiffStats, String) {
+    assert!(
+        !matches!(filetype, FileType::RegionMcc),
+        "diff-stat only supports .mca files"
+    );
+    with_diff_type!(filetype, D => {
+        let diff = D::from_compare(old, new);
+        (diff.chunk_stats().unwrap(), diff.render_chunk_stats().unwrap())
+    })
+}
+
+/// Result of a subcommand, printed as a JSON object with `--json-result`.
+struct CliResult {
+    operation: &'static str,
+    /// Input paths with their sizes on disk when the result was created
+    inputs: Vec<(String, Option<u64>)>,
+    outputs: Vec<String>,
+    /// Chunk change counts, for diffs of .mca files
+    chunks: Option<DiffStats>,
+    /// Number of jobs run, in batch mode
+    jobs: Option<usize>,
+    /// Batch jobs that failed, with their errors
+    failed_jobs: Vec<(String, String)>,
+    /// Human-readable output, printed as is without `--json-result`
+    text: Option<String>,
+}
+
+/// Size of the file at `path`, or `None` for paths that aren't files, such as `-`.
+fn file_size(path: &str) -> Option<u64> {
+    fs::metadata(path).ok().map(|m| m.len())
+}
+
+impl CliResult {
+    /// Result of an operation on `inputs`, whose sizes are taken now: create it before writing
+    /// an output that may replace an input.
+    fn new(operation: &'static str, inputs: Vec<String>) -> Self {
+        Self {
+            operation,
+            inputs: inputs
+                .into_iter()
+                .map(|path| {
+                    let size = file_size(&path);
+                    (path, size)
+                })
+                .collect(),
+            outputs: Vec::new(),
+            chunks: None,
+            jobs: None,
+            failed_jobs: Vec::new(),
+            text: None,
+        }
+    }
+
+    /// Render the result as JSON, with the sizes of its outputs as they are on disk now. Paths
+    /// that aren't files, such as `-`, have no size and don't count towards the totals.
+    fn to_json(&self, elapsed: Duration) -> serde_json::Value {
+        let files = |sized: Vec<(&String, Option<u64>)>| {
+            let total: u64 = sized.iter().filter_map(|(_, bytes)| *bytes).sum();
+            let files: Vec<_> = sized
+                .into_iter()
+                .map(|(path, bytes)| serde_json::json!({ "path": path, "bytes": bytes }))
+                .collect();
+            (files, total)
+        };
+        let (inputs, input_bytes) = files(self.inputs.iter().map(|(p, b)| (p, *b)).collect());
+        let (outputs, output_bytes) =
+            files(self.outputs.iter().map(|p| (p, file_size(p))).collect());
+        let compression_ratio = match (self.outputs.is_empty(), input_bytes) {
+            (true, _) | (_, 0) => None,
+            _ => Some(output_bytes as f64 / input_bytes as f64),
+        };
+        let failed: Vec<_> = self
+            .failed_jobs
+            .iter()
+            .map(|(job, error)| serde_json::json!({ "job": job, "error": error }))
+            .collect();
+        serde_json::json!({
+            "ok": self.failed_jobs.is_empty(),
+            "operation": self.operation,
+            "inputs": inputs,
+            "outputs": outputs,
+            "input_bytes": input_bytes,
+            "output_bytes": output_bytes,
+            "compression_ratio": compression_ratio,
+            "elapsed_secs": elapsed.as_secs_f64(),
+            "chunks": self.chunks.as_ref().map(|stats| serde_json::json!({
+                "created": stats.created,
+                "deleted": stats.deleted,
+                "updated": stats.updated,
+                "unchanged": stats.unchanged,
+            })),
+            "jobs": self.jobs,
+            "failed": failed,
+            "text": self.text,
+        })
+    }
+
+    /// Render an operation that failed with `error` as JSON.
+    fn failure_json(operation: &str, error: &str, elapsed: Duration) -> serde_json::Value {
+        serde_json::json!({
+            "ok": false,
+            "operation": operation,
+            "error": error,
+            "elapsed_secs": elapsed.as_secs_f64(),
+        })
+    }
+}
+
+impl Commands {
+    /// Name of the subcommand, as given on the command line.
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::Diff(_) => "diff",
+            Commands::Patch(_) => "patch",
+            Commands::Revert(_) => "revert",
+            Commands::Squash(_) => "squash",
+            Commands::DumpNbt(_) => "dump-nbt",
+            Commands::Repack(_) => "repack",
+            Commands::DiffStat(_) => "diff-stat",
+        }
+    }
+}
+
+impl Cli {
+    fn diff_compression(&self) -> CompressionType {
+        self.diff_compression
+            .clone()
+            .unwrap_or_else(|| self.compression_type.clone())
+    }
+    fn chunk_compression(&self) -> CompressionType {
+        self.chunk_compression
+            .clone()
+            .unwrap_or_else(|| self.compression_type.clone())
+    }
+}
 
 pub fn main() {
+    let start = Instant::now();
     let cli = Cli::parse();
     init_config(Config {
         log_config: LogConfig::Verbose(cli.verbose),
         threads: cli.threads,
+        thread_stack_size: cli.thread_stack_size << 20,
+        compression_type: cli.chunk_compression(),
+        ignored_tags: cli.ignore_tags.clone(),
+        shuffle_seed: cli.shuffle_chunks,
+        skip_equal_chunks: cli.skip_equal_chunks,
+        compare_timestamps: cli.compare_timestamps,
+        preserve_empty_sectors: cli.preserve_empty_sectors,
+        algorithm_threshold: cli.algorithm_threshold.unwrap_or(usize::MAX),
     });
+    run(cli, start);
+}
+
+/// Run the command of `cli`, once the config is initialized.
+fn run(cli: Cli, start: Instant) {
+    let diff_compression = cli.diff_compression();
+    let chunk_compression = cli.chunk_compression();
+    let output_buffer_size = cli.output_buffer_size << 10;
     log::debug!("cli args: {:#?}", cli);
-    match cli.command {
-        Commands::Diff(args) => {
-            log::info!("reading old file...");
-            let old = fs::read(PathBuf::from(args.old)).expect("cannot find old file");
-            log::info!("reading new file...");
-            let new = fs::read(PathBuf::from(args.new)).expect("cannot find new file");
-            log::info!("comparing...");
-            let diff = match cli.filetype {
-                FileType::RegionMca => {
-                    let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&old, &new);
-                    ser(diff)
+    let read_input = |path: &str, file_type: Option<&FileType>| {
+        let is_region = matches!(
+            file_type,
+            Some(FileType::RegionMca) | Some(FileType::EntitiesMca) | Some(FileType::PoiMca)
+        );
+        read_with_retry(
+            || match file_type {
+                Some(_) => fs::read(path).and_then(ungzip_region),
+                None => fs::read(path),
+            },
+            |bytes| !is_region || !is_region_truncated(bytes),
+            cli.retry_read,
+            Duration::from_millis(cli.retry_delay),
+        )
+    };
+    let read_diff_input = |path: &str| {
+        let data = read_input(path, None)?;
+        join_split_diff(data, |i| read_input(&format!("{}.part{}", path, i), None))
+            .and_then(strip_checksum)
+    };
+    let operation = cli.command.name();
+    let result = catch_unwind(AssertUnwindSafe(|| -> Result<CliResult, String> {
+        Ok(match cli.command {
+            Commands::Diff(args) if args.stdin_manifest => {
+                let results = run_diff_batch(
+                    io::stdin().lock(),
+                    &cli.filetype,
+                    &diff_compression,
+                    |path| read_input(path, Some(&cli.filetype)),
+                    output_buffer_size,
+                )
+                .expect("Failed to read manifest from stdin");
+                let (mut inputs, mut outputs, mut failed_jobs) =
+                    (Vec::new(), Vec::new(), Vec::new());
+                let mut text = Vec::new();
+                for (job, result) in &results {
+                    match result {
+                        Ok(()) => {
+                            text.push(format!("ok: {}", job));
+                            let paths: Vec<_> = job.split_whitespace().map(String::from).collect();
+                            inputs.extend_from_slice(&paths[..2]);
+                            outputs.push(paths[2].clone());
+                        }
+                        Err(e) => {
+                            text.push(format!("failed: {}: {}", job, e));
+                            failed_jobs.push((job.clone(), e.clone()));
+                        }
+                    }
                 }
-                FileType::RegionMcc => {
-                    let diff: MCCDiff<RegionChunkDiff> = MCCDiff::from_compare(&old, &new);
-                    ser(diff)
+                log::info!("{} of {} jobs failed", failed_jobs.len(), results.len());
+                CliResult {
+                    outputs,
+                    jobs: Some(results.len()),
+                    failed_jobs,
+                    text: Some(text.join("\n")),
+                    ..CliResult::new("diff", inputs)
                 }
-                FileType::EntitiesMca => {
-                    let diff: MCADiff<EntitiesChunkDiff> = MCADiff::from_compare(&old, &new);
-                    ser(diff)
+            }
+            Commands::Diff(args) => {
+                let (old_path, new_path, diff_path) = (
+                    args.old.expect("OLD is required"),
+                    args.new.expect("NEW is required"),
+                    args.diff.expect("DIFF is required"),
+                );
+                let read_region = |path: &str| {
+                    #[cfg(feature = "zip")]
+                    if let Some(entry) = &args.entry {
+                        return read_zip_entry(path, entry).and_then(ungzip_region);
+                    }
+                    read_input(path, Some(&cli.filetype))
+                };
+                let mut old_ref = None;
+                let old = if args.from_empty {
+                    assert!(old_path == "-", "OLD must be `-` with --from-empty");
+                    assert!(
+                        !matches!(cli.filetype, FileType::RegionMcc),
+                        "--from-empty only applies to .mca files"
+                    );
+                    MCABuilder::new()
+                        .to_bytes(chunk_compression.clone())
+                        .expect("Failed to build an empty region")
+                } else if let (Some(base_old), Some(base_diff)) = (&args.base_old, &args.base_diff)
+                {
+                    assert!(old_path == "-", "OLD must be `-` with --base-old");
+                    log::info!("reading base old file...");
+                    let base_old = read_input(base_old, Some(&cli.filetype)).expect(ERR_MSG_READ);
+                    log::info!("reading base diff file...");
+                    let base_diff = read_diff_input(base_diff).expect(ERR_MSG_READ);
+                    let base_diff = diff_compression
+                        .decompress_all(base_diff)
+                        .expect(ERR_MSG_DECOMPRESS);
+                    log::info!("reconstructing old file...");
+                    let (base_new_ref, old) = with_diff_type!(cli.filetype, D => {
+                        patch_diff_file::<D>(&base_old, base_diff)
+                    });
+                    old_ref = base_new_ref;
+                    old
+                } else {
+                    log::info!("reading old file...");
+                    read_region(&old_path).expect("cannot find old file")
+                };
+                log::info!("reading new file...");
+                let new = read_region(&new_path).expect("cannot find new file");
+                if args.validate_input {
+                    assert!(
+                        !matches!(cli.filetype, FileType::RegionMcc),
+                        "--validate-input only applies to .mca files"
+                    );
+                    log::info!("validating input...");
+                    validate_changed_chunks(&old, &new, cli.compare_timestamps)
+                        .unwrap_or_else(|e| panic!("{}", e));
                 }
-            };
-            log::info!("writing diff file...");
-            let mut reader = Cursor::new(diff);
-            let mut writer = File::create(PathBuf::from(args.diff)).expect(ERR_MSG_CREATE);
-            cli.compression_type
-                .compress(&mut reader, &mut writer)
-                .expect(ERR_MSG_COMPRESS);
-            writer.flush().expect(ERR_MSG_WRITE);
-        }
-        Commands::Squash(args) => {
-            log::info!("reading base diff file...");
-            let base = fs::read(PathBuf::from(args.base)).expect(ERR_MSG_READ);
-            let base = cli
-                .compression_type
-                .decompress_all(base)
-                .expect(ERR_MSG_DECOMPRESS);
-            log::info!("reading squashing diff file...");
-            let squashing = fs::read(PathBuf::from(args.squashing)).expect(ERR_MSG_READ);
-            let squashing = cli
-                .compression_type
-                .decompress_all(squashing)
-                .expect(ERR_MSG_DECOMPRESS);
-            log::info!("squashing...");
-            let squashed = match cli.filetype {
-                FileType::RegionMca => {
-                    let base: MCADiff<RegionChunkDiff> = de(&base);
-                    let squashing: MCADiff<RegionChunkDiff> = de(&squashing);
-                    let squashed = MCADiff::from_squash(&base, &squashing);
-                    ser(squashed)
+                let mut refs = DiffRefs::from_states(&old, &new);
+                // keep the chain squashable with the base diff
+                if let Some(old_ref) = old_ref {
+                    refs.old = old_ref;
                 }
-                FileType::RegionMcc => {
-                    let base: MCCDiff<RegionChunkDiff> = de(&base);
-                    let squashing: MCCDiff<RegionChunkDiff> = de(&squashing);
-                    let squashed = MCCDiff::from_squash(&base, &squashing);
-                    ser(squashed)
+                // a split diff is buffered, then written in parts
+                let mut output = match args.split_bytes {
+                    Some(_) => None,
+                    None => Some(
+                        DiffOutput::create(&diff_path, output_buffer_size).expect(ERR_MSG_CREATE),
+                    ),
+                };
+                let mut buffer = Vec::new();
+                let mut writer: &mut dyn Write = match &mut output {
+                    Some(output) => output,
+                    None => &mut buffer,
+                };
+                log::info!("comparing...");
+                let chunks = with_diff_type!(cli.filetype, D => {
+                    let diff: D = compare_verified(
+                        &cli.filetype,
+                        &old,
+                        &new,
+                        args.chunks.as_ref(),
+                        args.verify_after,
+                    );
+                    let stats = diff.chunk_stats();
+                    log::info!("writing diff file...");
+                    write_diff(&mut writer, Some(&refs), diff, &diff_compression);
+                    stats
+                });
+                let mut outputs = vec![diff_path.clone()];
+                match (output, args.split_bytes) {
+                    (Some(output), _) => output.commit().expect(ERR_MSG_WRITE),
+                    (None, Some(part_size)) => {
+                        // the parts add up to a checksummed diff file, the manifest has no footer
+                        buffer.extend(checksum_footer(crc32fast::hash(&buffer)));
+                        let (manifest, parts) = split_diff(&buffer, part_size as usize);
+                        log::info!("writing {} diff file parts...", parts.len());
+                        for (i, part) in parts.iter().enumerate() {
+                            let part_path = format!("{}.part{}", diff_path, i);
+                            fs::write(&part_path, part).expect(ERR_MSG_WRITE);
+                            outputs.push(part_path);
+                        }
+                        write_atomically(Path::new(&diff_path), &manifest).expect(ERR_MSG_WRITE);
+                    }
+                    (None, None) => unreachable!(),
                 }
-                FileType::EntitiesMca => {
-                    let base: MCADiff<EntitiesChunkDiff> = de(&base);
-                    let squashing: MCADiff<EntitiesChunkDiff> = de(&squashing);
-                    let squashed = MCADiff::from_squash(&base, &squashing);
-                    ser(squashed)
+                let inputs = match (&args.base_old, &args.base_diff) {
+                    (Some(base_old), Some(base_diff)) => vec![base_old.clone(), base_diff.clone()],
+                    _ => vec![old_path],
+                };
+                CliResult {
+                    outputs,
+                    chunks,
+                    ..CliResult::new("diff", [inputs, vec![new_path]].concat())
                 }
-            };
-            log::info!("writing squashed diff file...");
-            let mut reader = Cursor::new(squashed);
-            let mut writer = File::create(PathBuf::from(args.squashed)).expect(ERR_MSG_CREATE);
-            cli.compression_type
-                .compress(&mut reader, &mut writer)
-                .expect(ERR_MSG_COMPRESS);
-            writer.flush().expect(ERR_MSG_WRITE);
-        }
-        Commands::Patch(args) => {
-            log::info!("reading old file...");
-            let old = fs::read(PathBuf::from(args.old)).expect(ERR_MSG_READ);
-            log::info!("reading diff file...");
-            let diff = fs::read(PathBuf::from(args.diff)).expect(ERR_MSG_READ);
-            let diff = cli
-                .compression_type
-                .decompress_all(diff)
-                .expect(ERR_MSG_DECOMPRESS);
-            log::info!("patching...");
-            let patched = match cli.filetype {
-                FileType::RegionMca => {
-                    let diff: MCADiff<RegionChunkDiff> = de(&diff);
-                    diff.patch(&old)
+            }
+            Commands::Squash(args) => {
+                log::info!("reading base diff file...");
+                let base = read_diff_input(&args.base).expect(ERR_MSG_READ);
+                let base = diff_compression
+                    .decompress_all(base)
+                    .expect(ERR_MSG_DECOMPRESS);
+                log::info!("reading squashing diff file...");
+                let squashing = read_diff_input(&args.squashing).expect(ERR_MSG_READ);
+                let squashing = diff_compression
+                    .decompress_all(squashing)
+                    .expect(ERR_MSG_DECOMPRESS);
+                log::info!("squashing...");
+                let squashed =
+                    with_diff_type!(cli.filetype, D => squash_diff_file::<D>(base, squashing))
+                        .map_err(|e| e.to_string())?;
+                log::info!("writing squashed diff file...");
+                let mut reader = Cursor::new(squashed);
+                let mut output =
+                    DiffOutput::create(&args.squashed, output_buffer_size).expect(ERR_MSG_CREATE);
+                diff_compression
+                    .compress(&mut reader, &mut output)
+                    .expect(ERR_MSG_COMPRESS);
+                output.commit().expect(ERR_MSG_WRITE);
+                CliResult {
+                    outputs: vec![args.squashed],
+                    ..CliResult::new("squash", vec![args.base, args.squashing])
                 }
-                FileType::RegionMcc => {
-                    let diff: MCCDiff<RegionChunkDiff> = de(&diff);
-                    diff.patch(&old)
+            }
+            Commands::Patch(args) => {
+                log::info!("reading old file...");
+                let old = read_input(&args.old, Some(&cli.filetype)).expect(ERR_MSG_READ);
+                log::info!("reading diff file...");
+                let diff = read_diff_input(&args.diff).expect(ERR_MSG_READ);
+                let diff = diff_compression
+                    .decompress_all(diff)
+                    .expect(ERR_MSG_DECOMPRESS);
+                let (_, diff) = unwrap_diff(diff).unwrap_or_else(|e| panic!("{}", e));
+                log::info!("patching...");
+                let patched = with_diff_type!(cli.filetype, D => {
+                    patch_output::<D>(&diff, &old, &args.output_format, args.chunk)
+                });
+                // an in-place patch replaces its input
+                let result = CliResult::new("patch", vec![args.old.clone(), args.diff.clone()]);
+                let patched_path = match args.patched {
+                    Some(patched_path) => {
+                        log::info!("writing patched file...");
+                        let mut writer =
+                            create_output(&patched_path, output_buffer_size).expect(ERR_MSG_CREATE);
+                        writer.write_all(&patched).expect(ERR_MSG_WRITE);
+                        writer.flush().expect(ERR_MSG_WRITE);
+                        patched_path
+                    }
+                    None => {
+                        log::info!("writing patched file in place...");
+                        write_atomically(Path::new(&args.old), &patched).expect(ERR_MSG_WRITE);
+                        args.old.clone()
+                    }
+                };
+                CliResult {
+                    outputs: vec![patched_path],
+                    ..result
                 }
-                FileType::EntitiesMca => {
-                    let diff: MCADiff<EntitiesChunkDiff> = de(&diff);
-                    diff.patch(&old)
+            }
+            Commands::Revert(args) => {
+                log::info!("reading new file...");
+                let new = read_input(&args.new, Some(&cli.filetype)).expect(ERR_MSG_READ);
+                log::info!("reading diff file...");
+                let diff = read_diff_input(&args.diff).expect(ERR_MSG_READ);
+                let diff = diff_compression
+                    .decompress_all(diff)
+                    .expect(ERR_MSG_DECOMPRESS);
+                let (_, diff) = unwrap_diff(diff).unwrap_or_else(|e| panic!("{}", e));
+                log::info!("reverting...");
+                let reverted = with_diff_type!(cli.filetype, D => de::<D>(&diff).revert(&new));
+                log::info!("writing reverted file...");
+                let mut writer =
+                    create_output(&args.reverted, output_buffer_size).expect(ERR_MSG_CREATE);
+                writer.write_all(&reverted).expect(ERR_MSG_WRITE);
+                writer.flush().expect(ERR_MSG_WRITE);
+                CliResult {
+                    outputs: vec![args.reverted],
+                    ..CliResult::new("revert", vec![args.new, args.diff])
                 }
-            };
-            log::info!("writing patched file...");
-            let mut writer = File::create(PathBuf::from(args.patched)).expect(ERR_MSG_CREATE);
-            writer.write_all(&patched).expect(ERR_MSG_WRITE);
-            writer.flush().expect(ERR_MSG_WRITE);
-        }
-        Commands::Revert(args) => {
-            log::info!("reading new file...");
-            let new = fs::read(PathBuf::from(args.new)).expect(ERR_MSG_READ);
-            log::info!("reading diff file...");
-            let diff = fs::read(PathBuf::from(args.diff)).expect(ERR_MSG_READ);
-            let diff = cli
-                .compression_type
-                .decompress_all(diff)
-                .expect(ERR_MSG_DECOMPRESS);
-            log::info!("reverting...");
-            let reverted = match cli.filetype {
-                FileType::RegionMca => {
-                    let diff: MCADiff<RegionChunkDiff> = de(&diff);
-                    diff.revert(&new)
+            }
+            Commands::DumpNbt(args) => {
+                log::info!("reading region file...");
+                let region = read_input(&args.region, Some(&cli.filetype)).expect(ERR_MSG_READ);
+                let dump = match cli.filetype {
+                    FileType::RegionMca | FileType::EntitiesMca | FileType::PoiMca => {
+                        dump_chunk_nbt(&region, args.x, args.z).map_err(|e| e.to_string())?
+                    }
+                    FileType::RegionMcc => {
+                        let nbt = CompressionType::Zlib
+                            .decompress_all(region)
+                            .expect(ERR_MSG_DECOMPRESS);
+                        to_snbt(&nbt_serde::de(&nbt))
+                    }
+                };
+                CliResult {
+                    text: Some(dump),
+                    ..CliResult::new("dump-nbt", vec![args.region])
                 }
-                FileType::RegionMcc => {
-                    let diff: MCCDiff<RegionChunkDiff> = de(&diff);
-                    diff.revert(&new)
+            }
+            Commands::Repack(args) => {
+                log::info!("reading region file...");
+                let region = read_input(&args.region, Some(&cli.filetype)).expect(ERR_MSG_READ);
+                log::info!("repacking...");
+                let repacked = match cli.filetype {
+                    FileType::RegionMca | FileType::EntitiesMca | FileType::PoiMca => {
+                        repack(&region, chunk_compression).expect("Failed to repack region file")
+                    }
+                    FileType::RegionMcc => panic!("repack only supports .mca files"),
+                };
+                log::info!("writing repacked file...");
+                let mut writer =
+                    create_output(&args.repacked, output_buffer_size).expect(ERR_MSG_CREATE);
+                writer.write_all(&repacked).expect(ERR_MSG_WRITE);
+                writer.flush().expect(ERR_MSG_WRITE);
+                CliResult {
+                    outputs: vec![args.repacked],
+                    text: Some(format!("{} -> {} bytes", region.len(), repacked.len())),
+                    ..CliResult::new("repack", vec![args.region])
                 }
-                FileType::EntitiesMca => {
-                    let diff: MCADiff<EntitiesChunkDiff> = de(&diff);
-                    diff.revert(&new)
+            }
+            Commands::DiffStat(args) => {
+                log::info!("reading old file...");
+                let old = read_input(&args.old, Some(&cli.filetype)).expect(ERR_MSG_READ);
+                log::info!("reading new file...");
+                let new = read_input(&args.new, Some(&cli.filetype)).expect(ERR_MSG_READ);
+                log::info!("comparing...");
+                let (stats, rendered) = diff_stat(&old, &new, &cli.filetype);
+                CliResult {
+                    chunks: Some(stats),
+                    text: Some(rendered),
+                    ..CliResult::new("diff-stat", vec![args.old, args.new])
                 }
-            };
-            log::info!("writing reverted file...");
-            let mut writer = File::create(PathBuf::from(args.reverted)).expect(ERR_MSG_CREATE);
-            writer.write_all(&reverted).expect(ERR_MSG_WRITE);
-            writer.flush().expect(ERR_MSG_WRITE);
+            }
+        })
+    }));
+    let result = match result {
+        Ok(result) => result,
+        Err(payload) if !cli.json_result => std::panic::resume_unwind(payload),
+        // the panic itself is reported on stderr, keep stdout parseable
+        Err(payload) => Err(panic_message(payload)),
+    };
+    let result = match result {
+        Ok(result) => result,
+        Err(error) => {
+            if cli.json_result {
+                println!(
+                    "{}",
+                    CliResult::failure_json(operation, &error, start.elapsed())
+                );
+            } else {
+                eprintln!("error: {}", error);
+            }
+            std::process::exit(1);
         }
+    };
+    if cli.json_result {
+        println!("{}", result.to_json(start.elapsed()));
+    } else if let Some(text) = &result.text {
+        println!("{}", text);
+    }
+    if !result.failed_jobs.is_empty() {
+        std::process::exit(1);
     }
     log::info!("success");
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::config::{Config, with_test_config};
+    use crate::mca::{LARGE_FLAG, SECTOR_SIZE};
+    use crate::util::{CHUNKS_PER_REGION, create_chunk_ixz_iter};
+
+    static TEST_CONFIG: Config = Config::for_test();
+
+    #[test]
+    fn test_verify_diff() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let v2 = fs::read(format!("{}/20250513.mca", dir)).unwrap();
+
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+            assert!(verify_diff(&FileType::RegionMca, &diff, &v0, &v1));
+            assert!(!verify_diff(&FileType::RegionMca, &diff, &v0, &v2));
+        });
+    }
+
+    #[test]
+    fn test_patch_in_place() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+
+            let tmp = tempfile::tempdir().unwrap();
+            let region = tmp.path().join("r.0.0.mca");
+            fs::write(&region, &v0).unwrap();
+            let old = fs::read(&region).unwrap();
+            write_atomically(&region, &diff.patch(&old)).unwrap();
+
+            assert!(mca_chunks_eq(&fs::read(&region).unwrap(), &v1).unwrap());
+            let entries: Vec<_> = fs::read_dir(tmp.path())
+                .unwrap()
+                .map(|e| e.unwrap().file_name())
+                .collect();
+            assert_eq!(entries, vec!["r.0.0.mca"]);
+        });
+    }
+
+    #[test]
+    fn test_patch_chunk_nbt() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+
+            let reader = MCAReader::from_bytes(&v1).unwrap();
+            let (x, z, expected) = create_chunk_ixz_iter()
+                .filter(|(_, x, z)| diff.affects(*x, *z))
+                .find_map(|(_, x, z)| match reader.get_chunk(x, z).unwrap() {
+                    Some(ChunkWithTimestamp {
+                        nbt: ChunkNbt::Small(nbt),
+                        ..
+                    }) => Some((x, z, nbt)),
+                    _ => None,
+                })
+                .unwrap();
+            let nbt = patched_chunk_nbt(diff.patch_chunk(&v0, x, z), x, z);
+            assert_eq!(nbt_serde::de(&nbt), nbt_serde::de(expected));
+        });
+    }
+
+    #[test]
+    fn test_diff_output_checksum() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("r.0.0.mca.diff");
+        let path_str = path.to_str().unwrap();
+        let data = (0..100_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        // crashing mid-write leaves only a temporary file, never a partial diff file
+        let mut output = DiffOutput::create(path_str, 1 << 10).unwrap();
+        output.write_all(&data[..data.len() / 2]).unwrap();
+        std::mem::forget(output);
+        assert!(!path.exists());
+
+        let mut output = DiffOutput::create(path_str, 1 << 10).unwrap();
+        output.write_all(&data).unwrap();
+        output.commit().unwrap();
+        let written = fs::read(&path).unwrap();
+        assert_eq!(strip_checksum(written.clone()).unwrap(), data);
+
+        let mut corrupt = written.clone();
+        corrupt[data.len() / 2] ^= 1;
+        assert!(strip_checksum(corrupt).is_err());
+        let truncated = written[..written.len() - 1].to_vec();
+        assert!(strip_checksum(truncated).is_err());
+        assert!(strip_checksum(data.clone()).is_err());
+    }
+
+    #[test]
+    fn test_json_result() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let inputs = vec![
+                format!("{}/20250514.mca", dir),
+                format!("{}/20250515.mca", dir),
+            ];
+            let tmp = tempfile::tempdir().unwrap();
+            let diff_path = tmp.path().join("r.0.0.mca.diff");
+            let diff_path = diff_path.to_str().unwrap().to_string();
+            let old = fs::read(&inputs[0]).unwrap();
+            let new = fs::read(&inputs[1]).unwrap();
+            let mut output = DiffOutput::create(&diff_path, 1 << 10).unwrap();
+            write_compared_diff(
+                &FileType::RegionMca,
+                &mut output,
+                &old,
+                &new,
+                &CompressionType::Zlib,
+            );
+            output.commit().unwrap();
+
+            let stats = MCADiff::<RegionChunkDiff>::from_compare(&old, &new).stats();
+            let result = CliResult {
+                outputs: vec![diff_path.clone()],
+                chunks: Some(stats.clone()),
+                ..CliResult::new("diff", inputs)
+            };
+            let json = result.to_json(Duration::from_millis(1500)).to_string();
+            let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+            let diff_size = fs::metadata(&diff_path).unwrap().len();
+            assert_eq!(parsed["ok"], true);
+            assert_eq!(parsed["operation"], "diff");
+            assert_eq!(parsed["output_bytes"], diff_size);
+            assert_eq!(parsed["outputs"][0]["bytes"], diff_size);
+            assert_eq!(parsed["input_bytes"], (old.len() + new.len()) as u64);
+            assert_eq!(
+                parsed["compression_ratio"],
+                diff_size as f64 / (old.len() + new.len()) as f64
+            );
+            assert_eq!(parsed["elapsed_secs"], 1.5);
+            assert_eq!(parsed["chunks"]["updated"], stats.updated);
+            assert_eq!(parsed["chunks"]["unchanged"], stats.unchanged);
+        });
+    }
+
+    #[test]
+    fn test_verify_after_refuses_broken_diff() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let tmp = tempfile::tempdir().unwrap();
+            let diff_path = tmp.path().join("r.0.0.mca.diff");
+            let cli = || {
+                Cli::try_parse_from([
+                    "region-diff",
+                    "region-mca",
+                    "diff",
+                    "--verify-after",
+                    &format!("{}/20250514.mca", dir),
+                    &format!("{}/20250515.mca", dir),
+                    diff_path.to_str().unwrap(),
+                ])
+                .unwrap()
+            };
+
+            fault_injection::set_break_verify(true);
+            let result = catch_unwind(AssertUnwindSafe(|| run(cli(), Instant::now())));
+            fault_injection::set_break_verify(false);
+            let msg = panic_message(result.unwrap_err());
+            assert!(msg.contains(ERR_MSG_VERIFY), "{}", msg);
+            assert!(fs::read_dir(tmp.path()).unwrap().next().is_none());
+
+            run(cli(), Instant::now());
+            assert!(diff_path.exists());
+        });
+    }
+
+    #[test]
+    fn test_diff_refuses_empty_input() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let tmp = tempfile::tempdir().unwrap();
+            let empty_path = tmp.path().join("r.0.0.mca");
+            fs::write(&empty_path, []).unwrap();
+            let diff_path = tmp.path().join("r.0.0.mca.diff");
+            let regions = [
+                format!("{}/20250514.mca", dir),
+                empty_path.to_str().unwrap().to_string(),
+            ];
+            for (old, new) in [(&regions[0], &regions[1]), (&regions[1], &regions[0])] {
+                let cli = Cli::try_parse_from([
+                    "region-diff",
+                    "region-mca",
+                    "diff",
+                    old,
+                    new,
+                    diff_path.to_str().unwrap(),
+                ])
+                .unwrap();
+                assert!(catch_unwind(AssertUnwindSafe(|| run(cli, Instant::now()))).is_err());
+                assert!(!diff_path.exists());
+            }
+        });
+    }
+
+    #[test]
+    fn test_json_result_sizes_and_failures() {
+        // input sizes are taken when the result is created, before an in-place write
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("r.0.0.mca");
+        let path = path.to_str().unwrap().to_string();
+        fs::write(&path, [0u8; 100]).unwrap();
+        let result = CliResult::new("patch", vec![path.clone()]);
+        fs::write(&path, [0u8; 40]).unwrap();
+        let result = CliResult {
+            outputs: vec![path],
+            failed_jobs: vec![("a b c".to_string(), "cannot read a".to_string())],
+            jobs: Some(2),
+            ..result
+        };
+        let parsed = result.to_json(Duration::ZERO);
+        assert_eq!(parsed["input_bytes"], 100);
+        assert_eq!(parsed["output_bytes"], 40);
+        assert_eq!(parsed["ok"], false);
+        assert_eq!(parsed["jobs"], 2);
+        assert_eq!(parsed["failed"][0]["job"], "a b c");
+        assert_eq!(parsed["failed"][0]["error"], "cannot read a");
+
+        let parsed = CliResult::failure_json("squash", "not adjacent", Duration::ZERO);
+        assert_eq!(parsed["ok"], false);
+        assert_eq!(parsed["operation"], "squash");
+        assert_eq!(parsed["error"], "not adjacent");
+    }
+
+    #[test]
+    fn test_extend_diff() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let v2 = fs::read(format!("{}/20250513.mca", dir)).unwrap();
+            let compression_type = CompressionType::Zlib;
+
+            let base: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+            let squashing: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v1, &v2);
+            let expected = ser(MCADiff::from_squash(&base, &squashing));
+            let mut base_file = Vec::new();
+            let refs = DiffRefs::from_states(&v0, &v1);
+            write_diff(&mut base_file, Some(&refs), base, &compression_type);
+            base_file.extend(checksum_footer(crc32fast::hash(&base_file)));
+
+            let extended = extend_diff::<MCADiff<RegionChunkDiff>>(
+                base_file.clone(),
+                &v1,
+                &v2,
+                &compression_type,
+            )
+            .unwrap();
+            let extended = compression_type
+                .decompress_all(strip_checksum(extended).unwrap())
+                .unwrap();
+            let (extended_refs, extended) = unwrap_diff(extended).unwrap();
+            assert_eq!(extended_refs, Some(DiffRefs::from_states(&v0, &v2)));
+            assert_eq!(extended, expected);
+
+            // the base diff must end where the new diff starts
+            assert!(matches!(
+                extend_diff::<MCADiff<RegionChunkDiff>>(base_file, &v0, &v2, &compression_type),
+                Err(DiffError::NotSquashable(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_patch_diff_file() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let v2 = fs::read(format!("{}/20250513.mca", dir)).unwrap();
+
+            let base: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+            let refs = DiffRefs::from_states(&v0, &v1);
+            let (old_ref, old) =
+                patch_diff_file::<MCADiff<RegionChunkDiff>>(&v0, wrap_diff(Some(&refs), ser(base)));
+            assert_eq!(old_ref, Some(refs.new));
+            let reconstructed: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&old, &v2);
+            let materialized: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v1, &v2);
+            assert_eq!(ser(reconstructed), ser(materialized));
+        });
+    }
+
+    #[test]
+    fn test_squash_diff_file_adjacency() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let v2 = fs::read(format!("{}/20250513.mca", dir)).unwrap();
+            let v3 = fs::read(format!("{}/20250514.mca", dir)).unwrap();
+            let diff_file = |old: &Vec<u8>, new: &Vec<u8>| {
+                let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(old, new);
+                wrap_diff(Some(&DiffRefs::from_states(old, new)), ser(diff))
+            };
+
+            let squashed = squash_diff_file::<MCADiff<RegionChunkDiff>>(
+                diff_file(&v0, &v1),
+                diff_file(&v1, &v2),
+            )
+            .expect("adjacent diffs should squash");
+            let (refs, squashed) = unwrap_diff(squashed).unwrap();
+            assert_eq!(refs, Some(DiffRefs::from_states(&v0, &v2)));
+            let squashed: MCADiff<RegionChunkDiff> = de(&squashed);
+            assert!(verify_diff(&FileType::RegionMca, &squashed, &v0, &v2));
+
+            let squashed = squash_diff_file::<MCADiff<RegionChunkDiff>>(
+                diff_file(&v0, &v1),
+                diff_file(&v2, &v3),
+            );
+            assert!(matches!(squashed, Err(DiffError::NotSquashable(_))));
+        });
+    }
+    #[test]
+    fn test_unwrap_diff_future_version() {
+        let refs = DiffRefs::from_states(b"old", b"new");
+        let mut file = wrap_diff(Some(&refs), vec![1, 2, 3]);
+        assert_eq!(
+            unwrap_diff(file.clone()).unwrap(),
+            (Some(refs), vec![1, 2, 3])
+        );
+
+        let version = DIFF_REF_MAGIC.len();
+        file[version..version + 2].copy_from_slice(&(DIFF_FORMAT_VERSION + 1).to_be_bytes());
+        let err = unwrap_diff(file.clone()).unwrap_err();
+        assert!(matches!(err, UnsupportedDiffFormat::Newer { .. }));
+        assert!(err.to_string().starts_with(&format!(
+            "diff was written by format v{}, this build supports up to v{}",
+            DIFF_FORMAT_VERSION + 1,
+            DIFF_FORMAT_VERSION
+        )));
+
+        file[version..version + 2].copy_from_slice(&(DIFF_FORMAT_VERSION - 1).to_be_bytes());
+        assert!(matches!(
+            unwrap_diff(file),
+            Err(UnsupportedDiffFormat::Older { .. })
+        ));
+
+        let file = wrap_diff(None, vec![1, 2, 3]);
+        assert_eq!(unwrap_diff(file.clone()).unwrap(), (None, vec![1, 2, 3]));
+        // diffs written before the header was always present have no magic
+        assert!(matches!(
+            unwrap_diff(vec![1, 2, 3]),
+            Err(UnsupportedDiffFormat::Older { found: 0, .. })
+        ));
+        assert!(matches!(
+            unwrap_diff(file[..version + 2].to_vec()),
+            Err(UnsupportedDiffFormat::Corrupt)
+        ));
+    }
+    #[test]
+    fn test_write_diff_streamed() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+            let refs = DiffRefs::from_states(&v0, &v1);
+
+            for compression_type in [
+                CompressionType::Gzip,
+                CompressionType::Zlib,
+                CompressionType::No,
+                CompressionType::LZ4,
+            ] {
+                let mut file = Vec::new();
+                write_diff(&mut file, Some(&refs), diff.clone(), &compression_type);
+                let (read_refs, read_diff) =
+                    unwrap_diff(compression_type.decompress_all(&file).unwrap()).unwrap();
+                assert_eq!(read_refs, Some(refs.clone()));
+                let read_diff: MCADiff<RegionChunkDiff> = de(&read_diff);
+                assert_eq!(ser(read_diff), ser(diff.clone()));
+            }
+        });
+    }
+    #[test]
+    fn test_diff_and_chunk_compression() {
+        // chunks are rebuilt with the config's compression type, independent of the diff file's
+        let config = Config {
+            compression_type: CompressionType::Zlib,
+            ..TEST_CONFIG.clone()
+        };
+        with_test_config(config, || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+            let mut file = Vec::new();
+            write_diff(&mut file, None, diff, &CompressionType::LZ4);
+
+            let (_, diff) =
+                unwrap_diff(CompressionType::LZ4.decompress_all(&file).unwrap()).unwrap();
+            let diff: MCADiff<RegionChunkDiff> = de(&diff);
+            let patched = diff.patch(&v0);
+            assert!(mca_chunks_eq(&v1, &patched).unwrap());
+            for i in 0..CHUNKS_PER_REGION {
+                let location = &patched[i * 4..i * 4 + 4];
+                let sector_offset = u32::from_be_bytes([0, location[0], location[1], location[2]]);
+                if sector_offset != 0 {
+                    let compression_magic = patched[sector_offset as usize * SECTOR_SIZE + 4];
+                    assert_eq!(
+                        compression_magic & !LARGE_FLAG,
+                        CompressionType::Zlib.to_magic()
+                    );
+                }
+            }
+        });
+    }
+    #[test]
+    fn test_split_diff() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let diff: MCADiff<RegionChunkDiff> = MCADiff::from_compare(&v0, &v1);
+            let mut file = Vec::new();
+            write_diff(&mut file, None, diff, &CompressionType::Zlib);
+
+            let (manifest, parts) = split_diff(&file, file.len().div_ceil(3));
+            assert_eq!(parts.len(), 3);
+            assert!(
+                parts
+                    .iter()
+                    .all(|part| part.len() <= file.len().div_ceil(3))
+            );
+            let joined = join_split_diff(manifest.clone(), |i| Ok(parts[i].to_vec())).unwrap();
+            assert_eq!(joined, file);
+            assert_eq!(
+                join_split_diff(file.clone(), |_| unreachable!()).unwrap(),
+                file
+            );
+            assert!(join_split_diff(manifest, |i| Ok(parts[i][1..].to_vec())).is_err());
+
+            let (_, diff) =
+                unwrap_diff(CompressionType::Zlib.decompress_all(&joined).unwrap()).unwrap();
+            let diff: MCADiff<RegionChunkDiff> = de(&diff);
+            assert!(mca_chunks_eq(&v1, &diff.patch(&v0)).unwrap());
+        });
+    }
+    #[test]
+    fn test_buffered_output() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let mut unbuffered = Vec::new();
+            write_compared_diff(
+                &FileType::RegionMca,
+                &mut unbuffered,
+                &v0,
+                &v1,
+                &CompressionType::Zlib,
+            );
+
+            let tmp = tempfile::tempdir().unwrap();
+            // a buffer smaller than most writes as well as one larger than the whole file
+            for buffer_size in [7, 1 << 20] {
+                let path = tmp.path().join(format!("{}.diff", buffer_size));
+                let mut file = create_output(path.to_str().unwrap(), buffer_size).unwrap();
+                write_compared_diff(
+                    &FileType::RegionMca,
+                    &mut file,
+                    &v0,
+                    &v1,
+                    &CompressionType::Zlib,
+                );
+                file.flush().unwrap();
+                assert_eq!(fs::read(&path).unwrap(), unbuffered);
+            }
+        });
+    }
+    #[cfg(feature = "zip")]
+    #[test]
+    fn test_read_zip_entry() {
+        use zip::{ZipWriter, write::SimpleFileOptions};
+
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let v0 = fs::read(format!("{}/20250511.mca", dir)).unwrap();
+            let v1 = fs::read(format!("{}/20250512.mca", dir)).unwrap();
+            let entry = "world/region/r.0.0.mca";
+
+            let tmp = tempfile::tempdir().unwrap();
+            let zip_with = |name: &str, region: &[u8]| {
+                let path = tmp.path().join(name);
+                let mut zip = ZipWriter::new(File::create(&path).unwrap());
+                zip.add_directory("world/region", SimpleFileOptions::default())
+                    .unwrap();
+                zip.start_file(entry, SimpleFileOptions::default()).unwrap();
+                zip.write_all(region).unwrap();
+                zip.finish().unwrap();
+                path.to_str().unwrap().to_string()
+            };
+            let (old_zip, new_zip) = (zip_with("old.zip", &v0), zip_with("new.zip", &v1));
+
+            let old = read_zip_entry(&old_zip, entry).unwrap();
+            let new = read_zip_entry(&new_zip, entry).unwrap();
+            let diff = |old: &Vec<u8>, new: &Vec<u8>| {
+                let mut diff = Vec::new();
+                write_compared_diff(
+                    &FileType::RegionMca,
+                    &mut diff,
+                    old,
+                    new,
+                    &CompressionType::Zlib,
+                );
+                diff
+            };
+            assert_eq!(diff(&old, &new), diff(&v0, &v1));
+            assert_eq!(
+                read_zip_entry(&old_zip, "world/region/r.1.0.mca")
+                    .unwrap_err()
+                    .kind(),
+                io::ErrorKind::NotFound
+            );
+        });
+    }
+
+    #[test]
+    fn test_run_diff_batch() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let tmp = tempfile::tempdir().unwrap();
+            let out = tmp.path();
+            let diffs = [out.join("0-1.diff"), out.join("1-2.diff")];
+            let manifest = format!(
+                "{dir}/20250511.mca {dir}/20250512.mca {}\n\n\
+                 {dir}/20250512.mca {dir}/20250513.mca {}\n\
+                 {dir}/missing.mca {dir}/20250513.mca {}\n",
+                diffs[0].display(),
+                diffs[1].display(),
+                out.join("missing.diff").display(),
+            );
+            let results = run_diff_batch(
+                manifest.as_bytes(),
+                &FileType::RegionMca,
+                &CompressionType::Zlib,
+                |path| fs::read(path),
+                1 << 10,
+            )
+            .unwrap();
+            assert_eq!(results.len(), 3);
+            assert!(results[0].1.is_ok() && results[1].1.is_ok());
+            assert!(results[2].1.is_err());
+
+            let versions = ["20250511", "20250512", "20250513"]
+                .map(|v| fs::read(format!("{}/{}.mca", dir, v)).unwrap());
+            for (i, path) in diffs.iter().enumerate() {
+                let file = strip_checksum(fs::read(path).unwrap()).unwrap();
+                let file = CompressionType::Zlib.decompress_all(file).unwrap();
+                let (refs, diff) = unwrap_diff(file).unwrap();
+                assert_eq!(
+                    refs,
+                    Some(DiffRefs::from_states(&versions[i], &versions[i + 1]))
+                );
+                let diff: MCADiff<RegionChunkDiff> = de(&diff);
+                assert!(mca_chunks_eq(&versions[i + 1], &diff.patch(&versions[i])).unwrap());
+            }
+        });
+    }
+
+    #[test]
+    fn test_dump_chunk_nbt() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let region =
+                fs::read("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca")
+                    .unwrap();
+            let dump = dump_chunk_nbt(&region, 25, 29).unwrap();
+            assert!(dump.contains("sections: ["));
+            assert!(dump.contains("block_entities: "));
+
+            let empty = MCABuilder::new().to_bytes(CompressionType::Zlib).unwrap();
+            assert!(
+                dump_chunk_nbt(&empty, 0, 0)
+                    .unwrap()
+                    .contains("does not exist")
+            );
+            assert!(matches!(
+                dump_chunk_nbt(&empty, 32, 0),
+                Err(MCAError::OutOfRegion { x: 32, z: 0 })
+            ));
+        });
+    }
+    #[test]
+    fn test_diff_stat() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let dir = "./resources/test-payload/region/mca/hairlessvillager-0";
+            let old = fs::read(format!("{}/20250514.mca", dir)).unwrap();
+            let new = fs::read(format!("{}/20250515.mca", dir)).unwrap();
+            let stats = MCADiff::<RegionChunkDiff>::from_compare(&old, &new).stats();
+            assert!(stats.updated > 0);
+            let (rendered_stats, rendered) = diff_stat(&old, &new, &FileType::RegionMca);
+            assert_eq!(rendered_stats, stats);
+            let mut lines = rendered.lines();
+            assert_eq!(lines.next().unwrap(), stats.to_string());
+            assert!(rendered.contains(&format!("updated: {},", stats.updated)));
+            assert_eq!(lines.count(), stats.created + stats.deleted + stats.updated);
+        });
+    }
+    #[test]
+    fn test_ungzip_region() {
+        let region =
+            fs::read("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca")
+                .unwrap();
+        let gzipped = CompressionType::Gzip.compress_all(&region).unwrap();
+        assert_ne!(gzipped, region);
+        assert_eq!(ungzip_region(gzipped).unwrap(), region);
+        assert_eq!(ungzip_region(region.clone()).unwrap(), region);
+        assert!(ungzip_region(vec![0x1f, 0x8b, 0, 0]).is_err());
+    }
+    #[test]
+    fn test_read_with_retry() {
+        let region =
+            fs::read("./resources/test-payload/region/mca/hairlessvillager-0/20250511.mca")
+                .unwrap();
+        let truncated = region[..region.len() / 2].to_vec();
+        assert!(is_region_truncated(&truncated));
+        assert!(!is_region_truncated(&region));
+
+        let mut attempts = 0;
+        let read = read_with_retry(
+            || {
+                attempts += 1;
+                Ok(if attempts == 1 {
+                    truncated.clone()
+                } else {
+                    region.clone()
+                })
+            },
+            |bytes| !is_region_truncated(bytes),
+            3,
+            Duration::ZERO,
+        )
+        .unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(read, region);
+
+        let mut attempts = 0;
+        let missing = read_with_retry(
+            || {
+                attempts += 1;
+                Err(io::Error::from(io::ErrorKind::NotFound))
+            },
+            |_| true,
+            3,
+            Duration::ZERO,
+        );
+        assert!(missing.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
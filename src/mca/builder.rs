@@ -1,29 +1,128 @@
-use super::{ChunkWithTimestamp, MCAError, SECTOR_SIZE};
+use super::{CRC_FOOTER_MAGIC, ChunkWithTimestamp, MCAError, SECTOR_SIZE};
 use crate::{
     compress::CompressionType,
     mca::{ChunkNbt, LARGE_FLAG},
-    util::{create_chunk_ixz_iter, parallel::parallel_process_with_cost_estimator},
+    util::{
+        CHUNKS_PER_REGION, REGION_DIM, create_chunk_ixz_iter,
+        parallel::parallel_process_with_cost_estimator,
+    },
 };
 
+/// Rough size of a stored chunk, used to preallocate rebuilt region files.
+pub fn estimated_chunk_size(compression_type: &CompressionType) -> usize {
+    match compression_type {
+        CompressionType::No => 0x40000, // 128KB
+        _ => 0x8000,                    // 16KB
+    }
+}
+
+/// Order in which `MCABuilder` lays out chunk sectors. The header records every chunk's
+/// offset, so any order is a valid region file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkOrder {
+    /// Chunk index order, i.e. row by row
+    #[default]
+    Index,
+    /// Morton (Z-order) curve, keeping spatially close chunks close in the file
+    ZOrder,
+    /// Oldest chunk first, ties in index order
+    Timestamp,
+}
+
+/// Interleave the bits of `x` and `z`, `x` in the lower bit of each pair.
+fn morton_code(x: usize, z: usize) -> usize {
+    (0..usize::BITS as usize / 2).fold(0, |code, bit| {
+        code | ((x >> bit) & 1) << (2 * bit) | ((z >> bit) & 1) << (2 * bit + 1)
+    })
+}
+
+/// Where the chunks of a region file are stored, see `MCAReader::layout`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectorLayout {
+    /// `(sector_offset, sector_count)` of each chunk in index order, zeros for absent chunks
+    pub(super) sectors: [(u32, u8); CHUNKS_PER_REGION],
+    /// Bytes of the file, whose last sector may not be padded
+    pub(super) raw: Vec<u8>,
+}
+
+impl SectorLayout {
+    /// Stored data of chunk `i` in the file: its length, compression type and compressed nbt.
+    fn stored_body(&self, i: usize) -> Option<&[u8]> {
+        let (offset, count) = self.sectors[i];
+        let start = offset as usize * SECTOR_SIZE;
+        let length = self.raw.get(start..start + 4)?;
+        let end = start + 4 + u32::from_be_bytes(length.try_into().ok()?) as usize;
+        (offset != 0 && end <= start + count as usize * SECTOR_SIZE)
+            .then(|| self.raw.get(start..end))
+            .flatten()
+    }
+}
+
 pub struct MCABuilder<'a> {
-    chunks: [Option<&'a ChunkWithTimestamp>; 1024],
+    chunks: [Option<&'a ChunkWithTimestamp>; CHUNKS_PER_REGION],
+    /// Chunks whose nbt is the same as in the file of the layout
+    unchanged: [bool; CHUNKS_PER_REGION],
+    crc_footer: bool,
+    chunk_order: ChunkOrder,
+    layout: Option<SectorLayout>,
 }
 impl<'a> MCABuilder<'a> {
     pub fn new() -> Self {
         Self {
-            chunks: [None; 1024],
+            chunks: [None; CHUNKS_PER_REGION],
+            unchanged: [false; CHUNKS_PER_REGION],
+            crc_footer: false,
+            chunk_order: ChunkOrder::Index,
+            layout: None,
         }
     }
+    /// Append a footer with the CRC32 of every chunk's stored data after the last chunk
+    /// sector, so corruption can be detected with `MCAReader::verify_crcs`.
+    ///
+    /// The footer is not part of the region file format, but vanilla ignores it since no
+    /// header entry points to it.
+    pub fn set_crc_footer(&mut self, enable: bool) {
+        self.crc_footer = enable;
+    }
+    /// Lay out chunk sectors in `order` instead of index order, which may help a compressor
+    /// applied to the whole file.
+    pub fn set_chunk_order(&mut self, order: ChunkOrder) {
+        self.chunk_order = order;
+    }
+    /// Write every chunk that still fits its sectors in `layout` back to those sectors, and
+    /// append the others in the chunk order. The bytes between and after the chunks are kept
+    /// from the original file.
+    ///
+    /// Rebuilding a region read by `MCAReader` with its layout gives the same bytes as the
+    /// original file when every chunk is marked with `set_chunk_unchanged`, or when
+    /// compressing the chunks gives the same bytes as stored.
+    pub fn set_layout(&mut self, layout: SectorLayout) {
+        self.layout = Some(layout);
+    }
     pub fn set_chunk(&mut self, x: usize, z: usize, chunk: &'a ChunkWithTimestamp) {
-        let i = x + z * 32;
+        let i = x + z * REGION_DIM;
         self.chunks[i] = Some(chunk);
     }
+    /// Mark the chunk set at (`x`, `z`) as having the same nbt as in the file of the layout, so
+    /// that its stored data is copied from there instead of compressing it again. Has no effect
+    /// without a layout.
+    pub fn set_chunk_unchanged(&mut self, x: usize, z: usize) {
+        self.unchanged[x + z * REGION_DIM] = true;
+    }
+    /// Stored data of chunk `i` to copy from the file of the layout, if it is unchanged.
+    fn stored_body(&self, i: usize) -> Option<&[u8]> {
+        match &self.layout {
+            Some(layout) if self.unchanged[i] => layout.stored_body(i),
+            _ => None,
+        }
+    }
     pub fn to_bytes(&self, compression_type: CompressionType) -> Result<Vec<u8>, MCAError> {
         // parallel compression
         let mut results = parallel_process_with_cost_estimator(
             create_chunk_ixz_iter(),
             |(i, x, z)| match self.chunks[*i] {
                 None => None,
+                Some(_) if self.stored_body(*i).is_some() => None,
                 Some(chunk) => match &chunk.nbt {
                     ChunkNbt::Large => None,
                     ChunkNbt::Small(nbt) => Some(compression_type.compress_all(nbt).map_err(|e| {
@@ -37,25 +136,35 @@ impl<'a> MCABuilder<'a> {
             },
             |(i, _, _)| match self.chunks[*i] {
                 None => 0,
+                Some(_) if self.stored_body(*i).is_some() => 0,
                 Some(chunk) => match &chunk.nbt {
                     ChunkNbt::Large => 0,
                     ChunkNbt::Small(nbt) => nbt.len(),
                 },
             },
         );
-        results.sort_by_key(|(ixz, ..)| ixz.0);
+        results.sort_by_key(|((i, x, z), ..)| match self.chunk_order {
+            ChunkOrder::Index => (0, *i),
+            ChunkOrder::ZOrder => (morton_code(*x, *z), *i),
+            ChunkOrder::Timestamp => (self.chunks[*i].map_or(0, |c| c.timestamp as usize), *i),
+        });
 
         let header_size = SECTOR_SIZE * 2;
         let chunks_count = self.chunks.iter().filter(|e| e.is_some()).count();
-        let chunk_estimated_size = match compression_type {
-            CompressionType::No => 0x40000, // 128KB
-            _ => 0x8000,                    // 16KB
-        };
+        let chunk_estimated_size = estimated_chunk_size(&compression_type);
         let mut buffer: Vec<u8> =
             Vec::with_capacity(header_size + chunk_estimated_size * chunks_count);
 
-        // prefill header
-        buffer.extend_from_slice(&[0; SECTOR_SIZE * 2]);
+        // prefill header, and with a layout the whole original file so that the bytes between
+        // chunks stay as they were
+        if let Some(layout) = &self.layout {
+            buffer.extend_from_slice(&layout.raw);
+        }
+        buffer.resize(buffer.len().max(header_size), 0);
+        buffer[..header_size].fill(0);
+
+        let mut crcs = [0u32; CHUNKS_PER_REGION];
+        let mut bodies = Vec::with_capacity(chunks_count);
 
         for ((i, _, _), compressed_nbt, _) in results {
             let nbt = match compressed_nbt {
@@ -63,44 +172,63 @@ impl<'a> MCABuilder<'a> {
                 Some(Err(e)) => return Err(e),
                 None => None,
             };
-
-            let chunk = self.chunks[i];
-
-            // calculate header info
-            let (sector_offset, sector_count, timestamp) = match chunk {
-                None => (0, 0, 0),
-                Some(chunk) => {
-                    let sector_offset = buffer.len() / SECTOR_SIZE;
-                    match nbt {
-                        Some(ref nbt) => {
-                            // `+ 5` for chunk data header (4 for length and 1 for compression type)
-                            // `+ SECTOR_SIZE - 1` for align to SECTOR_SIZE
-                            let sector_count = (nbt.len() + 5 + SECTOR_SIZE - 1) / SECTOR_SIZE;
-                            (sector_offset, sector_count, chunk.timestamp)
-                        }
-                        None => (sector_offset, 1, chunk.timestamp),
-                    }
-                }
+            let Some(chunk) = self.chunks[i] else {
+                continue;
             };
 
-            // write body if chunk exists
-            if let Some(_) = chunk {
+            // chunk data header: 4 bytes for length and 1 for compression type
+            let mut body = Vec::with_capacity(nbt.as_ref().map_or(0, |nbt| nbt.len()) + 5);
+            match (self.stored_body(i), nbt) {
+                (Some(stored), _) => body.extend_from_slice(stored),
                 // small chunk
-                if let Some(nbt) = nbt {
-                    buffer.extend_from_slice(&(nbt.len() as u32 + 1).to_be_bytes());
-                    buffer.push(compression_type.to_magic());
-                    buffer.extend_from_slice(&nbt);
-                    let padding_size = sector_count * SECTOR_SIZE - (nbt.len() + 5);
-                    buffer.extend(std::iter::repeat(0).take(padding_size));
+                (None, Some(nbt)) => {
+                    body.extend_from_slice(&(nbt.len() as u32 + 1).to_be_bytes());
+                    body.push(compression_type.to_magic());
+                    body.extend_from_slice(&nbt);
                 }
                 // large chunk
-                else {
-                    buffer.extend_from_slice(&1u32.to_be_bytes());
-                    buffer.push((compression_type.to_magic()) | LARGE_FLAG);
-                    let padding_size = sector_count * SECTOR_SIZE - 5;
-                    buffer.extend(std::iter::repeat(0).take(padding_size));
+                (None, None) => {
+                    body.extend_from_slice(&1u32.to_be_bytes());
+                    // the external .mcc file is not rebuilt here and is always zlib-compressed
+                    body.push(CompressionType::Zlib.to_magic() | LARGE_FLAG);
                 }
             }
+            crcs[i] = crc32fast::hash(&body);
+            bodies.push((i, chunk.timestamp, body));
+        }
+
+        // chunks keeping their sectors are placed first, so that appended ones can't take them
+        let kept_sectors = |i: usize, body: &Vec<u8>| match &self.layout {
+            Some(layout) => {
+                let (offset, count) = layout.sectors[i];
+                (offset != 0 && body.len().div_ceil(SECTOR_SIZE) <= count as usize)
+                    .then_some((offset as usize, count as usize))
+            }
+            None => None,
+        };
+        let (kept, appended): (Vec<_>, Vec<_>) = bodies
+            .into_iter()
+            .partition(|(i, _, body)| kept_sectors(*i, body).is_some());
+
+        for (i, timestamp, body) in kept.into_iter().chain(appended) {
+            let (sector_offset, sector_count) = match kept_sectors(i, &body) {
+                Some((offset, count)) => {
+                    let start = offset * SECTOR_SIZE;
+                    if buffer.len() < start + body.len() {
+                        buffer.resize(start + body.len(), 0);
+                    }
+                    buffer[start..start + body.len()].copy_from_slice(&body);
+                    (offset, count)
+                }
+                None => {
+                    buffer.resize(buffer.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE, 0);
+                    let offset = buffer.len() / SECTOR_SIZE;
+                    let count = body.len().div_ceil(SECTOR_SIZE);
+                    buffer.extend_from_slice(&body);
+                    buffer.resize((offset + count) * SECTOR_SIZE, 0);
+                    (offset, count)
+                }
+            };
 
             // update header: location part
             let header_loc_offset = i * 4;
@@ -113,6 +241,19 @@ impl<'a> MCABuilder<'a> {
             buffer[header_ts_offset..header_ts_offset + 4]
                 .copy_from_slice(&timestamp.to_be_bytes());
         }
+
+        // write footer: magic, then the CRC32 of each chunk in index order
+        if self.crc_footer {
+            buffer.resize(buffer.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE, 0);
+            let footer_start = buffer.len();
+            buffer.extend_from_slice(CRC_FOOTER_MAGIC);
+            for crc in crcs {
+                buffer.extend_from_slice(&crc.to_be_bytes());
+            }
+            let footer_size = buffer.len() - footer_start;
+            let padding_size = footer_size.div_ceil(SECTOR_SIZE) * SECTOR_SIZE - footer_size;
+            buffer.extend(std::iter::repeat_n(0, padding_size));
+        }
         Ok(buffer)
     }
 }
@@ -128,10 +269,7 @@ mod tests {
 
     use super::*;
 
-    static TEST_CONFIG: Config = Config {
-        log_config: crate::config::LogConfig::Trace,
-        threads: 16,
-    };
+    static TEST_CONFIG: Config = Config::for_test();
 
     #[test]
     fn test_to_bytes() {
@@ -172,4 +310,49 @@ mod tests {
             assert_eq!(mca_1, mca_2);
         });
     }
+
+    #[test]
+    fn test_morton_code() {
+        assert_eq!(morton_code(0, 0), 0);
+        assert_eq!(morton_code(1, 0), 1);
+        assert_eq!(morton_code(0, 1), 2);
+        assert_eq!(morton_code(3, 3), 15);
+        assert_eq!(morton_code(31, 31), 1023);
+    }
+
+    #[test]
+    fn test_chunk_order() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let mca =
+                fs::read("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca")
+                    .expect("Failed to read test MCA file");
+            let reader = MCAReader::from_bytes(&mca).expect("Failed to create MCA reader");
+            let build = |order| {
+                let mut builder = MCABuilder::new();
+                builder.set_chunk_order(order);
+                for (_, x, z) in create_chunk_ixz_iter() {
+                    if let LazyChunk::Some(chunk) = reader.get_chunk_lazily(x, z) {
+                        builder.set_chunk(x, z, chunk);
+                    }
+                }
+                builder
+                    .to_bytes(CompressionType::Zlib)
+                    .expect("Failed to build MCA bytes")
+            };
+            let index_order = build(ChunkOrder::Index);
+            for order in [ChunkOrder::ZOrder, ChunkOrder::Timestamp] {
+                let built = build(order);
+                assert!(crate::mca::mca_chunks_eq(&index_order, &built).unwrap());
+                log::info!(
+                    "{:?} order: {} bytes compressed, index order: {} bytes",
+                    order,
+                    CompressionType::Zlib.compress_all(&built).unwrap().len(),
+                    CompressionType::Zlib
+                        .compress_all(&index_order)
+                        .unwrap()
+                        .len()
+                );
+            }
+        });
+    }
 }
@@ -3,13 +3,16 @@ mod reader;
 use std::fmt::Debug;
 use thiserror::Error;
 
-pub use builder::MCABuilder;
-pub use reader::{LazyChunk, MCAReader};
+pub use builder::{ChunkOrder, MCABuilder, SectorLayout, estimated_chunk_size};
+pub use reader::{ChunkStatus, LazyChunk, MCAReader, ValidationReport};
 
-use crate::util::nbt_serde::de;
+use crate::compress::CompressionType;
+use crate::util::{CHUNKS_PER_REGION, REGION_DIM, create_chunk_ixz_iter, nbt_serde::try_de};
 
 pub const SECTOR_SIZE: usize = 4096;
 pub const LARGE_FLAG: u8 = 0b_1000_0000;
+/// Magic bytes opening the optional CRC footer written by `MCABuilder`.
+pub const CRC_FOOTER_MAGIC: &[u8; 8] = b"RDIFFCRC";
 
 #[derive(Error, Debug)]
 pub enum MCAError {
@@ -29,6 +32,21 @@ pub enum MCAError {
     },
     #[error("Failed to load chunk at ({x}, {z}): {reason}")]
     ChunkLoadFailed { x: usize, z: usize, reason: String },
+    #[error("CRC footer not found")]
+    MissingCrcFooter,
+    #[error("Chunk ({x}, {z}) cannot be reverted without the new file")]
+    NewChunkRequired { x: usize, z: usize },
+    #[error("Chunk ({x}, {z}) is stored in an external .mcc file")]
+    LargeChunk { x: usize, z: usize },
+    #[error("Chunk ({x}, {z}) is changed by both diffs")]
+    OverlappingChunk { x: usize, z: usize },
+    #[error("Failed to squash chunk ({x}, {z}): {reason}")]
+    SquashFailed { x: usize, z: usize, reason: String },
+    #[error(
+        "Chunk ({x}, {z}) is outside of the region, coordinates must be in 0..{}",
+        REGION_DIM
+    )]
+    OutOfRegion { x: usize, z: usize },
 }
 
 #[derive(Debug, Clone)]
@@ -39,7 +57,6 @@ struct HeaderEntry {
     timestamp: u32,
 }
 impl HeaderEntry {
-    #[allow(dead_code)]
     fn is_available(&self) -> Result<bool, MCAError> {
         if self.sector_count == 0 && self.sector_offset == 0 {
             Ok(false)
@@ -59,20 +76,315 @@ pub enum ChunkNbt {
     Large, // so large that saved to a extra .mcc file, see also: https://minecraft.wiki/w/Region_file_format#Payload
 }
 
-impl PartialEq for ChunkNbt {
-    fn eq(&self, other: &Self) -> bool {
+impl ChunkNbt {
+    /// Whether both chunks hold the very same bytes, which implies they are equal without
+    /// decoding them.
+    pub fn eq_bytes(&self, other: &Self) -> bool {
         match (self, other) {
             (ChunkNbt::Large, ChunkNbt::Large) => true,
+            (ChunkNbt::Small(self_nbt), ChunkNbt::Small(other_nbt)) => self_nbt == other_nbt,
+            _ => false,
+        }
+    }
+    /// Whether both chunks hold the same NBT, regardless of the order of compound tags. Only
+    /// decodes them when their bytes differ, failing if either can't be decoded then.
+    pub fn try_eq(&self, other: &Self) -> Result<bool, fastnbt::error::Error> {
+        if self.eq_bytes(other) {
+            return Ok(true);
+        }
+        match (self, other) {
             (ChunkNbt::Small(self_nbt), ChunkNbt::Small(other_nbt)) => {
-                de(&self_nbt) == de(&other_nbt)
+                Ok(try_de(self_nbt)? == try_de(other_nbt)?)
             }
-            _ => false,
+            _ => Ok(false),
         }
     }
 }
 
+/// Chunks that fail to decode are unequal to anything but the same bytes; use
+/// `ChunkNbt::try_eq` to tell them apart from chunks that differ.
+impl PartialEq for ChunkNbt {
+    fn eq(&self, other: &Self) -> bool {
+        self.try_eq(other).unwrap_or(false)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ChunkWithTimestamp {
     pub timestamp: u32,
     pub nbt: ChunkNbt,
 }
+
+/// Check whether a region file ends before its header or before one of the chunks its header
+/// points to, as happens when it is read while the game is still saving it.
+pub fn is_region_truncated(bytes: &[u8]) -> bool {
+    if bytes.len() < SECTOR_SIZE * 2 {
+        return true;
+    }
+    (0..CHUNKS_PER_REGION).any(|idx| {
+        let loc = &bytes[idx * 4..idx * 4 + 4];
+        let offset = u32::from_be_bytes([0, loc[0], loc[1], loc[2]]) as usize * SECTOR_SIZE;
+        if offset == 0 && loc[3] == 0 {
+            return false;
+        }
+        match bytes.get(offset..offset + 4) {
+            Some(length) => {
+                let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+                bytes.len() < offset + 4 + length
+            }
+            None => true,
+        }
+    })
+}
+
+/// Compare two region files chunk by chunk, ignoring sector layout and chunk compression.
+pub fn mca_chunks_eq(a: &[u8], b: &[u8]) -> Result<bool, MCAError> {
+    let reader_a = MCAReader::from_bytes(a)?;
+    let reader_b = MCAReader::from_bytes(b)?;
+    for (_, x, z) in create_chunk_ixz_iter() {
+        let eq = match (
+            reader_a.get_chunk_lazily(x, z),
+            reader_b.get_chunk_lazily(x, z),
+        ) {
+            (LazyChunk::Some(chunk_a), LazyChunk::Some(chunk_b)) => {
+                chunk_a.timestamp == chunk_b.timestamp
+                    && chunk_a
+                        .nbt
+                        .try_eq(&chunk_b.nbt)
+                        .map_err(|source| MCAError::NBTParsingError { x, z, source })?
+            }
+            (LazyChunk::NotExists, LazyChunk::NotExists) => true,
+            _ => false,
+        };
+        if !eq {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Decode the NBT of every chunk that differs between two region files, on both sides, failing
+/// with the coordinates of the first chunk that can't be decoded. Chunks are taken as unchanged
+/// the way the diff takes them: same timestamp on both sides, and also the same bytes unless
+/// `compare_timestamps` is set.
+pub fn validate_changed_chunks(
+    old: &[u8],
+    new: &[u8],
+    compare_timestamps: bool,
+) -> Result<(), MCAError> {
+    let reader_old = MCAReader::from_bytes(old)?;
+    let reader_new = MCAReader::from_bytes(new)?;
+    for (_, x, z) in create_chunk_ixz_iter() {
+        let chunks = match (
+            reader_old.get_chunk_lazily(x, z),
+            reader_new.get_chunk_lazily(x, z),
+        ) {
+            (LazyChunk::Some(old), LazyChunk::Some(new))
+                if old.timestamp == new.timestamp
+                    && (compare_timestamps || old.nbt.eq_bytes(&new.nbt)) =>
+            {
+                continue;
+            }
+            (LazyChunk::Some(old), LazyChunk::Some(new)) => vec![old, new],
+            (LazyChunk::Some(chunk), _) | (_, LazyChunk::Some(chunk)) => vec![chunk],
+            _ => continue,
+        };
+        for chunk in chunks {
+            if let ChunkNbt::Small(nbt) = &chunk.nbt {
+                try_de(nbt).map_err(|source| MCAError::NBTParsingError { x, z, source })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite a region file with its chunks stored contiguously, dropping the unused sectors left
+/// behind when chunks shrink or move. Chunks are recompressed with `compression_type`.
+pub fn repack(region: &[u8], compression_type: CompressionType) -> Result<Vec<u8>, MCAError> {
+    let reader = MCAReader::from_bytes(region)?;
+    let mut builder = MCABuilder::new();
+    for (_, x, z) in create_chunk_ixz_iter() {
+        if let LazyChunk::Some(chunk) = reader.get_chunk_lazily(x, z) {
+            builder.set_chunk(x, z, chunk);
+        }
+    }
+    builder.to_bytes(compression_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::config::{Config, with_test_config};
+
+    static TEST_CONFIG: Config = Config::for_test();
+
+    /// Move every chunk of `region` one sector further than the previous one, leaving a sector
+    /// of `fill` bytes in front of each.
+    fn add_gaps(region: &[u8], fill: u8) -> Vec<u8> {
+        let mut entries: Vec<(usize, usize, usize)> = (0..CHUNKS_PER_REGION)
+            .filter_map(|idx| {
+                let loc = &region[idx * 4..idx * 4 + 4];
+                let offset = u32::from_be_bytes([0, loc[0], loc[1], loc[2]]) as usize;
+                (offset != 0).then_some((idx, offset, loc[3] as usize))
+            })
+            .collect();
+        entries.sort_by_key(|(_, offset, _)| *offset);
+
+        let mut gapped = region[..SECTOR_SIZE * 2].to_vec();
+        for (idx, offset, count) in entries {
+            gapped.extend(std::iter::repeat_n(fill, SECTOR_SIZE));
+            let new_offset = gapped.len() / SECTOR_SIZE;
+            gapped[idx * 4..idx * 4 + 3].copy_from_slice(&(new_offset as u32).to_be_bytes()[1..4]);
+            gapped.extend_from_slice(&region[offset * SECTOR_SIZE..(offset + count) * SECTOR_SIZE]);
+        }
+        gapped
+    }
+
+    #[test]
+    fn test_chunk_nbt_eq() {
+        let chunk = crate::util::test::get_test_chunk_by_xz(
+            &std::path::PathBuf::from(
+                "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+            ),
+            25,
+            29,
+        )
+        .unwrap();
+        let ChunkNbt::Small(bytes) = &chunk.nbt else {
+            panic!("test chunk should be small");
+        };
+
+        // byte-equal chunks are equal without being decoded, even if they are not valid NBT
+        let garbage = ChunkNbt::Small(vec![0xff; 16]);
+        assert!(garbage.eq_bytes(&garbage.clone()));
+        assert_eq!(garbage.try_eq(&garbage.clone()), Ok(true));
+        assert!(garbage.try_eq(&chunk.nbt).is_err());
+        assert_ne!(garbage, chunk.nbt);
+
+        let rearranged = ChunkNbt::Small(crate::util::test::rearranged_nbt(bytes).unwrap());
+        assert!(!chunk.nbt.eq_bytes(&rearranged));
+        assert_eq!(chunk.nbt.try_eq(&rearranged), Ok(true));
+        assert_eq!(chunk.nbt, rearranged);
+
+        let mut value = crate::util::nbt_serde::de(bytes);
+        let fastnbt::Value::Compound(kv) = &mut value else {
+            panic!("chunk should be a compound");
+        };
+        kv.insert("DataVersion".to_string(), fastnbt::Value::Int(-1));
+        let changed = ChunkNbt::Small(crate::util::nbt_serde::ser(&value));
+        assert_eq!(chunk.nbt.try_eq(&changed), Ok(false));
+        assert_ne!(chunk.nbt, changed);
+        assert_ne!(chunk.nbt, ChunkNbt::Large);
+    }
+    #[test]
+    fn test_validate_changed_chunks() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let chunk = crate::util::test::get_test_chunk_by_xz(
+                &std::path::PathBuf::from(
+                    "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+                ),
+                25,
+                29,
+            )
+            .unwrap();
+            let region_with = |chunk: &ChunkWithTimestamp| {
+                let mut builder = MCABuilder::new();
+                builder.set_chunk(25, 29, chunk);
+                builder.to_bytes(CompressionType::Zlib).unwrap()
+            };
+            let clean = region_with(&chunk);
+            let touched = region_with(&ChunkWithTimestamp {
+                timestamp: chunk.timestamp + 60,
+                nbt: chunk.nbt.clone(),
+            });
+            let corrupt = region_with(&ChunkWithTimestamp {
+                timestamp: chunk.timestamp + 60,
+                nbt: ChunkNbt::Small(vec![0xff; 16]),
+            });
+
+            // rewritten without touching the timestamp
+            let corrupt_same_ts = region_with(&ChunkWithTimestamp {
+                timestamp: chunk.timestamp,
+                nbt: ChunkNbt::Small(vec![0xff; 16]),
+            });
+
+            assert!(validate_changed_chunks(&clean, &touched, false).is_ok());
+            assert!(validate_changed_chunks(&[], &clean, false).is_ok());
+            assert!(matches!(
+                validate_changed_chunks(&clean, &corrupt, false),
+                Err(MCAError::NBTParsingError { x: 25, z: 29, .. })
+            ));
+            assert!(matches!(
+                validate_changed_chunks(&corrupt, &[], false),
+                Err(MCAError::NBTParsingError { x: 25, z: 29, .. })
+            ));
+            assert!(matches!(
+                validate_changed_chunks(&clean, &corrupt_same_ts, false),
+                Err(MCAError::NBTParsingError { x: 25, z: 29, .. })
+            ));
+            assert!(validate_changed_chunks(&clean, &corrupt_same_ts, true).is_ok());
+        });
+    }
+    #[test]
+    fn test_repack() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let region =
+                fs::read("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca")
+                    .unwrap();
+            let packed = repack(&region, CompressionType::Zlib).unwrap();
+            let gapped = add_gaps(&packed, 0);
+            assert!(gapped.len() > packed.len());
+
+            let repacked = repack(&gapped, CompressionType::Zlib).unwrap();
+            assert!(repacked.len() <= gapped.len());
+            assert_eq!(repacked, packed);
+            assert!(mca_chunks_eq(&repacked, &region).unwrap());
+        });
+    }
+    #[test]
+    fn test_preserve_layout() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let region =
+                fs::read("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca")
+                    .unwrap();
+            let gapped = add_gaps(&repack(&region, CompressionType::Zlib).unwrap(), 0xaa);
+            let reader = MCAReader::from_bytes(&gapped).unwrap();
+            let rebuild_with = |layout: Option<SectorLayout>, unchanged: bool, compression| {
+                let mut builder = MCABuilder::new();
+                if let Some(layout) = layout {
+                    builder.set_layout(layout);
+                }
+                for (_, x, z) in create_chunk_ixz_iter() {
+                    if let LazyChunk::Some(chunk) = reader.get_chunk_lazily(x, z) {
+                        builder.set_chunk(x, z, chunk);
+                        if unchanged {
+                            builder.set_chunk_unchanged(x, z);
+                        }
+                    }
+                }
+                builder.to_bytes(compression).unwrap()
+            };
+            let rebuild = |layout| rebuild_with(layout, false, CompressionType::Zlib);
+            assert_ne!(rebuild(None), gapped);
+            assert_eq!(rebuild(Some(reader.layout().unwrap())), gapped);
+
+            // unchanged chunks are copied as stored, whatever the compression type
+            let layout = Some(reader.layout().unwrap());
+            assert_eq!(rebuild_with(layout, true, CompressionType::No), gapped);
+            assert_ne!(rebuild_with(None, true, CompressionType::No), gapped);
+
+            // a chunk that no longer fits its sectors moves to the end of the file
+            let mut layout = reader.layout().unwrap();
+            let i = (0..CHUNKS_PER_REGION)
+                .find(|i| layout.sectors[*i].0 != 0)
+                .unwrap();
+            layout.sectors[i].1 = 0;
+            let rebuilt = rebuild(Some(layout));
+            let moved = MCAReader::from_bytes(&rebuilt).unwrap().layout().unwrap();
+            assert!(moved.sectors[i].0 as usize >= gapped.len() / SECTOR_SIZE);
+            assert!(mca_chunks_eq(&rebuilt, &gapped).unwrap());
+        });
+    }
+}
@@ -1,10 +1,19 @@
 use std::io::{Cursor, Read, Seek};
 use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use fastnbt::Value;
 
 use crate::compress::CompressionType;
-use crate::util::{create_chunk_ixz_iter, parallel::parallel_process_with_cost_estimator};
+use crate::util::{
+    CHUNKS_PER_REGION, REGION_DIM, create_chunk_ixz_iter,
+    parallel::parallel_process_with_cost_estimator,
+};
 
-use super::{ChunkNbt, ChunkWithTimestamp, HeaderEntry, LARGE_FLAG, MCAError, SECTOR_SIZE};
+use super::{
+    CRC_FOOTER_MAGIC, ChunkNbt, ChunkWithTimestamp, HeaderEntry, LARGE_FLAG, MCAError, SECTOR_SIZE,
+    SectorLayout,
+};
 
 #[derive(Debug, Clone)]
 pub enum LazyChunk {
@@ -12,17 +21,71 @@ pub enum LazyChunk {
     NotExists,
     Some(ChunkWithTimestamp),
 }
+/// Health of a chunk slot as far as the region header tells, see `MCAReader::validate_file`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkStatus {
+    NotExists,
+    Ok,
+    /// The sectors start inside the header.
+    SectorHeaderOverlap,
+    /// The sector count is zero while the offset is not.
+    InvalidSectorSize,
+    /// Some of the sectors start past the end of the file.
+    OutOfFile,
+    /// The sectors overlap those of the chunk at (`x`, `z`).
+    Overlapping {
+        x: usize,
+        z: usize,
+    },
+}
+
+/// Per-chunk result of `MCAReader::validate_file`.
+#[derive(Debug, Clone)]
+pub struct ValidationReport {
+    /// Status of every chunk slot, in index order.
+    pub chunks: Vec<ChunkStatus>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.flagged().next().is_none()
+    }
+    /// Coordinates and status of the chunks that are neither absent nor healthy.
+    pub fn flagged(&self) -> impl Iterator<Item = (usize, usize, &ChunkStatus)> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, status)| !matches!(status, ChunkStatus::NotExists | ChunkStatus::Ok))
+            .map(|(idx, status)| (idx % REGION_DIM, idx / REGION_DIM, status))
+    }
+}
+
+/// Reader of a region file. Chunks not loaded up front are loaded on first access, so a reader
+/// can be shared by threads loading different chunks.
 pub struct MCAReader<R: Read + Seek> {
-    #[allow(dead_code)]
-    mca_reader: R,
-    header: [HeaderEntry; 1024],
-    chunks: [LazyChunk; 1024],
+    mca_reader: Mutex<R>,
+    header: [HeaderEntry; CHUNKS_PER_REGION],
+    chunks: [OnceLock<LazyChunk>; CHUNKS_PER_REGION],
+    /// Length of the file in bytes
+    len: u64,
+}
+
+static ERR_MSG_POISONED: &str = "A thread panicked while reading the region file";
+static UNLOADED: LazyChunk = LazyChunk::Unloaded;
+
+/// Chunks loaded so far, `LazyChunk::Unloaded` ones are left to be loaded on access.
+fn into_slots(chunks: [LazyChunk; CHUNKS_PER_REGION]) -> [OnceLock<LazyChunk>; CHUNKS_PER_REGION] {
+    chunks.map(|chunk| match chunk {
+        LazyChunk::Unloaded => OnceLock::new(),
+        chunk => OnceLock::from(chunk),
+    })
 }
 
 impl<R: Read + Seek> MCAReader<R> {
     fn from_reader(mut reader: R, lazy: bool) -> Result<Self, MCAError> {
-        let mut chunks = [const { LazyChunk::Unloaded }; 1024];
+        let mut chunks = [const { LazyChunk::Unloaded }; CHUNKS_PER_REGION];
         let header = read_header(&mut reader)?;
+        let len = reader.seek(std::io::SeekFrom::End(0))?;
 
         if !lazy {
             let mut header_refs: Vec<&HeaderEntry> = header.iter().collect();
@@ -41,8 +104,8 @@ impl<R: Read + Seek> MCAReader<R> {
                             timestamp: header_entry.timestamp,
                             nbt: read_chunk_nbt(
                                 &sector_buf,
-                                header_entry.idx % 32,
-                                header_entry.idx / 32,
+                                header_entry.idx % REGION_DIM,
+                                header_entry.idx / REGION_DIM,
                             )?,
                         })
                     }
@@ -50,26 +113,21 @@ impl<R: Read + Seek> MCAReader<R> {
             }
         }
         Ok(Self {
-            mca_reader: reader,
+            mca_reader: Mutex::new(reader),
             header,
-            chunks,
+            chunks: into_slots(chunks),
+            len,
         })
     }
-    #[allow(dead_code)]
-    pub fn get_chunk(
-        &mut self,
-        x: usize,
-        z: usize,
-    ) -> Result<Option<&ChunkWithTimestamp>, MCAError> {
+    pub fn get_chunk(&self, x: usize, z: usize) -> Result<Option<&ChunkWithTimestamp>, MCAError> {
         use std::io::SeekFrom;
 
-        let idx = x + 32 * z;
+        let idx = x + REGION_DIM * z;
 
-        if let LazyChunk::Some(ref chunk) = self.chunks[idx] {
-            return Ok(Some(chunk));
-        }
-        if let LazyChunk::NotExists = self.chunks[idx] {
-            return Ok(None);
+        match self.chunks[idx].get() {
+            Some(LazyChunk::Some(chunk)) => return Ok(Some(chunk)),
+            Some(_) => return Ok(None),
+            None => (),
         }
 
         let header = &self.header[idx];
@@ -79,17 +137,19 @@ impl<R: Read + Seek> MCAReader<R> {
 
         let mut sector_buf = vec![0u8; header.sector_count as usize * SECTOR_SIZE];
         let offset = (header.sector_offset as usize) * SECTOR_SIZE;
-        self.mca_reader.seek(SeekFrom::Start(offset as u64))?;
-        self.mca_reader.read_exact(&mut sector_buf)?;
+        {
+            let mut mca_reader = self.mca_reader.lock().expect(ERR_MSG_POISONED);
+            mca_reader.seek(SeekFrom::Start(offset as u64))?;
+            mca_reader.read_exact(&mut sector_buf)?;
+        }
 
         let chunk = ChunkWithTimestamp {
             timestamp: header.timestamp,
             nbt: read_chunk_nbt(&sector_buf, x, z)?,
         };
 
-        self.chunks[idx] = LazyChunk::Some(chunk);
-
-        match &self.chunks[idx] {
+        // another thread may have loaded the chunk meanwhile, its result is the same
+        match self.chunks[idx].get_or_init(|| LazyChunk::Some(chunk)) {
             LazyChunk::Some(chunk) => Ok(Some(chunk)),
             _ => Err(MCAError::ChunkLoadFailed {
                 x,
@@ -98,14 +158,120 @@ impl<R: Read + Seek> MCAReader<R> {
             }),
         }
     }
+    /// Same as `get_chunk`, but returns the decoded NBT of the chunk.
+    ///
+    /// Fails with `MCAError::LargeChunk` for chunks stored in an external .mcc file, whose NBT is
+    /// not in the region file.
+    pub fn get_chunk_value(&self, x: usize, z: usize) -> Result<Option<Value>, MCAError> {
+        match self.get_chunk(x, z)? {
+            None => Ok(None),
+            Some(chunk) => match &chunk.nbt {
+                ChunkNbt::Large => Err(MCAError::LargeChunk { x, z }),
+                ChunkNbt::Small(nbt) => fastnbt::from_bytes(nbt)
+                    .map(Some)
+                    .map_err(|source| MCAError::NBTParsingError { x, z, source }),
+            },
+        }
+    }
     pub fn get_chunk_lazily(&self, x: usize, z: usize) -> &LazyChunk {
-        let idx = x + 32 * z;
-        &self.chunks[idx]
+        let idx = x + REGION_DIM * z;
+        self.chunks[idx].get().unwrap_or(&UNLOADED)
     }
     pub fn get_timestamp(&self, x: usize, z: usize) -> u32 {
-        let idx = x + 32 * z;
+        let idx = x + REGION_DIM * z;
         self.header[idx].timestamp
     }
+    /// Raw header of every chunk slot, in index order.
+    ///
+    /// Each entry is `(i, x, z, sector_offset, sector_count, timestamp)`; slots without a chunk
+    /// have a zero offset and count.
+    pub fn header_summary(&self) -> Vec<(usize, usize, usize, u32, u8, u32)> {
+        self.header
+            .iter()
+            .map(|e| {
+                (
+                    e.idx,
+                    e.idx % REGION_DIM,
+                    e.idx / REGION_DIM,
+                    e.sector_offset,
+                    e.sector_count,
+                    e.timestamp,
+                )
+            })
+            .collect()
+    }
+    /// Where the chunks are stored in the file, and the bytes of the file, for
+    /// `MCABuilder::set_layout`.
+    pub fn layout(&self) -> Result<SectorLayout, MCAError> {
+        use std::io::SeekFrom;
+
+        let mut raw = Vec::with_capacity(self.len as usize);
+        {
+            let mut mca_reader = self.mca_reader.lock().expect(ERR_MSG_POISONED);
+            mca_reader.seek(SeekFrom::Start(0))?;
+            mca_reader.read_to_end(&mut raw)?;
+        }
+        Ok(SectorLayout {
+            sectors: self
+                .header
+                .each_ref()
+                .map(|e| (e.sector_offset, e.sector_count)),
+            raw,
+        })
+    }
+    /// Check every chunk against the CRC footer written by `MCABuilder::set_crc_footer`.
+    ///
+    /// Returns the coordinates of the chunks whose stored data does not match its CRC.
+    pub fn verify_crcs(&mut self) -> Result<Vec<(usize, usize)>, MCAError> {
+        use std::io::SeekFrom;
+
+        let footer_sector = self
+            .header
+            .iter()
+            .map(|e| e.sector_offset as u64 + e.sector_count as u64)
+            .max()
+            .unwrap_or(0)
+            .max(2);
+        let mca_reader = self.mca_reader.get_mut().expect(ERR_MSG_POISONED);
+        mca_reader.seek(SeekFrom::Start(footer_sector * SECTOR_SIZE as u64))?;
+        let mut magic = [0u8; CRC_FOOTER_MAGIC.len()];
+        let mut crcs = [0u8; CHUNKS_PER_REGION * 4];
+        if mca_reader.read_exact(&mut magic).is_err()
+            || &magic != CRC_FOOTER_MAGIC
+            || mca_reader.read_exact(&mut crcs).is_err()
+        {
+            return Err(MCAError::MissingCrcFooter);
+        }
+
+        let mut mismatched = Vec::new();
+        for header in &self.header {
+            let expected = u32::from_be_bytes(
+                crcs[header.idx * 4..header.idx * 4 + 4]
+                    .try_into()
+                    .expect("CRC entry is 4 bytes"),
+            );
+            if !header.is_available()? {
+                if expected != 0 {
+                    mismatched.push((header.idx % REGION_DIM, header.idx / REGION_DIM));
+                }
+                continue;
+            }
+
+            let mut sector_buf = vec![0u8; header.sector_count as usize * SECTOR_SIZE];
+            let offset = (header.sector_offset as usize) * SECTOR_SIZE;
+            mca_reader.seek(SeekFrom::Start(offset as u64))?;
+            mca_reader.read_exact(&mut sector_buf)?;
+            let length =
+                u32::from_be_bytes([sector_buf[0], sector_buf[1], sector_buf[2], sector_buf[3]])
+                    as usize;
+            let matched = length + 4 <= sector_buf.len()
+                && crc32fast::hash(&sector_buf[..length + 4]) == expected;
+            if !matched {
+                mismatched.push((header.idx % REGION_DIM, header.idx / REGION_DIM));
+            }
+        }
+        Ok(mismatched)
+    }
 }
 
 impl MCAReader<std::io::BufReader<std::fs::File>> {
@@ -115,10 +281,61 @@ impl MCAReader<std::io::BufReader<std::fs::File>> {
         let reader = BufReader::new(file);
         Self::from_reader(reader, lazy)
     }
+    /// Check the header of a region file without reading any chunk data, so that corruption can
+    /// be spotted far more cheaply than by loading the file.
+    ///
+    /// The last sector of a chunk only has to start inside the file, as the final sector of a
+    /// region may not be padded. Only an unreadable header fails the check.
+    pub fn validate_file(path: &PathBuf) -> Result<ValidationReport, MCAError> {
+        use std::fs::File;
+        let mut file = File::open(path)?;
+        let sectors = file.metadata()?.len().div_ceil(SECTOR_SIZE as u64);
+        let header = read_header(&mut file)?;
+
+        let mut chunks: Vec<ChunkStatus> = header
+            .iter()
+            .map(|e| match e.is_available() {
+                Ok(false) => ChunkStatus::NotExists,
+                Ok(true) if e.sector_offset as u64 + e.sector_count as u64 > sectors => {
+                    ChunkStatus::OutOfFile
+                }
+                Ok(true) => ChunkStatus::Ok,
+                Err(MCAError::SectorHeaderOverlap { .. }) => ChunkStatus::SectorHeaderOverlap,
+                Err(_) => ChunkStatus::InvalidSectorSize,
+            })
+            .collect();
+
+        // sweep the chunks by offset, remembering the one reaching furthest so far
+        let mut placed: Vec<&HeaderEntry> = header
+            .iter()
+            .filter(|e| chunks[e.idx] != ChunkStatus::NotExists && e.sector_count > 0)
+            .collect();
+        placed.sort_by_key(|e| e.sector_offset);
+        let mut furthest: Option<&HeaderEntry> = None;
+        for e in placed {
+            let end = |e: &HeaderEntry| e.sector_offset + e.sector_count as u32;
+            match furthest {
+                Some(other) if e.sector_offset < end(other) => {
+                    let coords = |e: &HeaderEntry| (e.idx % REGION_DIM, e.idx / REGION_DIM);
+                    let (x, z) = coords(other);
+                    chunks[e.idx] = ChunkStatus::Overlapping { x, z };
+                    if chunks[other.idx] == ChunkStatus::Ok {
+                        let (x, z) = coords(e);
+                        chunks[other.idx] = ChunkStatus::Overlapping { x, z };
+                    }
+                    if end(e) > end(other) {
+                        furthest = Some(e);
+                    }
+                }
+                _ => furthest = Some(e),
+            }
+        }
+        Ok(ValidationReport { chunks })
+    }
 }
 impl<'a> MCAReader<Cursor<&'a [u8]>> {
     pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, MCAError> {
-        let mut chunks = [const { LazyChunk::Unloaded }; 1024];
+        let mut chunks = [const { LazyChunk::Unloaded }; CHUNKS_PER_REGION];
         let mut reader = Cursor::new(bytes);
         let header = read_header(&mut reader)?;
 
@@ -131,7 +348,13 @@ impl<'a> MCAReader<Cursor<&'a [u8]>> {
                     1..=u32::MAX => {
                         let offset = header_entry.sector_offset as usize * SECTOR_SIZE;
                         let size = header_entry.sector_count as usize * SECTOR_SIZE;
-                        let sector_data = &bytes[offset..offset + size];
+                        let sector_data = bytes.get(offset..offset + size).ok_or_else(|| {
+                            MCAError::ChunkLoadFailed {
+                                x: *x,
+                                z: *z,
+                                reason: "allocated sectors exceed the file size".to_string(),
+                            }
+                        })?;
                         Ok(Some(ChunkWithTimestamp {
                             timestamp: header_entry.timestamp,
                             nbt: read_chunk_nbt(&sector_data, *x, *z)?,
@@ -151,48 +374,52 @@ impl<'a> MCAReader<Cursor<&'a [u8]>> {
         }
 
         Ok(Self {
-            mca_reader: reader,
+            mca_reader: Mutex::new(reader),
             header,
-            chunks,
+            chunks: into_slots(chunks),
+            len: bytes.len() as u64,
         })
     }
 }
-fn read_header<R: Read + Seek>(reader: &mut R) -> Result<[HeaderEntry; 1024], MCAError> {
-    let mut headers = std::array::from_fn(|_| HeaderEntry {
-        idx: 0,
-        sector_offset: 0,
-        sector_count: 0,
-        timestamp: 0,
-    });
-
-    // read locations
-    for (idx, _offset) in (0x0000..0x0fff).step_by(4).enumerate() {
-        let mut buf = [0u8; 4];
-        reader.read_exact(&mut buf)?;
-        let sector_offset = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]);
-        let sector_count = buf[3];
-        headers[idx] = HeaderEntry {
+fn read_header<R: Read + Seek>(
+    reader: &mut R,
+) -> Result<[HeaderEntry; CHUNKS_PER_REGION], MCAError> {
+    let mut buf = [0u8; SECTOR_SIZE * 2];
+    reader.read_exact(&mut buf)?;
+
+    // locations fill the first sector and timestamps the second, 4 bytes per chunk
+    let headers = std::array::from_fn(|idx| {
+        let loc = &buf[idx * 4..idx * 4 + 4];
+        let ts = &buf[SECTOR_SIZE + idx * 4..SECTOR_SIZE + idx * 4 + 4];
+        HeaderEntry {
             idx,
-            sector_offset,
-            sector_count,
-            timestamp: 0,
-        };
-    }
-
-    // read timestamps
-    for (idx, _offset) in (0x1000..0x1fff).step_by(4).enumerate() {
-        let mut buf = [0u8; 4];
-        reader.read_exact(&mut buf)?;
-        let timestamp = u32::from_be_bytes(buf);
-        headers[idx].timestamp = timestamp;
-    }
+            sector_offset: u32::from_be_bytes([0, loc[0], loc[1], loc[2]]),
+            sector_count: loc[3],
+            timestamp: u32::from_be_bytes([ts[0], ts[1], ts[2], ts[3]]),
+        }
+    });
 
     Ok(headers)
 }
 
 fn read_chunk_nbt(sector_buf: &[u8], x: usize, z: usize) -> Result<ChunkNbt, MCAError> {
+    let chunk_load_failed = |reason: String| MCAError::ChunkLoadFailed { x, z, reason };
+    if sector_buf.len() < 5 {
+        return Err(chunk_load_failed(format!(
+            "chunk data is {} bytes, too short for its length and compression fields",
+            sector_buf.len()
+        )));
+    }
     let length =
         u32::from_be_bytes([sector_buf[0], sector_buf[1], sector_buf[2], sector_buf[3]]) as usize;
+    // the length covers the compression byte and the data
+    if length == 0 || length + 4 > sector_buf.len() {
+        return Err(chunk_load_failed(format!(
+            "declared length {} does not fit in {} bytes of allocated sectors",
+            length,
+            sector_buf.len()
+        )));
+    }
 
     let compression_type = sector_buf[4];
     let data = &sector_buf[5..length + 4];
@@ -220,10 +447,39 @@ mod tests {
         util::{create_chunk_ixz_iter, test::all_file_iter},
     };
     use std::io::Write;
-    static TEST_CONFIG: Config = Config {
-        log_config: crate::config::LogConfig::NoLog,
-        threads: 16,
-    };
+    static TEST_CONFIG: Config = Config::for_test();
+
+    #[test]
+    fn test_read_chunk_nbt_short_buffer() {
+        let result = read_chunk_nbt(&[0, 0, 0], 1, 2);
+        assert!(matches!(
+            result,
+            Err(MCAError::ChunkLoadFailed { x: 1, z: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_read_chunk_nbt_length_exceeds_sectors() {
+        let mut sector_buf = vec![0u8; SECTOR_SIZE];
+        sector_buf[0..4].copy_from_slice(&(SECTOR_SIZE as u32 * 2).to_be_bytes());
+        sector_buf[4] = CompressionType::Zlib.to_magic();
+        let result = read_chunk_nbt(&sector_buf, 1, 2);
+        assert!(matches!(
+            result,
+            Err(MCAError::ChunkLoadFailed { x: 1, z: 2, .. })
+        ));
+
+        // the same through a crafted region file
+        let mut mca = create_test_mca();
+        mca[SECTOR_SIZE * 2..SECTOR_SIZE * 2 + 4]
+            .copy_from_slice(&(SECTOR_SIZE as u32 * 2).to_be_bytes());
+        with_test_config(TEST_CONFIG.clone(), || {
+            assert!(matches!(
+                MCAReader::from_bytes(&mca),
+                Err(MCAError::ChunkLoadFailed { x: 0, z: 0, .. })
+            ));
+        });
+    }
 
     fn create_test_mca() -> Vec<u8> {
         let mut buffer = Vec::new();
@@ -290,6 +546,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_header_reading_last_entry() {
+        let mut mca = create_test_mca();
+        mca[0x0ffc..0x1000].copy_from_slice(&[0, 0, 3, 2]);
+        mca[0x1ffc..0x2000].copy_from_slice(&0x12345678u32.to_be_bytes());
+        let headers = read_header(&mut Cursor::new(&mca)).expect("Failed to read header");
+
+        let header_entry = &headers[1023];
+        assert_eq!(header_entry.idx, 1023);
+        assert_eq!(header_entry.sector_offset, 3);
+        assert_eq!(header_entry.sector_count, 2);
+        assert_eq!(header_entry.timestamp, 0x12345678);
+        assert_eq!(headers[1022].timestamp, 0);
+    }
+
     #[test]
     fn test_mca_file_reading() {
         with_test_config(TEST_CONFIG.clone(), || {
@@ -297,7 +568,7 @@ mod tests {
             let mca = MCAReader::from_bytes(&mut mca).expect("Failed to create MCA reader");
 
             // test first chunk
-            let chunk = mca.chunks[0].clone();
+            let chunk = mca.get_chunk_lazily(0, 0).clone();
             match chunk {
                 LazyChunk::Some(chunk) => {
                     assert_eq!(chunk.timestamp, 1);
@@ -309,7 +580,7 @@ mod tests {
                 _ => panic!("Chunk should be Some, but got {:?}", chunk),
             }
             // test second chunk should be empty
-            let chunk = mca.chunks[1].clone();
+            let chunk = mca.get_chunk_lazily(1, 0).clone();
             match chunk {
                 LazyChunk::NotExists => (),
                 _ => panic!("Chunk should be NotExists, but got {:?}", chunk),
@@ -321,12 +592,166 @@ mod tests {
     fn test_real_files_reading() {
         for paths in all_file_iter(crate::FileType::RegionMca) {
             for path in paths {
-                let mut reader =
-                    MCAReader::from_file(&path, false).expect("Failed to read MCA file");
+                let reader = MCAReader::from_file(&path, false).expect("Failed to read MCA file");
                 for (_, x, z) in create_chunk_ixz_iter() {
                     let _ = reader.get_chunk(x, z).expect("Failed to get chunk");
                 }
             }
         }
     }
+
+    #[test]
+    fn test_header_summary() {
+        let path =
+            PathBuf::from("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca");
+        let reader = MCAReader::from_file(&path, false).expect("Failed to read MCA file");
+        let summary = reader.header_summary();
+        assert_eq!(summary.len(), CHUNKS_PER_REGION);
+        for (i, x, z, sector_offset, sector_count, timestamp) in summary {
+            assert_eq!(i, x + REGION_DIM * z);
+            let exists = matches!(reader.get_chunk_lazily(x, z), LazyChunk::Some(_));
+            assert_eq!(sector_offset != 0, exists, "chunk ({x}, {z})");
+            assert_eq!(sector_count != 0, exists, "chunk ({x}, {z})");
+            assert_eq!(timestamp, reader.get_timestamp(x, z));
+        }
+    }
+
+    #[test]
+    fn test_validate_file() {
+        let path =
+            PathBuf::from("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca");
+        let report = MCAReader::validate_file(&path).unwrap();
+        assert!(
+            report.is_clean(),
+            "{:?}",
+            report.flagged().collect::<Vec<_>>()
+        );
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        let existing: Vec<usize> = with_test_config(TEST_CONFIG.clone(), || {
+            MCAReader::from_bytes(&bytes).unwrap().header_summary()
+        })
+        .into_iter()
+        .filter(|e| e.4 > 0)
+        .map(|e| e.0)
+        .collect();
+        let set_location = |bytes: &mut Vec<u8>, idx: usize, offset: u32, count: u8| {
+            let offset = offset.to_be_bytes();
+            bytes[idx * 4..idx * 4 + 4].copy_from_slice(&[offset[1], offset[2], offset[3], count]);
+        };
+        let location = |bytes: &Vec<u8>, idx: usize| bytes[idx * 4..idx * 4 + 4].to_vec();
+        // the second chunk starts inside the first one
+        let first = location(&bytes, existing[0]);
+        let first_offset = u32::from_be_bytes([0, first[0], first[1], first[2]]);
+        set_location(&mut bytes, existing[1], first_offset, 1);
+        set_location(&mut bytes, existing[2], 1, 1);
+        set_location(&mut bytes, existing[3], 0xFF_FFFF, 1);
+        set_location(&mut bytes, existing[4], 2, 0);
+        let mut corrupted = tempfile::NamedTempFile::new().unwrap();
+        corrupted.write_all(&bytes).unwrap();
+        let report = MCAReader::validate_file(&corrupted.path().to_path_buf()).unwrap();
+
+        let status = |idx: usize| &report.chunks[idx];
+        let coords = |idx: usize| (idx % REGION_DIM, idx / REGION_DIM);
+        let (x, z) = coords(existing[0]);
+        assert_eq!(status(existing[1]), &ChunkStatus::Overlapping { x, z });
+        let (x, z) = coords(existing[1]);
+        assert_eq!(status(existing[0]), &ChunkStatus::Overlapping { x, z });
+        assert_eq!(status(existing[2]), &ChunkStatus::SectorHeaderOverlap);
+        assert_eq!(status(existing[3]), &ChunkStatus::OutOfFile);
+        assert_eq!(status(existing[4]), &ChunkStatus::InvalidSectorSize);
+        assert_eq!(report.flagged().count(), 5);
+    }
+
+    #[test]
+    fn test_get_chunk_value() {
+        let path =
+            PathBuf::from("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca");
+        let reader = MCAReader::from_file(&path, true).expect("Failed to read MCA file");
+        let mut values = 0;
+        for (_, x, z) in create_chunk_ixz_iter() {
+            let value = reader.get_chunk_value(x, z);
+            match reader.get_chunk(x, z).unwrap() {
+                None => assert!(matches!(value, Ok(None))),
+                Some(ChunkWithTimestamp {
+                    nbt: ChunkNbt::Large,
+                    ..
+                }) => assert!(matches!(value, Err(MCAError::LargeChunk { .. }))),
+                Some(ChunkWithTimestamp {
+                    nbt: ChunkNbt::Small(nbt),
+                    ..
+                }) => {
+                    assert_eq!(value.unwrap(), Some(crate::util::nbt_serde::de(nbt)));
+                    values += 1;
+                }
+            }
+        }
+        assert!(values > 0);
+    }
+
+    #[test]
+    fn test_get_chunk_shared() {
+        use std::sync::Arc;
+
+        let path =
+            PathBuf::from("./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca");
+        let expected = MCAReader::from_file(&path, false).expect("Failed to read MCA file");
+        let reader = Arc::new(MCAReader::from_file(&path, true).expect("Failed to read MCA file"));
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let reader = Arc::clone(&reader);
+                std::thread::spawn(move || {
+                    create_chunk_ixz_iter()
+                        .filter(|(i, _, _)| i % 8 == t)
+                        .map(|(_, x, z)| (x, z, reader.get_chunk(x, z).unwrap().cloned()))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for handle in handles {
+            for (x, z, chunk) in handle.join().unwrap() {
+                let expected = match expected.get_chunk_lazily(x, z) {
+                    LazyChunk::Some(chunk) => Some(chunk.clone()),
+                    LazyChunk::NotExists => None,
+                    LazyChunk::Unloaded => panic!("Chunk ({x}, {z}) should be loaded"),
+                };
+                assert_eq!(chunk, expected, "chunk ({x}, {z})");
+                assert!(
+                    !matches!(reader.get_chunk_lazily(x, z), LazyChunk::Unloaded)
+                        || chunk.is_none()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_crcs() {
+        with_test_config(TEST_CONFIG.clone(), || {
+            let mca = std::fs::read(
+                "./resources/test-payload/region/mca/hairlessvillager-0/20250516.mca",
+            )
+            .unwrap();
+            let reader = MCAReader::from_bytes(&mca).expect("Failed to create MCA reader");
+            let mut builder = crate::mca::MCABuilder::new();
+            builder.set_crc_footer(true);
+            for (_, x, z) in create_chunk_ixz_iter() {
+                if let LazyChunk::Some(chunk) = reader.get_chunk_lazily(x, z) {
+                    builder.set_chunk(x, z, chunk);
+                }
+            }
+            let mut built = builder
+                .to_bytes(CompressionType::Zlib)
+                .expect("Failed to build MCA bytes");
+
+            let mut reader = MCAReader::from_bytes(&built).expect("Failed to read built bytes");
+            assert_eq!(reader.verify_crcs().unwrap(), vec![]);
+
+            // flip a byte inside the data of the first present chunk
+            let header = reader.header.iter().find(|e| e.sector_offset != 0).unwrap();
+            let (idx, offset) = (header.idx, header.sector_offset as usize * SECTOR_SIZE);
+            built[offset + 10] ^= 0xff;
+            let mut reader = MCAReader::from_reader(Cursor::new(&built[..]), true).unwrap();
+            assert_eq!(reader.verify_crcs().unwrap(), vec![(idx % 32, idx / 32)]);
+        });
+    }
 }
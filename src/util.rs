@@ -1,50 +1,419 @@
+/// Number of chunks along each side of a region.
+pub const REGION_DIM: usize = 32;
+/// Number of chunks in a region.
+pub const CHUNKS_PER_REGION: usize = REGION_DIM * REGION_DIM;
+
 pub type IXZ = (usize, usize, usize);
 pub fn create_chunk_ixz_iter() -> impl Iterator<Item = IXZ> {
-    (0..32).flat_map(|z| {
-        (0..32).map(move |x| {
-            let i = x + 32 * z;
+    (0..REGION_DIM).flat_map(|z| {
+        (0..REGION_DIM).map(move |x| {
+            let i = x + REGION_DIM * z;
             (i, x, z)
         })
     })
 }
 
 pub mod nbt_serde {
+    use fastnbt::Value;
+
     pub fn ser(v: &fastnbt::Value) -> Vec<u8> {
         fastnbt::to_bytes(v).expect("Failed to serialize NBT data")
     }
     pub fn de(input: &[u8]) -> fastnbt::Value {
-        fastnbt::from_bytes(input).expect("Failed to deserialize NBT data")
+        try_de(input).expect("Failed to deserialize NBT data")
+    }
+    pub fn try_de(input: &[u8]) -> Result<fastnbt::Value, fastnbt::error::Error> {
+        fastnbt::from_bytes(input)
+    }
+    /// Format `v` as indented SNBT. Arrays are kept on one line.
+    pub fn to_snbt(v: &Value) -> String {
+        fn quote(s: &str) -> String {
+            format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+        fn key(s: &str) -> String {
+            let bare = !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-.+".contains(c));
+            if bare { s.to_string() } else { quote(s) }
+        }
+        fn array<T: ToString>(
+            prefix: &str,
+            suffix: &str,
+            items: impl Iterator<Item = T>,
+        ) -> String {
+            let items: Vec<String> = items.map(|i| i.to_string() + suffix).collect();
+            format!("[{}; {}]", prefix, items.join(", "))
+        }
+        fn write(v: &Value, indent: usize, out: &mut String) {
+            let pad = "  ".repeat(indent + 1);
+            match v {
+                Value::Byte(x) => out.push_str(&format!("{}b", x)),
+                Value::Short(x) => out.push_str(&format!("{}s", x)),
+                Value::Int(x) => out.push_str(&x.to_string()),
+                Value::Long(x) => out.push_str(&format!("{}L", x)),
+                Value::Float(x) => out.push_str(&format!("{:?}f", x)),
+                Value::Double(x) => out.push_str(&format!("{:?}d", x)),
+                Value::String(x) => out.push_str(&quote(x)),
+                Value::ByteArray(x) => out.push_str(&array("B", "b", x.iter())),
+                Value::IntArray(x) => out.push_str(&array("I", "", x.iter())),
+                Value::LongArray(x) => out.push_str(&array("L", "L", x.iter())),
+                Value::List(items) if items.is_empty() => out.push_str("[]"),
+                Value::List(items) => {
+                    out.push_str("[\n");
+                    for (i, item) in items.iter().enumerate() {
+                        out.push_str(&pad);
+                        write(item, indent + 1, out);
+                        out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+                    }
+                    out.push_str(&"  ".repeat(indent));
+                    out.push(']');
+                }
+                Value::Compound(map) if map.is_empty() => out.push_str("{}"),
+                Value::Compound(map) => {
+                    out.push_str("{\n");
+                    for (i, (k, item)) in map.iter().enumerate() {
+                        out.push_str(&pad);
+                        out.push_str(&key(k));
+                        out.push_str(": ");
+                        write(item, indent + 1, out);
+                        out.push_str(if i + 1 < map.len() { ",\n" } else { "\n" });
+                    }
+                    out.push_str(&"  ".repeat(indent));
+                    out.push('}');
+                }
+            }
+        }
+        let mut out = String::new();
+        write(v, 0, &mut out);
+        out
+    }
+    /// Overwrite the tags at dotted `paths` (e.g. `Level.InhabitedTime`) in `to` with their
+    /// values in `from`, removing them from `to` if `from` lacks them. Paths through
+    /// non-compound tags are skipped.
+    pub fn copy_tags(from: &Value, to: &mut Value, paths: &[String]) {
+        fn get<'a>(value: &'a Value, keys: &[&str]) -> Option<&'a Value> {
+            keys.iter().try_fold(value, |value, key| match value {
+                Value::Compound(map) => map.get(*key),
+                _ => None,
+            })
+        }
+        for path in paths {
+            let keys: Vec<&str> = path.split('.').collect();
+            let (last, parents) = keys.split_last().expect("split yields at least one key");
+            let Some(Value::Compound(to_parent)) =
+                parents.iter().try_fold(&mut *to, |value, key| match value {
+                    Value::Compound(map) => map.get_mut(*key),
+                    _ => None,
+                })
+            else {
+                continue;
+            };
+            match get(from, &keys) {
+                Some(value) => to_parent.insert(last.to_string(), value.clone()),
+                None => to_parent.remove(*last),
+            };
+        }
+    }
+    /// Serialize `v` in a canonical form, so that NBT values that only differ in how they were
+    /// written serialize to the same bytes and can be hashed or deduplicated by them:
+    ///
+    /// - compound tags are written sorted by key, as compounds are `BTreeMap`s;
+    /// - `-0.0` floats and doubles are written as `0.0`, and every NaN as the same quiet NaN;
+    /// - everything else, including the order of list items and the type of each number, is
+    ///   kept, since Minecraft reads them differently.
+    pub fn canonical_nbt(v: &Value) -> Vec<u8> {
+        fn canonicalize(v: &Value) -> Value {
+            match v {
+                Value::Float(x) if x.is_nan() => Value::Float(f32::NAN),
+                Value::Float(x) if *x == 0.0 => Value::Float(0.0),
+                Value::Double(x) if x.is_nan() => Value::Double(f64::NAN),
+                Value::Double(x) if *x == 0.0 => Value::Double(0.0),
+                Value::List(items) => Value::List(items.iter().map(canonicalize).collect()),
+                Value::Compound(map) => Value::Compound(
+                    map.iter()
+                        .map(|(k, item)| (k.clone(), canonicalize(item)))
+                        .collect(),
+                ),
+                v => v.clone(),
+            }
+        }
+        ser(&canonicalize(v))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use fastnbt::nbt;
+
+        use super::*;
+
+        #[test]
+        fn test_canonical_nbt() {
+            let a = nbt!({
+                "Pos": [0.5_f64, -0.0_f64, 1.5_f64],
+                "Motion": [f64::NAN, 0.0_f64, 0.0_f64],
+                "Rotation": [-0.0_f32, 90.0_f32],
+                "id": "minecraft:cow",
+            });
+            let b = nbt!({
+                "id": "minecraft:cow",
+                "Rotation": [0.0_f32, 90.0_f32],
+                "Motion": [-f64::NAN, -0.0_f64, 0.0_f64],
+                "Pos": [0.5_f64, 0.0_f64, 1.5_f64],
+            });
+            assert_ne!(ser(&a), ser(&b));
+            assert_eq!(canonical_nbt(&a), canonical_nbt(&b));
+
+            let moved = nbt!({
+                "id": "minecraft:cow",
+                "Rotation": [0.0_f32, 90.0_f32],
+                "Motion": [-f64::NAN, -0.0_f64, 0.0_f64],
+                "Pos": [1.5_f64, 0.0_f64, 0.5_f64],
+            });
+            assert_ne!(canonical_nbt(&a), canonical_nbt(&moved));
+        }
     }
 }
 
 pub mod serde {
     use bincode::{
         Decode, Encode,
-        config::{BigEndian, Configuration},
-        decode_from_slice, encode_to_vec,
+        config::{BigEndian, Configuration, Limit, Varint},
+        decode_from_slice, encode_into_std_write, encode_to_vec,
+        error::DecodeError,
     };
+    use std::io::Write;
+
+    use crate::diff::file::DECODE_FLOOR;
+
+    /// Decoded containers may take more memory than their encoding (wider integers, struct
+    /// layout), so a decode may claim up to this many times the input size.
+    pub const DECODE_FACTOR: usize = 8;
+
+    /// Most bytes a decode of `len` input bytes may claim for its containers: `len` times
+    /// [`DECODE_FACTOR`], but at least what a region diff claims for its chunk diffs, rounded up
+    /// to the next limit tier. A crafted length prefix fails with `DecodeError::LimitExceeded`
+    /// instead of allocating up front.
+    pub fn decode_limit(len: usize) -> usize {
+        let wanted = len.saturating_mul(DECODE_FACTOR).max(DECODE_FLOOR);
+        LIMIT_TIERS
+            .iter()
+            .copied()
+            .find(|&tier| tier >= wanted)
+            .unwrap_or(LIMIT_TIERS[LIMIT_TIERS.len() - 1])
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    const LIMIT_TIERS: [usize; 8] = [
+        1 << 12,
+        1 << 16,
+        1 << 20,
+        1 << 24,
+        1 << 28,
+        1 << 31,
+        1 << 34,
+        1 << 37,
+    ];
+    #[cfg(not(target_pointer_width = "64"))]
+    const LIMIT_TIERS: [usize; 6] = [1 << 12, 1 << 16, 1 << 20, 1 << 24, 1 << 28, 1 << 31];
+
+    const fn config<const N: usize>() -> Configuration<BigEndian, Varint, Limit<N>> {
+        bincode::config::standard()
+            .with_big_endian()
+            .with_variable_int_encoding()
+            .with_limit::<N>()
+    }
 
-    static CONFIG: Configuration<BigEndian> = bincode::config::standard()
+    static CONFIG: Configuration<BigEndian, Varint> = bincode::config::standard()
         .with_big_endian()
         .with_variable_int_encoding();
 
     pub fn ser<T: Encode>(val: T) -> Vec<u8> {
         encode_to_vec(val, CONFIG.clone()).expect("Failed to serialize object to bytes")
     }
+    pub fn ser_into<T: Encode>(val: T, writer: &mut impl Write) {
+        encode_into_std_write(val, writer, CONFIG.clone())
+            .expect("Failed to serialize object to writer");
+    }
+    pub fn try_de<T: Decode<()>>(data: &[u8]) -> Result<T, DecodeError> {
+        let decoded = match decode_limit(data.len()) {
+            0x1000 => decode_from_slice(data, config::<0x1000>()),
+            0x1_0000 => decode_from_slice(data, config::<0x1_0000>()),
+            0x10_0000 => decode_from_slice(data, config::<0x10_0000>()),
+            0x100_0000 => decode_from_slice(data, config::<0x100_0000>()),
+            0x1000_0000 => decode_from_slice(data, config::<0x1000_0000>()),
+            0x8000_0000 => decode_from_slice(data, config::<0x8000_0000>()),
+            #[cfg(target_pointer_width = "64")]
+            0x4_0000_0000 => decode_from_slice(data, config::<0x4_0000_0000>()),
+            #[cfg(target_pointer_width = "64")]
+            0x20_0000_0000 => decode_from_slice(data, config::<0x20_0000_0000>()),
+            limit => unreachable!("no decode config for limit {limit}"),
+        };
+        decoded.map(|(de, _)| de)
+    }
     pub fn de<T: Decode<()>>(data: &Vec<u8>) -> T {
-        decode_from_slice(data, CONFIG.clone())
-            .map(|(de, _)| de)
-            .expect("Failed to deserialize object from bytes")
+        try_de(data).expect("Failed to deserialize object from bytes")
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_decode_limit() {
+            // a length prefix claiming far more elements than the limit, and no elements
+            let huge = ser(u64::MAX >> 8);
+            assert!(matches!(
+                try_de::<Vec<u8>>(&huge),
+                Err(DecodeError::LimitExceeded)
+            ));
+            assert!(matches!(
+                try_de::<Vec<u64>>(&huge),
+                Err(DecodeError::LimitExceeded)
+            ));
+            assert!(matches!(
+                try_de::<String>(&huge),
+                Err(DecodeError::LimitExceeded)
+            ));
+
+            // the limit follows the input size: 16 MiB of zeros cannot come from a few bytes
+            let claims_mib = ser(1usize << 24);
+            assert!(claims_mib.len() < 8);
+            assert!(matches!(
+                try_de::<Vec<u8>>(&claims_mib),
+                Err(DecodeError::LimitExceeded)
+            ));
+
+            // small inputs still get room for the chunk diffs of a whole region
+            assert!(decode_limit(0) >= DECODE_FLOOR);
+            assert_eq!(decode_limit(0), decode_limit(DECODE_FLOOR / DECODE_FACTOR));
+            assert_eq!(decode_limit(1 << 20), 1 << 24);
+            assert_eq!(decode_limit(1 << 28), 1 << 31);
+            assert_eq!(decode_limit(usize::MAX), LIMIT_TIERS[LIMIT_TIERS.len() - 1]);
+
+            let data = vec![vec![0u8; 1024]; 16];
+            assert_eq!(try_de::<Vec<Vec<u8>>>(&ser(&data)).unwrap(), data);
+            let data = vec![u64::MAX; 1 << 12];
+            assert_eq!(try_de::<Vec<u64>>(&ser(&data)).unwrap(), data);
+        }
+    }
+}
+
+pub mod reference {
+    /// Blake2s hash identifying a file state that a diff starts or ends at.
+    pub type Ref = [u8; 32];
+
+    pub fn hash_ref(data: &[u8]) -> Ref {
+        *blake2s_simd::blake2s(data).as_array()
     }
 }
 
 pub mod parallel {
     use std::{
         fmt::Debug,
+        sync::atomic::{AtomicBool, Ordering},
         time::{Duration, Instant},
     };
 
-    use rayon::{ThreadPoolBuilder, prelude::*};
+    use rayon::{ThreadPool, ThreadPoolBuilder, prelude::*};
+    use thiserror::Error;
+
+    /// Logs the task a worker was processing if the worker unwinds while processing it, since the
+    /// rayon backtrace alone doesn't tell which chunk failed.
+    struct PanicGuard<'a, I: Debug>(&'a I);
+    impl<I: Debug> Drop for PanicGuard<'_, I> {
+        fn drop(&mut self) {
+            if std::thread::panicking() {
+                log::error!(
+                    "task {:?} panicked on {}",
+                    self.0,
+                    std::thread::current().name().unwrap_or("unnamed thread")
+                );
+            }
+        }
+    }
+
+    fn build_pool() -> ThreadPool {
+        let config = crate::config::get_config();
+        ThreadPoolBuilder::new()
+            .num_threads(config.threads)
+            .stack_size(config.thread_stack_size)
+            .thread_name(|n| format!("region-diff-worker-{}", n))
+            .build()
+            .expect("Failed to build thread pool")
+    }
+
+    /// With a single thread, tasks run in order on one worker thread without a rayon pool, so
+    /// the work is done in the same order on every run.
+    fn is_serial() -> bool {
+        crate::config::get_config().threads == 1
+    }
+
+    /// Tasks in a random order that only depends on `seed`, as an alternative way to balance
+    /// the load among workers.
+    fn shuffled<I>(tasks: impl Iterator<Item = I>, seed: u64) -> Vec<I> {
+        use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+
+        let mut tasks = tasks.collect::<Vec<_>>();
+        tasks.shuffle(&mut StdRng::seed_from_u64(seed));
+        tasks
+    }
+
+    fn process_task<I, O, F>(input: I, process_func: &F) -> (I, O, Option<Duration>)
+    where
+        I: Debug,
+        F: Fn(&I) -> O,
+    {
+        log::trace!("process task: {:?}...", &input);
+        let start = Instant::now();
+        let output = {
+            let _guard = PanicGuard(&input);
+            process_func(&input)
+        };
+        let duration = start.elapsed();
+        log::trace!("process task: {:?}...done", &input);
+        (input, output, Some(duration))
+    }
+
+    /// Returned by the cancellable helpers when their flag is set before all tasks ran.
+    #[derive(Debug, Error)]
+    #[error("operation cancelled")]
+    pub struct Cancelled;
+
+    /// Runs `tasks` in order, or on a pool when there is more than one thread. Once `cancel` is
+    /// set, no further task is started and `Cancelled` is returned after the running ones finish.
+    fn run_tasks<I, O, F>(
+        tasks: Vec<I>,
+        process_func: F,
+        cancel: &AtomicBool,
+    ) -> Result<Vec<(I, O, Option<Duration>)>, Cancelled>
+    where
+        I: Send + Debug,
+        O: Send,
+        F: Fn(&I) -> O + Sync + Send,
+    {
+        let process = |input| {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Cancelled);
+            }
+            Ok(process_task(input, &process_func))
+        };
+        if is_serial() {
+            // on a thread of its own, which gets the configured stack size like pool workers
+            let stack_size = crate::config::get_config().thread_stack_size;
+            return std::thread::scope(|scope| {
+                std::thread::Builder::new()
+                    .name("region-diff-worker-0".to_string())
+                    .stack_size(stack_size)
+                    .spawn_scoped(scope, || tasks.into_iter().map(process).collect())
+                    .expect("Failed to spawn worker thread")
+                    .join()
+                    .unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+            });
+        }
+        let pool = build_pool();
+
+        pool.install(|| tasks.into_iter().par_bridge().map(process).collect())
+    }
 
     pub fn parallel_process<I, O, G, F>(
         task_generator: G,
@@ -56,24 +425,27 @@ pub mod parallel {
         G: Iterator<Item = I> + ParallelBridge + Send,
         F: Fn(&I) -> O + Sync + Send,
     {
-        let pool = ThreadPoolBuilder::new()
-            .num_threads(crate::config::get_config().threads)
-            .build()
-            .expect("Failed to build thread pool");
-
-        pool.install(|| {
-            task_generator
-                .par_bridge()
-                .map(|input| {
-                    log::trace!("process task: {:?}...", &input);
-                    let start = Instant::now();
-                    let output = process_func(&input);
-                    let duration = start.elapsed();
-                    log::trace!("process task: {:?}...done", &input);
-                    (input, output, Some(duration))
-                })
-                .collect()
-        })
+        parallel_process_cancellable(task_generator, process_func, &AtomicBool::new(false))
+            .unwrap_or_else(|_| unreachable!())
+    }
+    /// Same as `parallel_process`, but stops early with `Cancelled` once `cancel` is set, e.g.
+    /// from another thread holding an `Arc` of the flag.
+    pub fn parallel_process_cancellable<I, O, G, F>(
+        task_generator: G,
+        process_func: F,
+        cancel: &AtomicBool,
+    ) -> Result<Vec<(I, O, Option<Duration>)>, Cancelled>
+    where
+        I: Send + Debug,
+        O: Send,
+        G: Iterator<Item = I> + ParallelBridge + Send,
+        F: Fn(&I) -> O + Sync + Send,
+    {
+        let tasks: Vec<_> = match crate::config::get_config().shuffle_seed {
+            Some(seed) => shuffled(task_generator, seed),
+            None => task_generator.collect(),
+        };
+        run_tasks(tasks, process_func, cancel)
     }
     pub fn parallel_process_with_cost_estimator<I, O, G, F, E>(
         task_generator: G,
@@ -87,31 +459,150 @@ pub mod parallel {
         F: Fn(&I) -> O + Sync + Send,
         E: Fn(&I) -> usize + Sync + Send,
     {
-        let pool = ThreadPoolBuilder::new()
-            .num_threads(crate::config::get_config().threads)
-            .build()
-            .expect("Failed to build thread pool");
-
-        log::trace!("sorting tasks for load balance...");
-        let mut tasks = task_generator.collect::<Vec<_>>();
-        tasks.sort_by_cached_key(|ixz| std::cmp::Reverse(cost_estimator(ixz)));
-        log::trace!("sorting tasks for load balance...done");
-        log::trace!("first 10 items: {:?}", &tasks[..10]);
-
-        pool.install(|| {
-            tasks
-                .into_iter()
-                .par_bridge()
-                .map(|input| {
-                    log::trace!("process task: {:?}...", &input);
-                    let start = Instant::now();
-                    let output = process_func(&input);
-                    let duration = start.elapsed();
-                    log::trace!("process task: {:?}...done", &input);
-                    (input, output, Some(duration))
-                })
-                .collect()
-        })
+        parallel_process_with_cost_estimator_cancellable(
+            task_generator,
+            process_func,
+            cost_estimator,
+            &AtomicBool::new(false),
+        )
+        .unwrap_or_else(|_| unreachable!())
+    }
+    /// Same as `parallel_process_with_cost_estimator`, but stops early with `Cancelled` once
+    /// `cancel` is set.
+    pub fn parallel_process_with_cost_estimator_cancellable<I, O, G, F, E>(
+        task_generator: G,
+        process_func: F,
+        cost_estimator: E,
+        cancel: &AtomicBool,
+    ) -> Result<Vec<(I, O, Option<Duration>)>, Cancelled>
+    where
+        I: Send + Debug,
+        O: Send,
+        G: Iterator<Item = I> + ParallelBridge + Send,
+        F: Fn(&I) -> O + Sync + Send,
+        E: Fn(&I) -> usize + Sync + Send,
+    {
+        let tasks = match crate::config::get_config().shuffle_seed {
+            Some(seed) => shuffled(task_generator, seed),
+            None if is_serial() => task_generator.collect(),
+            None => {
+                log::trace!("sorting tasks for load balance...");
+                let mut tasks = task_generator.collect::<Vec<_>>();
+                tasks.sort_by_cached_key(|ixz| std::cmp::Reverse(cost_estimator(ixz)));
+                log::trace!("sorting tasks for load balance...done");
+                log::trace!("first 10 items: {:?}", &tasks[..10]);
+                tasks
+            }
+        };
+        run_tasks(tasks, process_func, cancel)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::{
+            panic::{AssertUnwindSafe, catch_unwind},
+            sync::{Arc, Mutex, Once, atomic::AtomicUsize},
+        };
+
+        use super::*;
+        use crate::config::{Config, with_test_config};
+
+        fn test_config() -> Config {
+            Config {
+                threads: 4,
+                ..Config::for_test()
+            }
+        }
+
+        /// Keeps the error messages logged by any test, so tests can check what they logged.
+        struct CapturingLogger(Mutex<Vec<String>>);
+        impl log::Log for CapturingLogger {
+            fn enabled(&self, metadata: &log::Metadata) -> bool {
+                metadata.level() <= log::Level::Error
+            }
+            fn log(&self, record: &log::Record) {
+                if self.enabled(record.metadata()) {
+                    self.0.lock().unwrap().push(record.args().to_string());
+                }
+            }
+            fn flush(&self) {}
+        }
+
+        static LOGGER: CapturingLogger = CapturingLogger(Mutex::new(Vec::new()));
+
+        #[test]
+        fn test_panicking_task_is_logged() {
+            static INSTALL_LOGGER: Once = Once::new();
+            INSTALL_LOGGER.call_once(|| {
+                log::set_logger(&LOGGER).unwrap();
+                log::set_max_level(log::LevelFilter::Error);
+            });
+            with_test_config(test_config(), || {
+                let result = catch_unwind(AssertUnwindSafe(|| {
+                    parallel_process(0..16usize, |i| {
+                        if *i == 11 {
+                            panic!("deliberate panic");
+                        }
+                        *i
+                    })
+                }));
+                assert!(result.is_err());
+            });
+            let logs = LOGGER.0.lock().unwrap();
+            assert!(
+                logs.iter()
+                    .any(|l| l.starts_with("task 11 panicked on region-diff-worker-"))
+            );
+        }
+
+        #[test]
+        fn test_cancel() {
+            for threads in [1, 4] {
+                let config = Config {
+                    threads,
+                    ..test_config()
+                };
+                with_test_config(config, || {
+                    let cancel = Arc::new(AtomicBool::new(false));
+                    let processed = AtomicUsize::new(0);
+                    let result = parallel_process_cancellable(
+                        0..1024usize,
+                        |_| {
+                            if processed.fetch_add(1, Ordering::Relaxed) == 10 {
+                                cancel.store(true, Ordering::Relaxed);
+                            }
+                        },
+                        &cancel,
+                    );
+                    assert!(result.is_err());
+                    assert!(processed.load(Ordering::Relaxed) < 1024);
+                });
+            }
+        }
+
+        #[test]
+        fn test_thread_stack_size() {
+            // about 1 KiB of stack per level, well past the 2 MiB rayon workers get by default
+            fn recurse(depth: usize) -> u8 {
+                let frame = std::hint::black_box([depth as u8; 1024]);
+                match depth {
+                    0 => frame[0],
+                    _ => recurse(depth - 1).wrapping_add(frame[1023]),
+                }
+            }
+            // serial runs get the configured stack too
+            for threads in [4, 1] {
+                let config = Config {
+                    threads,
+                    thread_stack_size: 64 << 20,
+                    ..test_config()
+                };
+                with_test_config(config, || {
+                    let results = parallel_process(0..4usize, |_| recurse(8 * 1024));
+                    assert_eq!(results.len(), 4);
+                });
+            }
+        }
     }
 }
 pub mod test {
@@ -119,7 +610,7 @@ pub mod test {
 
     use rand::prelude::*;
 
-    use super::create_chunk_ixz_iter;
+    use super::{CHUNKS_PER_REGION, create_chunk_ixz_iter};
     use crate::compress::CompressionType;
     use crate::{
         FileType,
@@ -133,6 +624,7 @@ pub mod test {
             FileType::RegionMca => "region/mca",
             FileType::RegionMcc => "region/mcc",
             FileType::EntitiesMca => "entities/mca",
+            FileType::PoiMca => "poi/mca",
         }));
         path
     }
@@ -173,8 +665,8 @@ pub mod test {
         })
     }
     pub fn assert_mca_eq(a: &[u8], b: &[u8]) {
-        let mut reader_a = MCAReader::from_bytes(a).unwrap();
-        let mut reader_b = MCAReader::from_bytes(b).unwrap();
+        let reader_a = MCAReader::from_bytes(a).unwrap();
+        let reader_b = MCAReader::from_bytes(b).unwrap();
         for (_, x, z) in create_chunk_ixz_iter() {
             let chunk_a = reader_a.get_chunk(x, z).unwrap();
             let chunk_b = reader_b.get_chunk(x, z).unwrap();
@@ -202,8 +694,8 @@ pub mod test {
         assert_eq!(nbt_a, nbt_b);
     }
     pub fn get_test_chunk(path: &PathBuf, rng: &mut StdRng) -> impl Iterator<Item = Vec<u8>> {
-        let mut reader = MCAReader::from_file(path, false).unwrap();
-        let mut xzs = [(0, 0); 1024];
+        let reader = MCAReader::from_file(path, false).unwrap();
+        let mut xzs = [(0, 0); CHUNKS_PER_REGION];
         for (i, x, z) in create_chunk_ixz_iter() {
             xzs[i] = (x, z);
         }
@@ -227,7 +719,23 @@ pub mod test {
         })
     }
     pub fn get_test_chunk_by_xz(path: &PathBuf, x: usize, z: usize) -> Option<ChunkWithTimestamp> {
-        let mut reader = MCAReader::from_file(path, false).unwrap();
+        let reader = MCAReader::from_file(path, false).unwrap();
         reader.get_chunk(x, z).unwrap().cloned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_chunk_ixz_iter() {
+        let ixzs: Vec<IXZ> = create_chunk_ixz_iter().collect();
+        assert_eq!(ixzs.len(), CHUNKS_PER_REGION);
+        for (n, (i, x, z)) in ixzs.into_iter().enumerate() {
+            assert_eq!(i, n);
+            assert_eq!(x, i % REGION_DIM);
+            assert_eq!(z, i / REGION_DIM);
+        }
+    }
+}